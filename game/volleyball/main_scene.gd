@@ -3,9 +3,12 @@ extends Node3D
 var players_fixed_axis
 var ball_fixed_axis
 var socket: PacketPeerUDP
-var msg = [58, 41, 58, 80, 58, 68, 11, 13, 0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0]
-var player_id = PackedByteArray()
-var board_id = PackedByteArray()
+# the player id comes over TCP, the server takes no game request without one
+var tcp_stream := StreamPeerTCP.new()
+var id_requested := false
+const Protocol = preload("res://protocol.gd")
+var player_id := 0
+var board_id := 0
 var ping_time = 0
 var game_time = 0
 
@@ -21,8 +24,8 @@ func _ready() -> void:
 	socket.bind(12001)
 	#socket.set_dest_address("127.0.0.1", 12542)
 	socket.set_dest_address("20.215.201.30", 12542)
-	socket.put_packet(msg)
-	print("packet sent")
+	#tcp_stream.connect_to_host("127.0.0.1", 12541)
+	tcp_stream.connect_to_host("20.215.201.30", 12541)
 	
 	players_fixed_axis = $player1.position[0]
 	ball_fixed_axis = $ball.position[0]
@@ -33,12 +36,11 @@ func _process(delta: float) -> void:
 	#print("delta ", delta, ' ', 1/delta)
 	ping_time += delta
 	game_time += delta
+	_poll_tcp()
 	#print("ping time", ping_time)
-	if ping_time >= PING_FREQ:
+	if player_id != 0 and ping_time >= PING_FREQ:
 		ping_time = 0
-		msg[6] = 96
-		msg[7] = 22
-		socket.put_packet(msg)
+		socket.put_packet(Protocol.encode_ping(player_id, board_id, 0))
 		#print("ping sent")
 	
 	var t = int(game_time)
@@ -53,29 +55,26 @@ func _process(delta: float) -> void:
 	while socket.get_available_packet_count() > 0:
 		#print(socket.get_available_packet_count())
 		var packet = socket.get_packet()
-		if player_id.is_empty() && board_id.is_empty():
+		if Protocol.is_ids(packet):
 			print("first packet", packet)
-			player_id = packet.slice(4, 12)
-			board_id = packet.slice(12, 20)
+			var ids = Protocol.decode_ids(packet)
+			player_id = ids["player_id"]
+			board_id = ids["board_id"]
 			print(player_id)
 			print(board_id)
-			for i in range(0, 8):
-				msg[i + 8] = player_id[i]
-				msg[i + 16] = board_id[i]
-		else:
+		elif Protocol.is_state(packet):
 			$wait_label.visible = false
 			#print(packet)
-			var ball_r = packet.decode_float(0)
-			var ball_x = packet.decode_float(4)
-			var ball_y = packet.decode_float(8)
-			var player_r = packet.decode_float(12)
-			var p1_x = packet.decode_float(16)
-			var p1_y = packet.decode_float(20)
-			var p2_x = packet.decode_float(24)
-			var p2_y = packet.decode_float(28)
-			var score1 = packet.decode_u32(32)
-			var score2 = packet.decode_u32(36)
-			var game_over = true if packet[40] == 1 else false
+			var state = Protocol.decode_state(packet)
+			var ball_x = state["ball_x"]
+			var ball_y = state["ball_y"]
+			var p1_x = state["player1_x"]
+			var p1_y = state["player1_y"]
+			var p2_x = state["player2_x"]
+			var p2_y = state["player2_y"]
+			var score1 = state["score1"]
+			var score2 = state["score2"]
+			var game_over = state["game_over"] == 1
 			#print(score1, " ", score2, " ", game_over)
 			$score1.text = str(score1)
 			$score2.text = str(score2)
@@ -93,34 +92,16 @@ func _process(delta: float) -> void:
 				$game_over.text = "Blue won!"
 				$game_over.visible = true
 	
-	#[11, 13] => Ok(MsgIn::GameRequest),
-	#[17, 23] => Ok(MsgIn::Input(player_id, board_id, Key::Left(true))),
-	#[25, 99] => Ok(MsgIn::Input(player_id, board_id, Key::Left(false))),
-	#[37, 31] => Ok(MsgIn::Input(player_id, board_id, Key::Right(true))),
-	#[67, 58] => Ok(MsgIn::Input(player_id, board_id, Key::Right(false))),
-	#[97, 33] => Ok(MsgIn::Input(player_id, board_id, Key::Jump)),
-	#[96, 22] => Ok(MsgIn::Ping(player_id, board_id)),
-	
 	if Input.is_action_just_pressed("ui_left"):
-		msg[6] = 17
-		msg[7] = 23
-		socket.put_packet(msg)
+		socket.put_packet(Protocol.encode_left_pressed(player_id, board_id, 0, 0))
 	elif Input.is_action_just_released("ui_left"):
-		msg[6] = 25
-		msg[7] = 99
-		socket.put_packet(msg)
+		socket.put_packet(Protocol.encode_left_released(player_id, board_id, 0, 0))
 	elif Input.is_action_just_pressed("ui_right"):
-		msg[6] = 37
-		msg[7] = 31
-		socket.put_packet(msg)
+		socket.put_packet(Protocol.encode_right_pressed(player_id, board_id, 0, 0))
 	elif Input.is_action_just_released("ui_right"):
-		msg[6] = 67
-		msg[7] = 58
-		socket.put_packet(msg)
+		socket.put_packet(Protocol.encode_right_released(player_id, board_id, 0, 0))
 	elif Input.is_action_just_pressed("ui_up"):
-		msg[6] = 97
-		msg[7] = 33
-		socket.put_packet(msg)
+		socket.put_packet(Protocol.encode_jump(player_id, board_id, 0, 0))
 		#var force = randf_range(-1.0, 1.0)
 		#$Node3D/RigidBody3D.apply_impulse(Vector3(force, force, force))
 		
@@ -146,6 +127,20 @@ func _process(delta: float) -> void:
 		$camera4.current = true
 
 
+func _poll_tcp() -> void:
+	tcp_stream.poll()
+	if tcp_stream.get_status() != StreamPeerTCP.STATUS_CONNECTED:
+		return
+	if not id_requested:
+		id_requested = true
+		tcp_stream.put_data(Protocol.encode_player_id_request())
+	# the answer is the player id in 8 bytes, then the game request goes out
+	if player_id == 0 and tcp_stream.get_available_bytes() >= 8:
+		player_id = tcp_stream.get_data(8)[1].decode_s64(0)
+		socket.put_packet(Protocol.encode_game_request(player_id))
+		print("game request sent, player ", player_id)
+
+
 func _input(_event: InputEvent) -> void:
 	pass
 	#print("ket event: ", event)
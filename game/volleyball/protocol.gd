@@ -0,0 +1,309 @@
+# Generated from rust_volleyball/protocol.toml by `cargo run --bin protocol_gen`, change the schema and run it
+# again instead of editing this file. Loaded with preload("res://protocol.gd"), all functions are static.
+extends RefCounted
+
+const CLIENT_PACKET_LEN := 32
+const MAGIC := [58, 41, 58, 80, 58, 68]
+const PROTOCOL_VERSION := 68
+const OPCODE_AT := 6
+
+const PLAYER_ID_REQUEST := [13, 22]
+const MIGRATION_KEY_REQUEST := [13, 23]
+const GAME_REQUEST := [11, 13]
+const RESUME_REQUEST := [11, 14]
+const LEFT_PRESSED := [17, 23]
+const LEFT_RELEASED := [25, 99]
+const RIGHT_PRESSED := [37, 31]
+const RIGHT_RELEASED := [67, 58]
+const JUMP := [97, 33]
+const PING := [96, 22]
+const PONG := [96, 23]
+const SESSION := [41, 7]
+const MIGRATE := [52, 9]
+const RESYNC_REQUEST := [11, 16]
+
+const IDS_HEADER := [12, 64, 13, 56]
+const IDS_LEN := 32
+const SESSION_REPLY_HEADER := [12, 64, 13, 57]
+const SESSION_REPLY_LEN := 32
+const SERVER_PONG_HEADER := [12, 64, 13, 80]
+const SERVER_PONG_LEN := 32
+const SERVER_PING_HEADER := [12, 64, 13, 81]
+const SERVER_PING_LEN := 32
+const SHUTDOWN_NOTICE_HEADER := [12, 64, 13, 99]
+const SHUTDOWN_NOTICE_LEN := 32
+const STATE_LEN := 68
+const BOARD_HEADER := [12, 64, 13, 66]
+const BOARD_LEN := 152
+
+
+# over TCP, answered with the player id in 8 bytes
+static func encode_player_id_request() -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + PLAYER_ID_REQUEST)
+	packet.resize(CLIENT_PACKET_LEN)
+	return packet
+
+
+# over TCP, answered with the key for signing migrations in 8 bytes
+static func encode_migration_key_request() -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + MIGRATION_KEY_REQUEST)
+	packet.resize(CLIENT_PACKET_LEN)
+	return packet
+
+
+static func encode_game_request(player_id: int) -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + GAME_REQUEST)
+	packet.resize(CLIENT_PACKET_LEN)
+	packet.encode_u64(8, player_id)
+	return packet
+
+
+# a game request back to the board saved by the last shutdown
+static func encode_resume_request(player_id: int, resume_token: int) -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + RESUME_REQUEST)
+	packet.resize(CLIENT_PACKET_LEN)
+	packet.encode_u64(8, player_id)
+	packet.encode_u64(16, resume_token)
+	return packet
+
+
+static func encode_left_pressed(player_id: int, board_id: int, seq: int, frame: int) -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + LEFT_PRESSED)
+	packet.resize(CLIENT_PACKET_LEN)
+	packet.encode_u64(8, player_id)
+	packet.encode_u64(16, board_id)
+	packet.encode_u32(24, seq)
+	packet.encode_u32(28, frame)
+	return packet
+
+
+static func encode_left_released(player_id: int, board_id: int, seq: int, frame: int) -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + LEFT_RELEASED)
+	packet.resize(CLIENT_PACKET_LEN)
+	packet.encode_u64(8, player_id)
+	packet.encode_u64(16, board_id)
+	packet.encode_u32(24, seq)
+	packet.encode_u32(28, frame)
+	return packet
+
+
+static func encode_right_pressed(player_id: int, board_id: int, seq: int, frame: int) -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + RIGHT_PRESSED)
+	packet.resize(CLIENT_PACKET_LEN)
+	packet.encode_u64(8, player_id)
+	packet.encode_u64(16, board_id)
+	packet.encode_u32(24, seq)
+	packet.encode_u32(28, frame)
+	return packet
+
+
+static func encode_right_released(player_id: int, board_id: int, seq: int, frame: int) -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + RIGHT_RELEASED)
+	packet.resize(CLIENT_PACKET_LEN)
+	packet.encode_u64(8, player_id)
+	packet.encode_u64(16, board_id)
+	packet.encode_u32(24, seq)
+	packet.encode_u32(28, frame)
+	return packet
+
+
+static func encode_jump(player_id: int, board_id: int, seq: int, frame: int) -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + JUMP)
+	packet.resize(CLIENT_PACKET_LEN)
+	packet.encode_u64(8, player_id)
+	packet.encode_u64(16, board_id)
+	packet.encode_u32(24, seq)
+	packet.encode_u32(28, frame)
+	return packet
+
+
+# also the TCP keepalive, with all fields 0
+static func encode_ping(player_id: int, board_id: int, client_time: int) -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + PING)
+	packet.resize(CLIENT_PACKET_LEN)
+	packet.encode_u64(8, player_id)
+	packet.encode_u64(16, board_id)
+	packet.encode_u64(24, client_time)
+	return packet
+
+
+# the answer to a server ping
+static func encode_pong(player_id: int, server_time: int, client_time: int) -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + PONG)
+	packet.resize(CLIENT_PACKET_LEN)
+	packet.encode_u64(8, player_id)
+	packet.encode_u64(16, server_time)
+	packet.encode_u64(24, client_time)
+	return packet
+
+
+# a control message of a UDP-only session
+static func encode_session(token: int, seq: int, ack: int, request: int, cookie: int) -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + SESSION)
+	packet.resize(CLIENT_PACKET_LEN)
+	packet.encode_u64(8, token)
+	packet.encode_u32(16, seq)
+	packet.encode_u32(20, ack)
+	packet.encode_u8(24, request)
+	packet.encode_u32(28, cookie)
+	return packet
+
+
+# the player is at the sender's address now
+static func encode_migrate(player_id: int, counter: int, tag: int) -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + MIGRATE)
+	packet.resize(CLIENT_PACKET_LEN)
+	packet.encode_u64(8, player_id)
+	packet.encode_u64(16, counter)
+	packet.encode_u64(24, tag)
+	return packet
+
+
+# asks for the whole board after a checksum mismatch
+static func encode_resync_request(player_id: int, board_id: int) -> PackedByteArray:
+	var packet := PackedByteArray(MAGIC + RESYNC_REQUEST)
+	packet.resize(CLIENT_PACKET_LEN)
+	packet.encode_u64(8, player_id)
+	packet.encode_u64(16, board_id)
+	return packet
+
+
+# the answer to a game request
+static func is_ids(data: PackedByteArray) -> bool:
+	return data.size() == IDS_LEN and data.slice(0, 4) == PackedByteArray(IDS_HEADER)
+
+
+static func decode_ids(data: PackedByteArray) -> Dictionary:
+	return {
+		"player_id": data.decode_u64(4),
+		"board_id": data.decode_u64(12),
+	}
+
+
+static func is_session_reply(data: PackedByteArray) -> bool:
+	return data.size() == SESSION_REPLY_LEN and data.slice(0, 4) == PackedByteArray(SESSION_REPLY_HEADER)
+
+
+static func decode_session_reply(data: PackedByteArray) -> Dictionary:
+	return {
+		"seq": data.decode_u32(4),
+		"ack": data.decode_u32(8),
+		"reply": data.decode_u8(12),
+		"player_id": data.decode_u64(16),
+		"key": data.decode_u64(24),
+	}
+
+
+static func is_server_pong(data: PackedByteArray) -> bool:
+	return data.size() == SERVER_PONG_LEN and data.slice(0, 4) == PackedByteArray(SERVER_PONG_HEADER)
+
+
+static func decode_server_pong(data: PackedByteArray) -> Dictionary:
+	return {
+		"client_time": data.decode_u64(4),
+		"server_time": data.decode_u64(12),
+	}
+
+
+# to be echoed in a pong
+static func is_server_ping(data: PackedByteArray) -> bool:
+	return data.size() == SERVER_PING_LEN and data.slice(0, 4) == PackedByteArray(SERVER_PING_HEADER)
+
+
+static func decode_server_ping(data: PackedByteArray) -> Dictionary:
+	return {
+		"server_time": data.decode_u64(4),
+	}
+
+
+# over TCP when the server stops
+static func is_shutdown_notice(data: PackedByteArray) -> bool:
+	return data.size() == SHUTDOWN_NOTICE_LEN and data.slice(0, 4) == PackedByteArray(SHUTDOWN_NOTICE_HEADER)
+
+
+static func decode_shutdown_notice(data: PackedByteArray) -> Dictionary:
+	return {
+		"resume_token": data.decode_u64(4),
+	}
+
+
+# a snapshot of the board, sent to each player
+static func is_state(data: PackedByteArray) -> bool:
+	return data.size() == STATE_LEN
+
+
+static func decode_state(data: PackedByteArray) -> Dictionary:
+	return {
+		"ball_radius": data.decode_float(0),
+		"ball_x": data.decode_float(4),
+		"ball_y": data.decode_float(8),
+		"player_radius": data.decode_float(12),
+		"player1_x": data.decode_float(16),
+		"player1_y": data.decode_float(20),
+		"player2_x": data.decode_float(24),
+		"player2_y": data.decode_float(28),
+		"score1": data.decode_u32(32),
+		"score2": data.decode_u32(36),
+		"game_over": data.decode_u8(40),
+		"own_player": data.decode_u8(41),
+		"held": data.decode_u8(42),
+		"ball_gravity": data.decode_u8(43),
+		"ack_seq": data.decode_u32(44),
+		"ack_age": data.decode_u16(48),
+		"has_checksum": data.decode_u8(50),
+		"ball_vx": data.decode_s16(52) / 1000.0,
+		"ball_vy": data.decode_s16(54) / 1000.0,
+		"own_vx": data.decode_s16(56) / 1000.0,
+		"own_vy": data.decode_s16(58) / 1000.0,
+		"frame": data.decode_u32(60),
+		"checksum": data.decode_u32(64),
+	}
+
+
+# the answer to a resync request
+static func is_board(data: PackedByteArray) -> bool:
+	return data.size() == BOARD_LEN and data.slice(0, 4) == PackedByteArray(BOARD_HEADER)
+
+
+static func decode_board(data: PackedByteArray) -> Dictionary:
+	return {
+		"frame": data.decode_u32(4),
+		"ack_seq": data.decode_u32(8),
+		"ack_age": data.decode_u16(12),
+		"flags": data.decode_u8(14),
+		"held": data.decode_u8(15),
+		"score1": data.decode_u32(16),
+		"score2": data.decode_u32(20),
+		"player1_x": data.decode_float(24),
+		"player1_y": data.decode_float(28),
+		"player1_cos": data.decode_float(32),
+		"player1_sin": data.decode_float(36),
+		"player1_vx": data.decode_float(40),
+		"player1_vy": data.decode_float(44),
+		"player1_spin": data.decode_float(48),
+		"player1_fx": data.decode_float(52),
+		"player1_fy": data.decode_float(56),
+		"player2_x": data.decode_float(60),
+		"player2_y": data.decode_float(64),
+		"player2_cos": data.decode_float(68),
+		"player2_sin": data.decode_float(72),
+		"player2_vx": data.decode_float(76),
+		"player2_vy": data.decode_float(80),
+		"player2_spin": data.decode_float(84),
+		"player2_fx": data.decode_float(88),
+		"player2_fy": data.decode_float(92),
+		"ball_x": data.decode_float(96),
+		"ball_y": data.decode_float(100),
+		"ball_cos": data.decode_float(104),
+		"ball_sin": data.decode_float(108),
+		"ball_vx": data.decode_float(112),
+		"ball_vy": data.decode_float(116),
+		"ball_spin": data.decode_float(120),
+		"ball_fx": data.decode_float(124),
+		"ball_fy": data.decode_float(128),
+		"player1_gravity": data.decode_float(132),
+		"player2_gravity": data.decode_float(136),
+		"ball_gravity": data.decode_float(140),
+		"reset_in": data.decode_u32(144),
+		"gravity_in": data.decode_u32(148),
+	}
@@ -0,0 +1,60 @@
+# Checks the packets the client builds and reads against the byte vectors the server tests use
+# (rust_volleyball/tests/fixtures/godot_packets.txt), so the two sides cannot drift apart.
+# Run from the repository root: godot --headless --path game/volleyball --script res://tests/packet_fixtures.gd
+extends SceneTree
+
+const FIXTURES := "../../rust_volleyball/tests/fixtures/godot_packets.txt"
+const Protocol = preload("res://protocol.gd")
+
+var failures := 0
+
+
+func _init() -> void:
+	var fixtures := _load_fixtures()
+	var client = load("res://volleyball_scene.gd").new()
+
+	# what the client sends, the game request goes out before the board is known
+	_expect_bytes(fixtures, "player_id_request", Protocol.encode_player_id_request())
+	_expect_bytes(fixtures, "tcp_ping", Protocol.encode_ping(0, 0, 0))
+	_expect_bytes(fixtures, "game_request", Protocol.encode_game_request(7))
+	_expect_bytes(fixtures, "jump", Protocol.encode_jump(7, 3, 0, 0))
+	_expect_bytes(fixtures, "left_pressed", Protocol.encode_left_pressed(7, 3, 0, 0))
+	_expect_bytes(fixtures, "left_released", Protocol.encode_left_released(7, 3, 0, 0))
+	_expect_bytes(fixtures, "right_pressed", Protocol.encode_right_pressed(7, 3, 0, 0))
+	_expect_bytes(fixtures, "right_released", Protocol.encode_right_released(7, 3, 0, 0))
+	_expect_bytes(fixtures, "rtt_ping", Protocol.encode_ping(7, 3, 1234567))
+	_expect_bytes(fixtures, "pong", Protocol.encode_pong(7, Protocol.decode_server_ping(fixtures["server_ping"])["server_time"], 1234567))
+
+	# what it reads
+	client._handle_udp_data(fixtures["ids"])
+	_expect("ids", [client.player_id, client.board_id, client.game_started], [7, 3, true])
+	client._handle_udp_data(fixtures["pong_reply"])
+	_expect("pong_reply", client.rtt_ms >= 0.0, true)
+	client._handle_udp_data(fixtures["state"])
+	_expect("state", [client.ball_pos, client.player1_pos, client.player2_pos, client.score1, client.score2, client.game_over],
+		[Vector2(4.0, 2.5), Vector2(6.0, 1.0), Vector2(2.0, 1.0), 3, 5, false])
+
+	client.free()
+	print("%d packet fixtures failed" % failures if failures > 0 else "packet fixtures ok")
+	quit(1 if failures > 0 else 0)
+
+
+func _load_fixtures() -> Dictionary:
+	var fixtures := {}
+	var path := ProjectSettings.globalize_path("res://").path_join(FIXTURES).simplify_path()
+	for line in FileAccess.get_file_as_string(path).split("\n"):
+		if line.is_empty() or line.begins_with("#"):
+			continue
+		var parts := line.split(" ")
+		fixtures[parts[0]] = parts[1].hex_decode()
+	return fixtures
+
+
+func _expect_bytes(fixtures: Dictionary, name: String, packet: PackedByteArray) -> void:
+	_expect(name, packet.hex_encode(), fixtures[name].hex_encode())
+
+
+func _expect(name: String, actual, expected) -> void:
+	if actual != expected:
+		failures += 1
+		push_error("%s: got %s, expected %s" % [name, actual, expected])
@@ -9,25 +9,15 @@ const TCP_PORT := 12541
 const UDP_HOST := "20.157.206.105"
 const UDP_PORT := 12542
 
-# Protocol magic bytes
-var MAGIC_HEADER := PackedByteArray([58, 41, 58, 80, 58, 68])  # ":):P:D"
-
-# TCP opcodes
-var OPCODE_PLAYER_ID_REQUEST := PackedByteArray([13, 22])
-var OPCODE_PING := PackedByteArray([96, 22])
-
-# UDP opcodes
-var OPCODE_GAME_REQUEST := PackedByteArray([11, 13])
-var OPCODE_JUMP := PackedByteArray([97, 33])
-var OPCODE_LEFT_PRESSED := PackedByteArray([17, 23])
-var OPCODE_LEFT_RELEASED := PackedByteArray([25, 99])
-var OPCODE_RIGHT_PRESSED := PackedByteArray([37, 31])
-var OPCODE_RIGHT_RELEASED := PackedByteArray([67, 58])
+# Packet encoding and decoding, generated from rust_volleyball/protocol.toml
+const Protocol = preload("res://protocol.gd")
 
 # Node references - assign these in the editor or via code
 @export var parent_character_1: Node3D
 @export var parent_character_2: Node3D
 @export var ball: Node3D
+# Optional, shows the round trip to the server
+@export var ping_label: Label
 
 # Connection state
 var tcp_stream := StreamPeerTCP.new()
@@ -43,6 +33,12 @@ var game_started := false
 var ping_timer := 0.0
 const PING_INTERVAL := 20.0
 
+# Timestamped UDP pings, the server answers each with a pong
+var rtt_timer := 0.0
+const RTT_INTERVAL := 1.0
+const RTT_GAIN := 0.125
+var rtt_ms := -1.0
+
 # Game state received from server
 var ball_pos := Vector2.ZERO
 var player1_pos := Vector2.ZERO
@@ -79,6 +75,8 @@ func _process(delta: float) -> void:
 	
 	if is_server_connected:
 		_handle_ping(delta)
+	if player_id != 0:
+		_handle_rtt_ping(delta)
 	
 	if game_started:
 		_handle_input()
@@ -119,13 +117,7 @@ func _poll_udp() -> void:
 
 
 func _send_player_id_request() -> void:
-	# Build 32-byte packet: magic (6) + opcode (2) + padding (24)
-	var packet := PackedByteArray()
-	packet.append_array(MAGIC_HEADER)
-	packet.append_array(OPCODE_PLAYER_ID_REQUEST)
-	packet.resize(32)  # Pad to 32 bytes
-	
-	var error := tcp_stream.put_data(packet)
+	var error := tcp_stream.put_data(Protocol.encode_player_id_request())
 	if error != OK:
 		push_error("Failed to send player ID request: %s" % error_string(error))
 
@@ -146,31 +138,38 @@ func _handle_tcp_data(data: PackedByteArray) -> void:
 
 
 func _handle_udp_data(data: PackedByteArray) -> void:
-	if data.size() < 4:
-		return
-	
 	# Check if this is a "set address" response (board assignment)
-	# Format: [12, 64, 13, 56] + player_id (8) + board_id (8)
-	if data.size() >= 20 and data[0] == 12 and data[1] == 64 and data[2] == 13 and data[3] == 56:
-		player_id = _bytes_to_int64(data.slice(4, 12))
-		board_id = _bytes_to_int64(data.slice(12, 20))
+	if Protocol.is_ids(data):
+		var ids := Protocol.decode_ids(data)
+		player_id = ids["player_id"]
+		board_id = ids["board_id"]
 		game_started = true
 		print("Game assigned - Player ID: %d, Board ID: %d" % [player_id, board_id])
 		return
 	
-	# Game state update (64 bytes)
-	# Format: ball_radius(4) + ball_x(4) + ball_y(4) + player_radius(4) + 
-	#         p1_x(4) + p1_y(4) + p2_x(4) + p2_y(4) + score1(4) + score2(4) + game_over(1)
-	if data.size() >= 41:
-		ball_pos.x = _bytes_to_float(data.slice(4, 8))
-		ball_pos.y = _bytes_to_float(data.slice(8, 12))
-		player1_pos.x = _bytes_to_float(data.slice(16, 20))
-		player1_pos.y = _bytes_to_float(data.slice(20, 24))
-		player2_pos.x = _bytes_to_float(data.slice(24, 28))
-		player2_pos.y = _bytes_to_float(data.slice(28, 32))
-		score1 = _bytes_to_int32(data.slice(32, 36))
-		score2 = _bytes_to_int32(data.slice(36, 40))
-		game_over = data[40] == 1
+	# Pong: our time echoed and the server time
+	if Protocol.is_server_pong(data):
+		var sample: float = (_now_micros() - Protocol.decode_server_pong(data)["client_time"]) / 1000.0
+		rtt_ms = sample if rtt_ms < 0.0 else rtt_ms + RTT_GAIN * (sample - rtt_ms)
+		if ping_label:
+			ping_label.text = "ping: %d ms" % roundi(rtt_ms)
+		return
+
+	# Server ping, answered with a pong so the server sees the round trip too
+	if Protocol.is_server_ping(data):
+		var server_time: int = Protocol.decode_server_ping(data)["server_time"]
+		udp_peer.put_packet(Protocol.encode_pong(player_id, server_time, _now_micros()))
+		return
+
+	# Game state update (the fields after game_over are for predicting clients)
+	if Protocol.is_state(data):
+		var state := Protocol.decode_state(data)
+		ball_pos = Vector2(state["ball_x"], state["ball_y"])
+		player1_pos = Vector2(state["player1_x"], state["player1_y"])
+		player2_pos = Vector2(state["player2_x"], state["player2_y"])
+		score1 = state["score1"]
+		score2 = state["score2"]
+		game_over = state["game_over"] == 1
 		
 		_update_node_positions()
 
@@ -194,8 +193,7 @@ func _update_node_positions() -> void:
 
 
 func _send_game_request() -> void:
-	var packet := _build_udp_packet(OPCODE_GAME_REQUEST)
-	udp_peer.put_packet(packet)
+	udp_peer.put_packet(Protocol.encode_game_request(player_id))
 	print("Game request sent")
 
 
@@ -208,73 +206,49 @@ func _handle_ping(delta: float) -> void:
 
 func _send_ping() -> void:
 	# Ping is sent via TCP (server tracks last_ping per TCP connection)
-	var packet := PackedByteArray()
-	packet.append_array(MAGIC_HEADER)
-	packet.append_array(OPCODE_PING)
-	packet.resize(32)  # Pad to 32 bytes
-	
-	var error := tcp_stream.put_data(packet)
+	var error := tcp_stream.put_data(Protocol.encode_ping(0, 0, 0))
 	if error != OK:
 		push_error("Failed to send ping: %s" % error_string(error))
 	else:
 		print("Ping sent")
 
 
+func _handle_rtt_ping(delta: float) -> void:
+	rtt_timer += delta
+	if rtt_timer >= RTT_INTERVAL:
+		rtt_timer = 0.0
+		# our clock, echoed back in the pong
+		udp_peer.put_packet(Protocol.encode_ping(player_id, board_id, _now_micros()))
+
+
+func _now_micros() -> int:
+	# never 0, that would be a plain keepalive
+	return Time.get_ticks_usec() + 1
+
+
 func _handle_input() -> void:
 	# Jump
 	if Input.is_action_just_pressed("ui_up"):
-		_send_udp_input(OPCODE_JUMP)
+		udp_peer.put_packet(Protocol.encode_jump(player_id, board_id, 0, 0))
 
 	# Left movement - swap left/right to fix inverted controls
 	if Input.is_action_just_pressed("ui_left"):
-		_send_udp_input(OPCODE_RIGHT_PRESSED)
+		udp_peer.put_packet(Protocol.encode_right_pressed(player_id, board_id, 0, 0))
 	if Input.is_action_just_released("ui_left"):
-		_send_udp_input(OPCODE_RIGHT_RELEASED)
+		udp_peer.put_packet(Protocol.encode_right_released(player_id, board_id, 0, 0))
 
 	# Right movement - swap left/right to fix inverted controls
 	if Input.is_action_just_pressed("ui_right"):
-		_send_udp_input(OPCODE_LEFT_PRESSED)
+		udp_peer.put_packet(Protocol.encode_left_pressed(player_id, board_id, 0, 0))
 	if Input.is_action_just_released("ui_right"):
-		_send_udp_input(OPCODE_LEFT_RELEASED)
-
-
-func _send_udp_input(opcode: PackedByteArray) -> void:
-	var packet := _build_udp_packet(opcode)
-	udp_peer.put_packet(packet)
-
-
-func _build_udp_packet(opcode: PackedByteArray) -> PackedByteArray:
-	# Build 32-byte packet:
-	# magic (6) + opcode (2) + player_id (8) + board_id (8) + padding (8)
-	var packet := PackedByteArray()
-	packet.append_array(MAGIC_HEADER)
-	packet.append_array(opcode)
-	packet.append_array(_int64_to_bytes(player_id))
-	packet.append_array(_int64_to_bytes(board_id))
-	packet.resize(32)  # Ensure exactly 32 bytes
-	return packet
-
-
-# Utility functions for byte conversion (little-endian)
-func _int64_to_bytes(value: int) -> PackedByteArray:
-	var bytes := PackedByteArray()
-	bytes.resize(8)
-	bytes.encode_s64(0, value)
-	return bytes
+		udp_peer.put_packet(Protocol.encode_left_released(player_id, board_id, 0, 0))
 
 
+# The TCP replies are 8 bytes (little-endian)
 func _bytes_to_int64(bytes: PackedByteArray) -> int:
 	return bytes.decode_s64(0)
 
 
-func _bytes_to_int32(bytes: PackedByteArray) -> int:
-	return bytes.decode_s32(0)
-
-
-func _bytes_to_float(bytes: PackedByteArray) -> float:
-	return bytes.decode_float(0)
-
-
 func disconnect_from_server() -> void:
 	tcp_stream.disconnect_from_host()
 	udp_peer.close()
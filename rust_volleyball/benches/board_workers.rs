@@ -0,0 +1,54 @@
+// cargo bench --bench board_workers
+// steps a fixed number of boards with different worker counts, frames per second only count the time the
+// workers were busy, the sleeps between ticks that let frames become due are left out
+use std::sync::Arc;
+use std::sync::mpsc::channel;
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+use rust_volleyball::board_worker::{WorkerMessage, WorkerPool};
+use rust_volleyball::config::ServerConfig;
+use rust_volleyball::scheduler::SystemClock;
+use rust_volleyball::server_logic;
+
+const TICKS: u32 = 50;
+
+fn main() {
+    let cpus = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    println!("{cpus} CPUs available");
+    println!("{:>8} {:>8} {:>14} {:>10}", "workers", "boards", "frames/s", "speedup");
+    for boards in [100, 200, 400, 800] {
+        let mut single = None;
+        for workers in [1, 2, 4, 8] {
+            let frames_per_sec = run(workers, boards);
+            let speedup = frames_per_sec / *single.get_or_insert(frames_per_sec);
+            println!("{workers:>8} {boards:>8} {frames_per_sec:>14.0} {speedup:>10.2}");
+        }
+    }
+}
+
+fn run(workers: usize, boards: u64) -> f64 {
+    let (logic_sender, logic_receiver) = server_logic::channel(ServerConfig::default().logic_queue);
+    let (udp_sender, udp_receiver) = channel();
+    // nobody reads the snapshots, they are only drained so the channels do not grow
+    spawn(move || udp_receiver.iter().count());
+    spawn(move || logic_receiver.iter().count());
+    // the default rewind window, the snapshot kept per frame is part of the cost
+    let pool = WorkerPool::start(workers, logic_sender, udp_sender, Arc::new(SystemClock::new()), 60, Duration::from_millis(100));
+    for board_id in 0..boards {
+        pool.send(board_id, WorkerMessage::NewBoard(board_id, board_id * 2, board_id * 2 + 1, Box::default()));
+    }
+    pool.boards();
+
+    let mut busy = Duration::ZERO;
+    for _ in 0..TICKS {
+        // boards step on wall clock deadlines, after the pause a few frames are due on every board
+        std::thread::sleep(Duration::from_millis(50));
+        let start = Instant::now();
+        pool.tick();
+        // answered only after the tick, so it marks the moment every worker is done
+        pool.boards();
+        busy += start.elapsed();
+    }
+    let frames = pool.boards().iter().map(|b| b.frame).sum::<u64>();
+    frames as f64 / busy.as_secs_f64()
+}
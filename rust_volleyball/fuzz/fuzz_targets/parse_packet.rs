@@ -0,0 +1,11 @@
+// what clients send: nothing panics, and whatever parses is encoded to bytes that parse to the same message
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_volleyball::udp_server::{encode_packet, parse_packet};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = parse_packet(data) {
+        assert_eq!(parse_packet(&encode_packet(&msg)), Ok(msg));
+    }
+});
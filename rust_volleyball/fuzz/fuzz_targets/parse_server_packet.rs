@@ -0,0 +1,14 @@
+// what the client reads: nothing panics, and a state that parses is encoded the same way twice, compared as bytes
+// because of NaN
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_volleyball::udp_server::{parse_server_packet, parse_to_packet, ServerPacket};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(ServerPacket::State(state)) = parse_server_packet(data) {
+        let packet = parse_to_packet(&state);
+        let Ok(ServerPacket::State(again)) = parse_server_packet(&packet) else { panic!("encoded state does not parse") };
+        assert_eq!(parse_to_packet(&again), packet);
+    }
+});
@@ -0,0 +1,59 @@
+// the ids, ping, pong, shutdown and session packets from any field values parse back to them, states to the same bytes
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use libfuzzer_sys::arbitrary::{Result, Unstructured};
+use rust_volleyball::server_logic::{GameStateSerialized, OwnPlayer};
+use rust_volleyball::udp_server::{parse_ids_to_packet, parse_server_packet, parse_to_packet, pong_packet, server_ping_packet, session_packet, shutdown_notice_packet, ServerPacket, SessionReply};
+
+fn state(u: &mut Unstructured) -> Result<GameStateSerialized> {
+    Ok(GameStateSerialized {
+        ball_pos: u.arbitrary()?,
+        ball_radius: u.arbitrary()?,
+        player_radius: u.arbitrary()?,
+        player1_pos: u.arbitrary()?,
+        player2_pos: u.arbitrary()?,
+        score1: u.arbitrary()?,
+        score2: u.arbitrary()?,
+        game_over: u.arbitrary()?,
+        ball_velocity: u.arbitrary()?,
+        ball_gravity: u.arbitrary()?,
+        own: OwnPlayer {
+            is_player1: u.arbitrary()?,
+            velocity: u.arbitrary()?,
+            held: u.arbitrary()?,
+            ack_seq: u.arbitrary()?,
+            ack_age: u.arbitrary()?,
+        },
+        frame: u.arbitrary()?,
+        checksum: u.arbitrary()?,
+    })
+}
+
+fn check(u: &mut Unstructured) -> Result<()> {
+    let (a, b): (u64, u64) = u.arbitrary()?;
+    assert_eq!(parse_server_packet(&parse_ids_to_packet(a, b)), Ok(ServerPacket::Ids(a, b)));
+    assert_eq!(parse_server_packet(&pong_packet(a, b)), Ok(ServerPacket::Pong(a, b)));
+    assert_eq!(parse_server_packet(&server_ping_packet(a)), Ok(ServerPacket::Ping(a)));
+    assert_eq!(parse_server_packet(&shutdown_notice_packet(a)), Ok(ServerPacket::ShutdownNotice(a)));
+
+    let (seq, ack): (u32, u32) = u.arbitrary()?;
+    let reply = match u.int_in_range(0..=4)? {
+        0 => None,
+        1 => Some(SessionReply::Welcome(a, b)),
+        2 => Some(SessionReply::ShutdownNotice(a)),
+        3 => Some(SessionReply::Retry(a as u32)),
+        _ => Some(SessionReply::Disconnect),
+    };
+    assert_eq!(parse_server_packet(&session_packet(seq, ack, reply)), Ok(ServerPacket::Session(seq, ack, reply)));
+
+    // velocities and the frame lose precision on the first encoding, not after
+    let packet = parse_to_packet(&state(u)?);
+    let Ok(ServerPacket::State(state)) = parse_server_packet(&packet) else { panic!("encoded state does not parse") };
+    assert_eq!(parse_to_packet(&state), packet);
+    Ok(())
+}
+
+fuzz_target!(|data: &[u8]| {
+    let _ = check(&mut Unstructured::new(data));
+});
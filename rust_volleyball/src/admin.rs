@@ -0,0 +1,269 @@
+use std::fmt::Write as _;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use log::LevelFilter;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::oneshot;
+use crate::flood::BlockList;
+use crate::metrics::METRICS;
+use crate::server_logic::{LogicMessage, LogicSender};
+
+const HELP: &str = "commands:
+  players            list connected players
+  boards             list active boards with scores and frame counters
+  lobby              show the player waiting in the lobby
+  stats              number of players, boards, lobby occupancy and snapshot jitter
+  kick <player_id>   disconnect a player and their opponent
+  end <board_id>     force-end a board, both players are disconnected
+  block <ip>         drop every UDP packet from that address, its players time out
+  unblock <ip>       take an address off the block list
+  blocked            list the blocked addresses
+  loglevel <level>   off, error, warn, info, debug or trace (cannot go above the startup filter)
+  help               this message
+  quit               close the console
+";
+
+#[derive(Debug, PartialEq)]
+pub enum AdminCommand {
+    Players,
+    Boards,
+    Lobby,
+    Stats,
+    Kick(u64),
+    EndBoard(u64),
+    Block(IpAddr),
+    Unblock(IpAddr),
+    Blocked,
+    LogLevel(LevelFilter),
+    Help,
+    Quit,
+}
+
+// questions only the game logic thread can answer, everything else is handled by the console itself
+#[derive(Debug)]
+pub enum AdminQuery {
+    Players,
+    Boards,
+    Lobby,
+    Kick(u64),
+    EndBoard(u64),
+}
+
+#[derive(Debug)]
+pub enum AdminReply {
+    Players(Vec<PlayerInfo>),
+    Boards(Vec<BoardInfo>),
+    Lobby(Option<(u64, u64)>),
+    Done(bool),
+}
+
+#[derive(Debug)]
+pub struct PlayerInfo {
+    pub player_id: u64,
+    pub idle: Duration,
+    pub tcp: bool,
+    pub in_lobby: bool,
+    pub board_id: Option<u64>,
+    // None until the player answered a server ping
+    pub rtt: Option<Duration>,
+    // player clock minus server clock in microseconds
+    pub clock_offset: Option<i64>,
+}
+
+#[derive(Debug)]
+pub struct BoardInfo {
+    pub board_id: u64,
+    pub player1: u64,
+    pub player2: u64,
+    pub score1: u32,
+    pub score2: u32,
+    pub frame: u64,
+    pub game_over: bool,
+}
+
+pub fn parse_command(line: &str) -> Result<AdminCommand, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let argument = words.next();
+    let id = |arg: Option<&str>| match arg.map(u64::from_str) {
+        Some(Ok(id)) => Ok(id),
+        Some(Err(e)) => Err(format!("invalid id, {e}")),
+        None => Err(format!("'{command}' needs an id")),
+    };
+    match command {
+        "players" => Ok(AdminCommand::Players),
+        "boards" => Ok(AdminCommand::Boards),
+        "lobby" => Ok(AdminCommand::Lobby),
+        "stats" => Ok(AdminCommand::Stats),
+        "kick" => id(argument).map(AdminCommand::Kick),
+        "end" => id(argument).map(AdminCommand::EndBoard),
+        "block" | "unblock" => match argument.map(IpAddr::from_str) {
+            Some(Ok(ip)) if command == "block" => Ok(AdminCommand::Block(ip)),
+            Some(Ok(ip)) => Ok(AdminCommand::Unblock(ip)),
+            Some(Err(e)) => Err(format!("invalid address, {e}")),
+            None => Err(format!("'{command}' needs an address")),
+        },
+        "blocked" => Ok(AdminCommand::Blocked),
+        "loglevel" => match argument.map(LevelFilter::from_str) {
+            Some(Ok(level)) => Ok(AdminCommand::LogLevel(level)),
+            Some(Err(e)) => Err(format!("invalid log level, {e}")),
+            None => Err("'loglevel' needs a level".to_string()),
+        },
+        "help" => Ok(AdminCommand::Help),
+        "quit" | "exit" => Ok(AdminCommand::Quit),
+        "" => Err("empty command".to_string()),
+        c => Err(format!("unknown command '{c}', type 'help'")),
+    }
+}
+
+pub fn start(addr: SocketAddr, logic_sender: LogicSender, blocklist: BlockList) {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .unwrap()
+        .block_on(async { run(addr, logic_sender, blocklist).await });
+    log::error!("Admin console stopped");
+}
+
+async fn run(addr: SocketAddr, logic_sender: LogicSender, blocklist: BlockList) {
+    let listener = tokio::net::TcpListener::bind(addr).await.expect("Cannot bind admin console");
+    log::info!("Admin console listening on {addr}");
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let logic_sender = logic_sender.clone();
+                let blocklist = blocklist.clone();
+                tokio::spawn(async move {
+                    log::info!("Admin console connection from {addr}");
+                    if let Err(e) = handle_connection(stream, logic_sender, blocklist).await {
+                        log::warn!("Admin console connection error, {e}");
+                    }
+                });
+            }
+            Err(e) => log::error!("Could not accept admin connection: {e}"),
+        }
+    }
+}
+
+async fn handle_connection(stream: tokio::net::TcpStream, logic_sender: LogicSender, blocklist: BlockList) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer.write_all(b"volleyball admin console, type 'help'\n> ").await?;
+    while let Some(line) = lines.next_line().await? {
+        let response = match parse_command(&line) {
+            Ok(AdminCommand::Quit) => break,
+            Ok(AdminCommand::Help) => HELP.to_string(),
+            Ok(AdminCommand::LogLevel(level)) => {
+                log::set_max_level(level);
+                log::warn!("Log level changed to {level} from admin console");
+                format!("log level set to {level}\n")
+            }
+            Ok(AdminCommand::Stats) => {
+                match (query(&logic_sender, AdminQuery::Players).await, query(&logic_sender, AdminQuery::Boards).await, query(&logic_sender, AdminQuery::Lobby).await) {
+                    (Some(AdminReply::Players(players)), Some(AdminReply::Boards(boards)), Some(AdminReply::Lobby(lobby))) => {
+                        let (jitter_mean, jitter_max) = METRICS.send_jitter();
+                        format!("players: {}, boards: {}, lobby: {}, send jitter mean: {:.2}ms max: {:.2}ms\n", players.len(), boards.len(), if lobby.is_some() { 1 } else { 0 },
+                            jitter_mean.as_secs_f64() * 1000.0, jitter_max.as_secs_f64() * 1000.0)
+                    }
+                    _ => "game logic not responding\n".to_string(),
+                }
+            }
+            Ok(AdminCommand::Block(ip)) => {
+                log::warn!("{ip} blocked from admin console");
+                if blocklist.block(ip) { "done\n" } else { "already blocked\n" }.to_string()
+            }
+            Ok(AdminCommand::Unblock(ip)) => {
+                log::warn!("{ip} unblocked from admin console");
+                if blocklist.unblock(ip) { "done\n" } else { "not blocked\n" }.to_string()
+            }
+            Ok(AdminCommand::Blocked) => {
+                let blocked = blocklist.list();
+                let mut out = format!("{} blocked\n", blocked.len());
+                for ip in blocked {
+                    let _ = writeln!(out, "  {ip}");
+                }
+                out
+            }
+            Ok(AdminCommand::Players) => format_reply(query(&logic_sender, AdminQuery::Players).await),
+            Ok(AdminCommand::Boards) => format_reply(query(&logic_sender, AdminQuery::Boards).await),
+            Ok(AdminCommand::Lobby) => format_reply(query(&logic_sender, AdminQuery::Lobby).await),
+            Ok(AdminCommand::Kick(player_id)) => format_reply(query(&logic_sender, AdminQuery::Kick(player_id)).await),
+            Ok(AdminCommand::EndBoard(board_id)) => format_reply(query(&logic_sender, AdminQuery::EndBoard(board_id)).await),
+            Err(e) => format!("{e}\n"),
+        };
+        writer.write_all(response.as_bytes()).await?;
+        writer.write_all(b"> ").await?;
+    }
+    Ok(())
+}
+
+async fn query(logic_sender: &LogicSender, admin_query: AdminQuery) -> Option<AdminReply> {
+    let (reply_sender, reply_receiver) = oneshot::channel();
+    if let Err(e) = logic_sender.send(LogicMessage::Admin(admin_query, reply_sender)) {
+        log::error!("Cannot send admin query, {e}");
+        return None;
+    }
+    reply_receiver.await.ok()
+}
+
+fn format_reply(reply: Option<AdminReply>) -> String {
+    let mut out = String::new();
+    match reply {
+        None => out.push_str("game logic not responding\n"),
+        Some(AdminReply::Players(players)) => {
+            let _ = writeln!(out, "{} players", players.len());
+            for p in players {
+                let location = match (p.in_lobby, p.board_id) {
+                    (true, _) => "lobby".to_string(),
+                    (false, Some(board_id)) => format!("board {board_id}"),
+                    (false, None) => "idle".to_string(),
+                };
+                let ping = match (p.rtt, p.clock_offset) {
+                    (Some(rtt), Some(offset)) => format!(" rtt: {:.1}ms offset: {:.1}ms", rtt.as_secs_f64() * 1000.0, offset as f64 / 1000.0),
+                    _ => String::new(),
+                };
+                let _ = writeln!(out, "  {} tcp: {} idle: {:.1}s {location}{ping}", p.player_id, p.tcp, p.idle.as_secs_f32());
+            }
+        }
+        Some(AdminReply::Boards(boards)) => {
+            let _ = writeln!(out, "{} boards", boards.len());
+            for b in boards {
+                let _ = writeln!(out, "  {} players: {} vs {} score: {}:{} frame: {}{}",
+                    b.board_id, b.player1, b.player2, b.score1, b.score2, b.frame, if b.game_over { " game over" } else { "" });
+            }
+        }
+        Some(AdminReply::Lobby(None)) => out.push_str("lobby is empty\n"),
+        Some(AdminReply::Lobby(Some((player_id, board_id)))) => {
+            let _ = writeln!(out, "player {player_id} waiting for board {board_id}");
+        }
+        Some(AdminReply::Done(true)) => out.push_str("done\n"),
+        Some(AdminReply::Done(false)) => out.push_str("not found\n"),
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use log::LevelFilter;
+    use crate::admin::{parse_command, AdminCommand};
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("players"), Ok(AdminCommand::Players));
+        assert_eq!(parse_command("  boards  "), Ok(AdminCommand::Boards));
+        assert_eq!(parse_command("kick 42"), Ok(AdminCommand::Kick(42)));
+        assert_eq!(parse_command("end 18446744073709551615"), Ok(AdminCommand::EndBoard(u64::MAX)));
+        assert_eq!(parse_command("block 10.0.0.1"), Ok(AdminCommand::Block("10.0.0.1".parse().unwrap())));
+        assert_eq!(parse_command("unblock ::1"), Ok(AdminCommand::Unblock("::1".parse().unwrap())));
+        assert_eq!(parse_command("blocked"), Ok(AdminCommand::Blocked));
+        assert!(parse_command("block 10.0.0.1:80").is_err());
+        assert_eq!(parse_command("loglevel info"), Ok(AdminCommand::LogLevel(LevelFilter::Info)));
+        assert_eq!(parse_command("quit"), Ok(AdminCommand::Quit));
+        assert!(parse_command("kick").is_err());
+        assert!(parse_command("kick abc").is_err());
+        assert!(parse_command("loglevel loud").is_err());
+        assert!(parse_command("").is_err());
+        assert!(parse_command("reboot").is_err());
+    }
+}
@@ -1,3 +1,5 @@
+// a scratch renderer, its leftovers are kept for debugging
+
 use macroquad::prelude::*;
 
 const WIDTH: f32 = 800.0;
@@ -16,7 +18,6 @@ fn window_conf() -> Conf {
 #[macroquad::main(window_conf)]
 async fn main() {
     let mut game_state = rust_volleyball::GameState::new();
-    let mut loop_counter: u64 = 0;
 
     loop {
         // PLAYER INPUT
@@ -73,7 +74,6 @@ async fn main() {
         let (xp2, yp2, rp2) = resize_ball_shape((xp2, yp2, rp2));
         draw_circle(xp1, yp1, rp1, RED);
         draw_circle(xp2, yp2, rp2, GREEN);
-        let (gsx, gsy, gsr) = game_state.ball();
         println!("game_state player1 {} {} {}", game_state.players().0, game_state.players().1, game_state.players().2);
         println!("resized {} {} {}", xp1, yp1, rp1);
         let (x_p, y_p, r_p) = resize_ball_shape(game_state.ball());
@@ -84,23 +84,13 @@ async fn main() {
         draw_rectangle(xn, yn, wn, hn, BROWN);
 
         let (p1, p2, game_over) = game_state.points();
-        draw_text(&p1.to_string(), WIDTH / 2.0 + 120.0, 60.0, 100.0, BLACK);
-        draw_text(&p2.to_string(), WIDTH / 2.0 - 170.0, 60.0, 100.0, BLACK);
+        draw_text(p1.to_string(), WIDTH / 2.0 + 120.0, 60.0, 100.0, BLACK);
+        draw_text(p2.to_string(), WIDTH / 2.0 - 170.0, 60.0, 100.0, BLACK);
         if game_over {
             let winner = if p1 > p2 { "Player 1" } else { "Player 2" };
-            draw_text(&format!("{winner} won!"), 120.0, 150.0, 100.0, BLACK);
+            draw_text(format!("{winner} won!"), 120.0, 150.0, 100.0, BLACK);
         }
 
-        // if loop_counter % 5 == 0 {
-        //     println!("player1 x: {} y: {} r: {}", xp1, yp1, rp1);
-        //     println!("player2 x: {} y: {} r: {}", xp2, yp2, rp2);
-        //     println!("ball x: {} y: {} r: {}", x_p, y_p, y_p);
-        //     println!("{} {}", screen_width(), screen_height());
-        //     println!();
-        //     println!("ground, x: {} y: {} width: {} height: {}", x_g, y_g, w_g, h_g);
-        // }
-
-        loop_counter += 1;
         next_frame().await
     }
 }
@@ -0,0 +1,308 @@
+// headless load generator: N clients open TCP sessions, get matched over UDP, press random keys and ping,
+// and measure how long an input takes to show up in a snapshot and how evenly the snapshots arrive
+// cargo run --release --bin load_test -- --clients 1000 --duration 60 --server-pid $(pgrep -x starter)
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{sleep_until, timeout};
+use rust_volleyball::udp_server::{encode_packet, parse_server_packet, Key, PacketMsg, ServerPacket};
+
+const USAGE: &str = "usage: load_test [options]
+  --server <ip>          server address (default 127.0.0.1)
+  --tcp-port <port>      server TCP port (default 12541)
+  --udp-port <port>      server UDP port (default 12542)
+  --clients <n>          simulated players, two per board (default 100)
+  --duration <secs>      how long every client plays (default 30)
+  --ramp <secs>          clients connect evenly spread over that time (default 5)
+  --send-rate <hz>       snapshot rate the server is configured with, for the loss estimate (default 60)
+  --server-pid <pid>     server process, its CPU time is read from /proc (default: not measured)
+  --threads <n>          worker threads of the generator (default: one per core)
+  -h, --help             this text
+";
+
+const INPUTS: [Key; 5] = [Key::Left(true), Key::Left(false), Key::Right(true), Key::Right(false), Key::Jump];
+// human-like pacing: a key event every few hundred milliseconds, a ping every two seconds like the Godot client
+const INPUT_INTERVAL_MS: std::ops::Range<u64> = 100..600;
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+
+struct Options {
+    server: IpAddr,
+    tcp_port: u16,
+    udp_port: u16,
+    clients: u64,
+    duration: Duration,
+    ramp: Duration,
+    send_rate: u32,
+    server_pid: Option<u32>,
+    threads: Option<usize>,
+}
+
+#[derive(Default)]
+struct ClientStats {
+    connected: bool,
+    games: u64,
+    snapshots: u64,
+    // time spent on a board, snapshots are expected during it
+    playing: Duration,
+    // from sending an input to the first snapshot that acknowledges it
+    latencies: Vec<Duration>,
+    // between consecutive snapshots of a board
+    intervals: Vec<Duration>,
+    error: Option<String>,
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{USAGE}");
+            return;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    // thousands of clients do not fit on one thread, the generator would measure itself
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    if let Some(threads) = options.threads {
+        builder.worker_threads(threads);
+    }
+    let runtime = builder.enable_all().build().unwrap();
+    let cpu_start = options.server_pid.and_then(process_cpu_time);
+    let started = Instant::now();
+
+    let stats: Vec<ClientStats> = runtime.block_on(async {
+        let tasks: Vec<_> = (0..options.clients).map(|i| {
+            let delay = options.ramp.mul_f64(i as f64 / options.clients as f64);
+            let (server, tcp_port, udp_port, duration) = (options.server, options.tcp_port, options.udp_port, options.duration);
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                let mut stats = ClientStats::default();
+                if let Err(e) = run_client(i, SocketAddr::new(server, tcp_port), SocketAddr::new(server, udp_port), duration, &mut stats).await {
+                    stats.error = Some(e.to_string());
+                }
+                stats
+            })
+        }).collect();
+        let mut stats = vec![];
+        for task in tasks {
+            stats.push(task.await.unwrap());
+        }
+        stats
+    });
+
+    let wall = started.elapsed();
+    let cpu = cpu_start.zip(options.server_pid.and_then(process_cpu_time)).map(|(start, end)| end.saturating_sub(start));
+    report(&options, &stats, wall, cpu);
+}
+
+async fn run_client(seed: u64, tcp_addr: SocketAddr, udp_addr: SocketAddr, duration: Duration, stats: &mut ClientStats) -> std::io::Result<()> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut stream = TcpStream::connect(tcp_addr).await?;
+    stream.write_all(&encode_packet(&PacketMsg::PlayerIdRequest)).await?;
+    let player_id = timeout(REQUEST_TIMEOUT * 4, stream.read_u64_le()).await??;
+    stats.connected = true;
+
+    let bind: SocketAddr = if udp_addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap();
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(udp_addr).await?;
+
+    let end = Instant::now() + duration;
+    let mut buf = [0; 128];
+    let mut board_id = None;
+    let mut game_over = false;
+    let mut request_sent: Option<Instant> = None;
+    let mut last_snapshot: Option<Instant> = None;
+    let mut board_since = Instant::now();
+    let mut next_input = Instant::now();
+    let mut next_ping = Instant::now() + PING_INTERVAL;
+    // inputs of the current board not yet acknowledged by a snapshot, sequence numbers start at 1 on every board
+    let mut unacked: VecDeque<(u32, Instant)> = VecDeque::new();
+    let mut seq = 0;
+
+    loop {
+        let now = Instant::now();
+        if now >= end {
+            break;
+        }
+        // queue for a game, again after a lost request and after a game over
+        let waiting = board_id.is_none() || game_over;
+        if waiting && request_sent.is_none_or(|sent| now - sent > REQUEST_TIMEOUT) {
+            socket.send(&encode_packet(&PacketMsg::GameRequest(player_id))).await?;
+            request_sent = Some(now);
+        }
+        if let Some(b_id) = board_id && !game_over && now >= next_input {
+            seq += 1;
+            socket.send(&encode_packet(&PacketMsg::Input(player_id, b_id, INPUTS[rng.random_range(0..INPUTS.len())], seq, 0))).await?;
+            unacked.push_back((seq, now));
+            next_input = now + Duration::from_millis(rng.random_range(INPUT_INTERVAL_MS));
+        }
+        if now >= next_ping {
+            // keeps the TCP session from timing out
+            stream.write_all(&encode_packet(&PacketMsg::Ping(player_id, board_id.unwrap_or(0), 0))).await?;
+            next_ping = now + PING_INTERVAL;
+        }
+
+        // sleeps until the next packet or the next thing to send, no polling
+        let mut wake_at = next_ping.min(end);
+        if waiting {
+            wake_at = wake_at.min(request_sent.map_or(now, |sent| sent + REQUEST_TIMEOUT));
+        } else {
+            wake_at = wake_at.min(next_input);
+        }
+        let len = tokio::select! {
+            received = socket.recv(&mut buf) => received?,
+            _ = sleep_until(wake_at.into()) => continue,
+        };
+        let now = Instant::now();
+        match parse_server_packet(&buf[..len]) {
+            Ok(ServerPacket::Ids(_, b_id)) => {
+                request_sent = None;
+                if board_id != Some(b_id) || game_over {
+                    if let Some(previous) = board_id && previous != b_id {
+                        stats.playing += now - board_since;
+                    }
+                    board_id = Some(b_id);
+                    board_since = now;
+                    game_over = false;
+                    last_snapshot = None;
+                    unacked.clear();
+                    seq = 0;
+                }
+            }
+            Ok(ServerPacket::State(state)) => {
+                stats.snapshots += 1;
+                if let Some(last) = last_snapshot {
+                    stats.intervals.push(now - last);
+                }
+                last_snapshot = Some(now);
+                // only the highest sequence number is acknowledged, inputs below it were applied earlier or lost
+                while let Some(&(input_seq, sent)) = unacked.front() && input_seq <= state.own.ack_seq {
+                    if input_seq == state.own.ack_seq {
+                        stats.latencies.push(now - sent);
+                    }
+                    unacked.pop_front();
+                }
+                if state.game_over && !game_over {
+                    game_over = true;
+                    stats.games += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    if board_id.is_some() {
+        stats.playing += Instant::now() - board_since;
+    }
+    Ok(())
+}
+
+// user plus system time of a process, from /proc/<pid>/stat
+fn process_cpu_time(pid: u32) -> Option<Duration> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // the command name may contain spaces, the fields after it do not
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let ticks: u64 = fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?;
+    Some(Duration::from_secs_f64(ticks as f64 / clock_ticks()? as f64))
+}
+
+// the unit of the times in /proc, USER_HZ
+fn clock_ticks() -> Option<u64> {
+    let output = std::process::Command::new("getconf").arg("CLK_TCK").output().ok()?;
+    String::from_utf8(output.stdout).ok()?.trim().parse().ok().filter(|&hz| hz > 0)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+fn report(options: &Options, stats: &[ClientStats], wall: Duration, cpu: Option<Duration>) {
+    let connected = stats.iter().filter(|s| s.connected).count();
+    let errors: Vec<&String> = stats.iter().filter_map(|s| s.error.as_ref()).collect();
+    let snapshots: u64 = stats.iter().map(|s| s.snapshots).sum();
+    let playing: Duration = stats.iter().map(|s| s.playing).sum();
+    let expected = playing.as_secs_f64() * options.send_rate as f64;
+    let games: u64 = stats.iter().map(|s| s.games).sum();
+    let mut latencies: Vec<Duration> = stats.iter().flat_map(|s| s.latencies.iter().copied()).collect();
+    latencies.sort();
+    let mut intervals: Vec<Duration> = stats.iter().flat_map(|s| s.intervals.iter().copied()).collect();
+    intervals.sort();
+    let interval = Duration::from_secs_f64(1.0 / options.send_rate as f64);
+    let mut jitter: Vec<Duration> = intervals.iter().map(|i| i.abs_diff(interval)).collect();
+    jitter.sort();
+    // two players per board, averaged over the run
+    let boards = playing.as_secs_f64() / wall.as_secs_f64() / 2.0;
+
+    println!("clients: {} connected: {connected} errors: {}", options.clients, errors.len());
+    if let Some(e) = errors.first() {
+        println!("  first error: {e}");
+    }
+    println!("average boards: {boards:.1}, finished games: {}", games / 2);
+    println!("snapshots: {snapshots} expected: {expected:.0} loss: {:.2}%", if expected > 0.0 { (1.0 - snapshots as f64 / expected).max(0.0) * 100.0 } else { 0.0 });
+    // includes up to one snapshot interval of waiting for the next snapshot after the input was applied
+    println!("input to snapshot ms: p50 {:.2} p95 {:.2} p99 {:.2} max {:.2} ({} inputs)",
+        millis(percentile(&latencies, 0.5)), millis(percentile(&latencies, 0.95)), millis(percentile(&latencies, 0.99)),
+        millis(latencies.last().copied().unwrap_or_default()), latencies.len());
+    println!("snapshot inter-arrival ms: p50 {:.2} p95 {:.2} p99 {:.2} max {:.2} (expected {:.2})",
+        millis(percentile(&intervals, 0.5)), millis(percentile(&intervals, 0.95)), millis(percentile(&intervals, 0.99)),
+        millis(intervals.last().copied().unwrap_or_default()), millis(interval));
+    println!("snapshot jitter ms: p50 {:.2} p95 {:.2} p99 {:.2} max {:.2}",
+        millis(percentile(&jitter, 0.5)), millis(percentile(&jitter, 0.95)), millis(percentile(&jitter, 0.99)),
+        millis(jitter.last().copied().unwrap_or_default()));
+    if let Some(cpu) = cpu {
+        let usage = cpu.as_secs_f64() / wall.as_secs_f64() * 100.0;
+        println!("server CPU: {usage:.1}% of one core, {:.3}% per board", if boards > 0.0 { usage / boards } else { 0.0 });
+    }
+}
+
+// None for --help
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options {
+        server: "127.0.0.1".parse().unwrap(),
+        tcp_port: 12541,
+        udp_port: 12542,
+        clients: 100,
+        duration: Duration::from_secs(30),
+        ramp: Duration::from_secs(5),
+        send_rate: 60,
+        server_pid: None,
+        threads: None,
+    };
+    let mut args = args.into_iter();
+    while let Some(flag) = args.next() {
+        if flag == "--help" || flag == "-h" {
+            return Ok(None);
+        }
+        let value = args.next().ok_or(format!("{flag} needs a value"))?;
+        let invalid = |e: &dyn std::fmt::Display| format!("invalid value '{value}' for {flag}: {e}");
+        match flag.as_str() {
+            "--server" => options.server = value.parse().map_err(|e| invalid(&e))?,
+            "--tcp-port" => options.tcp_port = value.parse().map_err(|e| invalid(&e))?,
+            "--udp-port" => options.udp_port = value.parse().map_err(|e| invalid(&e))?,
+            "--clients" => options.clients = value.parse().map_err(|e| invalid(&e))?,
+            "--duration" => options.duration = Duration::from_secs(value.parse().map_err(|e| invalid(&e))?),
+            "--ramp" => options.ramp = Duration::from_secs(value.parse().map_err(|e| invalid(&e))?),
+            "--send-rate" => options.send_rate = value.parse().map_err(|e| invalid(&e))?,
+            "--server-pid" => options.server_pid = Some(value.parse().map_err(|e| invalid(&e))?),
+            "--threads" => options.threads = Some(value.parse().map_err(|e| invalid(&e))?),
+            _ => return Err(format!("unknown option {flag}")),
+        }
+    }
+    if options.clients == 0 || options.send_rate == 0 || options.threads == Some(0) {
+        return Err("--clients, --send-rate and --threads must be greater than 0".to_string());
+    }
+    Ok(Some(options))
+}
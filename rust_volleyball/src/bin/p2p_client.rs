@@ -0,0 +1,159 @@
+// two players on a LAN without the server, each runs the whole board and rolls back on late inputs of the other
+// cargo run --bin p2p_client -- --bind 0.0.0.0:12550 --peer 192.168.1.20:12550 --player 1
+// cargo run --bin p2p_client -- --bind 0.0.0.0:12550 --peer 192.168.1.10:12550 --player 2
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+use macroquad::prelude::*;
+use rust_volleyball::GameConfig;
+use rust_volleyball::p2p::RollbackSession;
+use rust_volleyball::udp_server::Key;
+
+const USAGE: &str = "usage: p2p_client --peer <ip:port> --player <1|2> [options]
+  --bind <ip:port>     local address (default 0.0.0.0:12550)
+  --delay <frames>     local input delay, hides part of the latency from the rollbacks (default 2)
+";
+
+const WIDTH: f32 = 800.0;
+const HEIGHT: f32 = 600.0;
+const RESIZE_FACTOR: f32 = 100.0;
+
+struct Options {
+    bind: SocketAddr,
+    peer: SocketAddr,
+    is_player1: bool,
+    delay: u64,
+}
+
+fn window_conf() -> Conf {
+    Conf {
+        window_title: "VolleyBall P2P".to_string(),
+        window_width: WIDTH as i32,
+        window_height: HEIGHT as i32,
+        ..Default::default()
+    }
+}
+
+#[macroquad::main(window_conf)]
+async fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    let socket = UdpSocket::bind(options.bind).unwrap();
+    socket.set_nonblocking(true).unwrap();
+    let config = GameConfig::default();
+    let time_step = Duration::from_secs(1) / config.frame_rate;
+    let mut session = RollbackSession::new(config, options.is_player1, options.delay);
+    let mut unsimulated = Duration::ZERO;
+    // a resync carries a whole board
+    let mut buf = vec![0; 65536];
+
+    loop {
+        if is_key_pressed(KeyCode::Escape) {
+            break;
+        }
+        let keys = [
+            (is_key_pressed(KeyCode::Up), Key::Jump),
+            (is_key_pressed(KeyCode::Left), Key::Left(true)),
+            (is_key_pressed(KeyCode::Right), Key::Right(true)),
+            (is_key_released(KeyCode::Left), Key::Left(false)),
+            (is_key_released(KeyCode::Right), Key::Right(false)),
+        ];
+        for (_, key) in keys.into_iter().filter(|(active, _)| *active) {
+            session.add_local_input(key);
+        }
+
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((len, addr)) if addr == options.peer => {
+                    if let Err(e) = session.receive(&buf[..len]) {
+                        println!("unexpected datagram from {addr}, {e}");
+                    }
+                }
+                Ok((_, addr)) => println!("datagram from unknown address {addr}"),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                // the peer is not up yet
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => break,
+                Err(e) => {
+                    println!("network error: {e}");
+                    break;
+                }
+            }
+        }
+
+        // a stalled frame is tried again next time, the time does not pile up meanwhile
+        unsimulated += Duration::from_secs_f32(get_frame_time());
+        let mut waiting = false;
+        while unsimulated >= time_step {
+            if !session.advance_frame() {
+                unsimulated = Duration::ZERO;
+                waiting = true;
+                break;
+            }
+            unsimulated -= time_step;
+        }
+        for datagram in std::iter::once(session.encode()).chain(session.resync_packets()) {
+            if let Err(e) = socket.send_to(&datagram, options.peer) {
+                println!("cannot send to {}: {e}", options.peer);
+            }
+        }
+
+        let state = session.state();
+        clear_background(Color::new(0.2, 0.5, 0.7, 1.0));
+        let (x1, y1, r1, x2, y2, r2) = state.players();
+        let (xp1, yp1, rp1) = resize_ball_shape((x1, y1, r1));
+        let (xp2, yp2, rp2) = resize_ball_shape((x2, y2, r2));
+        draw_circle(xp1, yp1, rp1, RED);
+        draw_circle(xp2, yp2, rp2, GREEN);
+        let (xb, yb, rb) = resize_ball_shape(state.ball());
+        draw_circle(xb, yb, rb, YELLOW);
+        let (score1, score2, game_over) = state.points();
+        draw_text(format!("{score1} : {score2}{}", if game_over { "  game over" } else { "" }), 10.0, 20.0, 20.0, WHITE);
+        if waiting {
+            draw_text(format!("waiting for {}", options.peer), 10.0, 40.0, 20.0, WHITE);
+        }
+        if let Some(frame) = session.desync() {
+            draw_text(format!("out of sync since frame {frame}"), 10.0, 60.0, 20.0, WHITE);
+        }
+
+        next_frame().await
+    }
+    println!("frames: {}, rolled back: {}, desyncs: {}", session.frame(), session.rolled_back(), session.desyncs());
+}
+
+fn resize_ball_shape(player: (f32, f32, f32)) -> (f32, f32, f32) {
+    let (x, y, r) = player;
+    (x * RESIZE_FACTOR, y * -RESIZE_FACTOR + HEIGHT, r * RESIZE_FACTOR)
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut bind: SocketAddr = "0.0.0.0:12550".parse().unwrap();
+    let mut peer = None;
+    let mut is_player1 = None;
+    let mut delay = 2;
+    let mut args = args.into_iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("{flag} needs a value"))?;
+        let invalid = |e: &dyn std::fmt::Display| format!("invalid value '{value}' for {flag}: {e}");
+        match flag.as_str() {
+            "--bind" => bind = value.parse().map_err(|e| invalid(&e))?,
+            "--peer" => peer = Some(value.parse().map_err(|e| invalid(&e))?),
+            "--player" => is_player1 = Some(match value.as_str() {
+                "1" => true,
+                "2" => false,
+                _ => return Err(invalid(&"must be 1 or 2")),
+            }),
+            "--delay" => delay = value.parse().map_err(|e| invalid(&e))?,
+            _ => return Err(format!("unknown option {flag}")),
+        }
+    }
+    Ok(Options {
+        bind,
+        peer: peer.ok_or("--peer is required")?,
+        is_player1: is_player1.ok_or("--player is required")?,
+        delay,
+    })
+}
@@ -0,0 +1,452 @@
+// generates the packet codecs of the server and the Godot client from protocol.toml
+// cargo run --bin protocol_gen [-- --check]
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+
+const USAGE: &str = "usage: protocol_gen [options]
+  --check                  only compare, exit with an error if a generated file is not what the schema gives
+";
+
+const SCHEMA: &str = "protocol.toml";
+const RUST_OUT: &str = "src/protocol.rs";
+const GDSCRIPT_OUT: &str = "../game/volleyball/protocol.gd";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Schema {
+    client: Client,
+    server: Server,
+    layouts: Vec<Layout>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Client {
+    length: usize,
+    // its last byte is the protocol version
+    magic: Vec<u8>,
+    opcode_at: usize,
+    messages: Vec<ClientMessage>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientMessage {
+    name: String,
+    opcode: [u8; 2],
+    layout: Option<String>,
+    doc: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Server {
+    magic: Vec<u8>,
+    messages: Vec<ServerMessage>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServerMessage {
+    name: String,
+    // none for a message told apart by its length
+    header: Option<Vec<u8>>,
+    length: usize,
+    layout: Option<String>,
+    doc: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Layout {
+    name: String,
+    fields: Vec<Field>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Field {
+    name: String,
+    #[serde(rename = "type")]
+    ty: Type,
+    at: usize,
+    // fixed16 only, the f32 is stored multiplied by it
+    scale: Option<u32>,
+    doc: Option<String>,
+}
+
+#[derive(Copy, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Type {
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    // an f32 in an i16, rounded and clamped
+    Fixed16,
+}
+
+impl Type {
+    fn width(self) -> usize {
+        match self {
+            Type::U8 => 1,
+            Type::U16 | Type::Fixed16 => 2,
+            Type::U32 | Type::F32 => 4,
+            Type::U64 => 8,
+        }
+    }
+
+    fn rust(self) -> &'static str {
+        match self {
+            Type::U8 => "u8",
+            Type::U16 => "u16",
+            Type::U32 => "u32",
+            Type::U64 => "u64",
+            Type::F32 | Type::Fixed16 => "f32",
+        }
+    }
+
+    fn gdscript(self) -> &'static str {
+        match self {
+            Type::F32 | Type::Fixed16 => "float",
+            _ => "int",
+        }
+    }
+}
+
+fn main() {
+    let check = match std::env::args().skip(1).collect::<Vec<_>>().as_slice() {
+        [] => false,
+        [flag] if flag == "--check" => true,
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    let outputs = match generate() {
+        Ok(outputs) => outputs,
+        Err(e) => {
+            eprintln!("{SCHEMA}: {e}");
+            std::process::exit(1);
+        }
+    };
+    let mut outdated = false;
+    for (path, content) in outputs {
+        if std::fs::read_to_string(&path).is_ok_and(|current| current == content) {
+            continue;
+        }
+        if check {
+            eprintln!("{} is not generated from the current {SCHEMA}", path.display());
+            outdated = true;
+        }
+        else if let Err(e) = std::fs::write(&path, content) {
+            eprintln!("Cannot write {}, {e}", path.display());
+            std::process::exit(1);
+        }
+        else {
+            println!("{} written", path.display());
+        }
+    }
+    if outdated {
+        std::process::exit(1);
+    }
+}
+
+// the files and what belongs in them
+fn generate() -> Result<Vec<(PathBuf, String)>, String> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let schema = std::fs::read_to_string(dir.join(SCHEMA)).map_err(|e| e.to_string())?;
+    let schema: Schema = toml::from_str(&schema).map_err(|e| e.to_string())?;
+    validate(&schema)?;
+    Ok(vec![(dir.join(RUST_OUT), rust(&schema)), (dir.join(GDSCRIPT_OUT), gdscript(&schema))])
+}
+
+fn validate(schema: &Schema) -> Result<(), String> {
+    let client = &schema.client;
+    if client.magic.is_empty() || client.opcode_at < client.magic.len() || client.opcode_at + 2 > client.length {
+        return Err("the client magic and opcode do not fit the client packet".into());
+    }
+    let mut names = HashSet::new();
+    for layout in &schema.layouts {
+        if !names.insert(&layout.name) {
+            return Err(format!("layout {} is there twice", layout.name));
+        }
+        let mut fields = HashSet::new();
+        for field in &layout.fields {
+            if !fields.insert(&field.name) {
+                return Err(format!("layout {} has field {} twice", layout.name, field.name));
+            }
+            if (field.ty == Type::Fixed16) != field.scale.is_some_and(|scale| scale > 0) {
+                return Err(format!("field {} needs a scale above 0 if and only if it is fixed16", field.name));
+            }
+        }
+    }
+
+    let mut names = HashSet::new();
+    let mut opcodes = HashSet::new();
+    for message in &client.messages {
+        if !names.insert(&message.name) || !opcodes.insert(message.opcode) {
+            return Err(format!("client message {} reuses a name or an opcode", message.name));
+        }
+        check_fields(schema, &message.name, message.layout.as_deref(), client.opcode_at + 2, client.length)?;
+    }
+    let mut headers = HashSet::new();
+    for message in &schema.server.messages {
+        if !names.insert(&message.name) {
+            return Err(format!("server message {} reuses a name", message.name));
+        }
+        let header = message.header.as_deref().unwrap_or_default();
+        if !header.is_empty() && (!header.starts_with(&schema.server.magic) || header.len() > message.length || !headers.insert(header)) {
+            return Err(format!("server message {} needs a header of its own starting with the server magic", message.name));
+        }
+        check_fields(schema, &message.name, message.layout.as_deref(), header.len(), message.length)?;
+    }
+    Ok(())
+}
+
+// the fields of a message are between its header and its end and do not overlap
+fn check_fields(schema: &Schema, message: &str, layout: Option<&str>, start: usize, end: usize) -> Result<(), String> {
+    let Some(name) = layout else { return Ok(()) };
+    let layout = schema.layouts.iter().find(|layout| layout.name == name).ok_or_else(|| format!("message {message} has no layout {name}"))?;
+    let mut used = vec![false; end];
+    for field in &layout.fields {
+        if field.at < start || field.at + field.ty.width() > end || used[field.at..field.at + field.ty.width()].contains(&true) {
+            return Err(format!("field {} of {message} overlaps the header, another field or the end of the packet", field.name));
+        }
+        used[field.at..field.at + field.ty.width()].fill(true);
+    }
+    Ok(())
+}
+
+fn layout<'a>(schema: &'a Schema, name: &Option<String>) -> Option<&'a Layout> {
+    name.as_ref().map(|name| schema.layouts.iter().find(|layout| &layout.name == name).unwrap())
+}
+
+fn camel_case(name: &str) -> String {
+    name.split('_').map(|word| word[..1].to_uppercase() + &word[1..]).collect()
+}
+
+fn rust(schema: &Schema) -> String {
+    let client = &schema.client;
+    let mut out = String::new();
+    let mut line = |text: String| writeln!(out, "{text}").unwrap();
+    line(format!("// generated from {SCHEMA} by `cargo run --bin protocol_gen`, change the schema and run it again instead of"));
+    line("// editing this file".into());
+    line(String::new());
+    line(format!("pub const CLIENT_PACKET_LEN: usize = {};", client.length));
+    line("// the last byte is the protocol version".into());
+    line(format!("pub const MAGIC: [u8; {}] = {:?};", client.magic.len(), client.magic));
+    line(format!("pub const PROTOCOL_VERSION: u8 = {};", client.magic[client.magic.len() - 1]));
+    line(format!("pub const OPCODE_AT: usize = {};", client.opcode_at));
+    line("// what server packets with a header start with".into());
+    line(format!("pub const SERVER_MAGIC: [u8; {}] = {:?};", schema.server.magic.len(), schema.server.magic));
+    line(String::new());
+    for message in &client.messages {
+        line(format!("pub const {}: [u8; 2] = {:?};", message.name.to_uppercase(), message.opcode));
+    }
+    line(String::new());
+    for message in &schema.server.messages {
+        if let Some(header) = &message.header {
+            line(format!("pub const {}_HEADER: [u8; {}] = {header:?};", message.name.to_uppercase(), header.len()));
+        }
+        line(format!("pub const {}_LEN: usize = {};", message.name.to_uppercase(), message.length));
+    }
+
+    for layout in &schema.layouts {
+        let name = camel_case(&layout.name);
+        line(String::new());
+        line("#[derive(Copy, Clone, Debug, Default, PartialEq)]".into());
+        line(format!("pub struct {name} {{"));
+        for field in &layout.fields {
+            if let Some(doc) = &field.doc {
+                line(format!("    // {doc}"));
+            }
+            line(format!("    pub {}: {},", field.name, field.ty.rust()));
+        }
+        line("}".into());
+        line(String::new());
+        line(format!("impl {name} {{"));
+        line(format!("    pub fn read(data: &[u8]) -> {name} {{"));
+        line(format!("        {name} {{"));
+        for field in &layout.fields {
+            let bytes = format!("data[{}..{}].try_into().unwrap()", field.at, field.at + field.ty.width());
+            let value = match field.ty {
+                Type::U8 => format!("data[{}]", field.at),
+                Type::Fixed16 => format!("i16::from_le_bytes({bytes}) as f32 / {:?}", field.scale.unwrap() as f32),
+                ty => format!("{}::from_le_bytes({bytes})", ty.rust()),
+            };
+            line(format!("            {}: {value},", field.name));
+        }
+        line("        }".into());
+        line("    }".into());
+        line(String::new());
+        line("    pub fn write(&self, packet: &mut [u8]) {".into());
+        for field in &layout.fields {
+            let range = format!("{}..{}", field.at, field.at + field.ty.width());
+            line(match field.ty {
+                Type::U8 => format!("        packet[{}] = self.{};", field.at, field.name),
+                Type::Fixed16 => format!("        packet[{range}].copy_from_slice(&((self.{} * {:?}).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16).to_le_bytes());",
+                                         field.name, field.scale.unwrap() as f32),
+                _ => format!("        packet[{range}].copy_from_slice(&self.{}.to_le_bytes());", field.name),
+            });
+        }
+        line("    }".into());
+        line("}".into());
+    }
+
+    for message in &client.messages {
+        line(String::new());
+        if let Some(doc) = &message.doc {
+            line(format!("// {doc}"));
+        }
+        let fields = layout(schema, &message.layout);
+        let args = fields.map_or(String::new(), |layout| format!("fields: &{}", camel_case(&layout.name)));
+        line(format!("pub fn encode_{}({args}) -> [u8; CLIENT_PACKET_LEN] {{", message.name));
+        line("    let mut packet = [0; CLIENT_PACKET_LEN];".into());
+        line("    packet[..MAGIC.len()].copy_from_slice(&MAGIC);".into());
+        line(format!("    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&{});", message.name.to_uppercase()));
+        if fields.is_some() {
+            line("    fields.write(&mut packet);".into());
+        }
+        line("    packet".into());
+        line("}".into());
+    }
+    for message in &schema.server.messages {
+        line(String::new());
+        if let Some(doc) = &message.doc {
+            line(format!("// {doc}"));
+        }
+        let upper = message.name.to_uppercase();
+        let fields = layout(schema, &message.layout);
+        let args = fields.map_or(String::new(), |layout| format!("fields: &{}", camel_case(&layout.name)));
+        line(format!("pub fn encode_{}({args}) -> [u8; {upper}_LEN] {{", message.name));
+        line(format!("    let mut packet = [0; {upper}_LEN];"));
+        if message.header.is_some() {
+            line(format!("    packet[..{upper}_HEADER.len()].copy_from_slice(&{upper}_HEADER);"));
+        }
+        if fields.is_some() {
+            line("    fields.write(&mut packet);".into());
+        }
+        line("    packet".into());
+        line("}".into());
+    }
+    out
+}
+
+// the Godot client only encodes what it sends and decodes what it receives, the decoded fields come in a Dictionary
+fn gdscript(schema: &Schema) -> String {
+    let client = &schema.client;
+    let mut out = String::new();
+    let mut line = |text: String| writeln!(out, "{text}").unwrap();
+    line(format!("# Generated from rust_volleyball/{SCHEMA} by `cargo run --bin protocol_gen`, change the schema and run it"));
+    line("# again instead of editing this file. Loaded with preload(\"res://protocol.gd\"), all functions are static.".into());
+    line("extends RefCounted".into());
+    line(String::new());
+    line(format!("const CLIENT_PACKET_LEN := {}", client.length));
+    line(format!("const MAGIC := {:?}", client.magic));
+    line(format!("const PROTOCOL_VERSION := {}", client.magic[client.magic.len() - 1]));
+    line(format!("const OPCODE_AT := {}", client.opcode_at));
+    line(String::new());
+    for message in &client.messages {
+        line(format!("const {} := {:?}", message.name.to_uppercase(), message.opcode));
+    }
+    line(String::new());
+    for message in &schema.server.messages {
+        if let Some(header) = &message.header {
+            line(format!("const {}_HEADER := {header:?}", message.name.to_uppercase()));
+        }
+        line(format!("const {}_LEN := {}", message.name.to_uppercase(), message.length));
+    }
+
+    for message in &client.messages {
+        let fields = layout(schema, &message.layout).map_or(&[][..], |layout| &layout.fields);
+        line(String::new());
+        line(String::new());
+        if let Some(doc) = &message.doc {
+            line(format!("# {doc}"));
+        }
+        let args: Vec<String> = fields.iter().map(|field| format!("{}: {}", field.name, field.ty.gdscript())).collect();
+        line(format!("static func encode_{}({}) -> PackedByteArray:", message.name, args.join(", ")));
+        line(format!("\tvar packet := PackedByteArray(MAGIC + {})", message.name.to_uppercase()));
+        line("\tpacket.resize(CLIENT_PACKET_LEN)".into());
+        for field in fields {
+            line(match field.ty {
+                Type::U8 => format!("\tpacket.encode_u8({}, {})", field.at, field.name),
+                Type::U16 => format!("\tpacket.encode_u16({}, {})", field.at, field.name),
+                Type::U32 => format!("\tpacket.encode_u32({}, {})", field.at, field.name),
+                Type::U64 => format!("\tpacket.encode_u64({}, {})", field.at, field.name),
+                Type::F32 => format!("\tpacket.encode_float({}, {})", field.at, field.name),
+                Type::Fixed16 => format!("\tpacket.encode_s16({}, clampi(roundi({} * {:?}), -32768, 32767))", field.at, field.name, field.scale.unwrap() as f32),
+            });
+        }
+        line("\treturn packet".into());
+    }
+
+    for message in &schema.server.messages {
+        let upper = message.name.to_uppercase();
+        line(String::new());
+        line(String::new());
+        if let Some(doc) = &message.doc {
+            line(format!("# {doc}"));
+        }
+        line(format!("static func is_{}(data: PackedByteArray) -> bool:", message.name));
+        line(match &message.header {
+            Some(header) => format!("\treturn data.size() == {upper}_LEN and data.slice(0, {}) == PackedByteArray({upper}_HEADER)", header.len()),
+            None => format!("\treturn data.size() == {upper}_LEN"),
+        });
+        let Some(layout) = layout(schema, &message.layout) else { continue };
+        line(String::new());
+        line(String::new());
+        line(format!("static func decode_{}(data: PackedByteArray) -> Dictionary:", message.name));
+        line("\treturn {".into());
+        for field in &layout.fields {
+            let value = match field.ty {
+                Type::U8 => format!("data.decode_u8({})", field.at),
+                Type::U16 => format!("data.decode_u16({})", field.at),
+                Type::U32 => format!("data.decode_u32({})", field.at),
+                Type::U64 => format!("data.decode_u64({})", field.at),
+                Type::F32 => format!("data.decode_float({})", field.at),
+                Type::Fixed16 => format!("data.decode_s16({}) / {:?}", field.at, field.scale.unwrap() as f32),
+            };
+            line(format!("\t\t\"{}\": {value},", field.name));
+        }
+        line("\t}".into());
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{generate, validate, Schema};
+
+    #[test]
+    fn test_generated_files_are_current() {
+        for (path, content) in generate().unwrap() {
+            let current = std::fs::read_to_string(&path).unwrap_or_default();
+            assert!(current == content, "{} is outdated, run cargo run --bin protocol_gen", path.display());
+        }
+
+        let schema = |fields: &str| -> Schema { toml::from_str(&format!("
+            [client]
+            length = 32
+            magic = [1, 2]
+            opcode_at = 2
+            messages = [{{ name = \"a\", opcode = [3, 4], layout = \"l\" }}]
+            [server]
+            magic = [5]
+            messages = []
+            [[layouts]]
+            name = \"l\"
+            fields = [{fields}]
+        ")).unwrap() };
+        assert!(validate(&schema("{ name = \"x\", type = \"u64\", at = 4 }, { name = \"y\", type = \"u32\", at = 12 }")).is_ok());
+        // into the opcode, into another field, past the end
+        assert!(validate(&schema("{ name = \"x\", type = \"u64\", at = 3 }")).is_err());
+        assert!(validate(&schema("{ name = \"x\", type = \"u64\", at = 4 }, { name = \"y\", type = \"u32\", at = 11 }")).is_err());
+        assert!(validate(&schema("{ name = \"x\", type = \"u64\", at = 28 }")).is_err());
+        assert!(validate(&schema("{ name = \"x\", type = \"fixed16\", at = 4 }")).is_err());
+    }
+}
@@ -1,40 +1,76 @@
-use std::net::UdpSocket;
-use std::sync::mpsc::channel;
-use std::thread::spawn;
-use rust_volleyball::{server_logic, tcp_server, udp_server};
+use std::net::{TcpListener, UdpSocket};
+use std::sync::Arc;
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+use rust_volleyball::{admin, metrics, shutdown};
+use rust_volleyball::metrics::METRICS;
+use rust_volleyball::packet_dump::DUMPS;
+use rust_volleyball::scheduler::SystemClock;
+use rust_volleyball::server::Server;
+use rust_volleyball::transport::TcpStreamListener;
+use rust_volleyball::config::{ConfigError, ServerConfig};
 
 /*
 todo
 - server sends player_id and board_id only in the first packet, should it send the same packet every time (containing the ids)?
-- player in lobby never disconnects, lobby with a player is created forever. server should include udp pings for lobby too, or use tcp based sessions
 - should there be a pre-game while waiting in the lobby? in that case server must send an indicator if the second player is available
 - server cleans up and finishes games after the game over
-- add statistics, how many players connected, how many active games (just print HashMap len)
 - if player reconnects too fast (within the current 5 sec ping time), client crashed, message is shorter than 40 bytes
  */
 
 fn main() {
-    env_logger::Builder::from_env(env_logger::Env::new().default_filter_or("debug"))
+    let config = match ServerConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(ConfigError::Help) => {
+            print!("{}", ConfigError::Help);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+
+    env_logger::Builder::from_env(env_logger::Env::new().default_filter_or(&config.log_level))
         .format_timestamp(Some(env_logger::TimestampPrecision::Millis))
         .init();
-    log::info!("Main start");
-
-    let udp_socket = UdpSocket::bind("0.0.0.0:12542").unwrap();
-    let socket_sender = udp_socket.try_clone().unwrap();
+    log::info!("Main start, {config:?}");
 
-    let (logic_sender, logic_receiver) = channel();
-    let udp_logic_sender = logic_sender.clone();
+    let udp_socket = UdpSocket::bind(config.udp_addr()).unwrap();
+    let tcp_listener = (!config.udp_only).then(|| TcpStreamListener::bind(config.tcp_addr()).unwrap());
+    let metrics_listener = TcpListener::bind(config.metrics).unwrap();
+    let admin_addr = config.admin;
 
-    let (udp_sender_ch, udp_receiver_ch) = channel();
+    #[cfg(feature = "websocket")]
+    let ws_listener = config.ws_addr().map(|addr| TcpStreamListener::bind(addr).unwrap());
+    #[cfg(feature = "quic")]
+    let quic_socket = config.quic_addr().map(|addr| UdpSocket::bind(addr).unwrap());
 
-    let udp_sender = spawn(move || udp_server::start_sender(socket_sender, udp_receiver_ch));
-    let udp_server = spawn(move || udp_server::start(udp_socket, udp_logic_sender));
-    let tcp_logic_sender = logic_sender.clone();
-    let tcp_server = spawn(move || tcp_server::start(tcp_logic_sender));
-    let server_logic = spawn(move || server_logic::start(logic_sender, logic_receiver, udp_sender_ch));
+    let server = match tcp_listener {
+        Some(tcp_listener) => Server::start(config, udp_socket, tcp_listener, Arc::new(SystemClock::new())),
+        None => Server::start_udp_only(config, udp_socket, Arc::new(SystemClock::new())),
+    }.unwrap();
+    #[cfg(feature = "websocket")]
+    if let Some(listener) = ws_listener {
+        server.serve_websocket(listener);
+    }
+    #[cfg(feature = "quic")]
+    if let Some(socket) = quic_socket {
+        server.serve_quic(socket).unwrap();
+    }
+    let admin_logic_sender = server.logic_sender.clone();
+    let blocklist = server.blocklist.clone();
+    spawn(move || admin::start(admin_addr, admin_logic_sender, blocklist));
+    spawn(move || metrics::start(metrics_listener));
+    let signal_logic_sender = server.logic_sender.clone();
+    spawn(move || shutdown::watch_signals(signal_logic_sender));
 
-    udp_server.join().unwrap();
-    tcp_server.join().unwrap();
-    udp_sender.join().unwrap();
-    server_logic.join().unwrap();
+    server.join();
+    // give the TCP tasks and the UDP sender a moment to flush the final disconnects
+    sleep(Duration::from_millis(200));
+    // the last scrape is up to an interval old, the final counters go to the log; the server records no replays
+    DUMPS.flush();
+    log::info!("Final metrics:\n{}", METRICS.render());
+    log::info!("Server stopped");
+    log::logger().flush();
 }
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::GameState;
+use crate::admin::BoardInfo;
+use crate::metrics::METRICS;
+use crate::rewind::RewindBuffer;
+use crate::scheduler::{BoardSchedule, Clock};
+use crate::server_logic::{GameStateSerialized, LogicMessage, LogicSender, OwnPlayer, ResyncState};
+use crate::shutdown::SavedBoard;
+use crate::udp_server::{Key, SenderMsg};
+
+// every that many snapshots of a board carry its checksum
+const CHECKSUM_EVERY: u64 = 10;
+
+pub enum WorkerMessage {
+    Tick,
+    NewBoard(u64, u64, u64, Box<GameState>),
+    // board, player, key, sequence number, intended frame (0 for none)
+    Input(u64, u64, Key, u32, u32),
+    // board, player that wants the whole board
+    Resync(u64, u64),
+    RemoveBoard(u64),
+    Boards(Sender<Vec<BoardInfo>>),
+    Drain(Sender<Vec<SavedBoard>>),
+}
+
+struct Board {
+    player1: u64,
+    player2: u64,
+    state: GameState,
+    schedule: BoardSchedule,
+    game_over: bool,
+    // highest input sequence number and the frame it was applied at, player 1 first
+    acks: [(u32, u64); 2],
+    rewind: RewindBuffer,
+    snapshots_sent: u64,
+}
+
+// boards are sharded by id over a fixed set of threads, each thread owns its boards and steps them on every tick
+pub struct WorkerPool {
+    workers: Vec<Sender<WorkerMessage>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub fn start(count: usize, logic_sender: LogicSender, udp_sender: Sender<SenderMsg>, clock: Arc<dyn Clock>, send_rate: u32, max_rewind: Duration) -> WorkerPool {
+        let (workers, handles) = (0..count).map(|worker_id| {
+            let (sender, receiver) = channel();
+            let logic_sender = logic_sender.clone();
+            let udp_sender = udp_sender.clone();
+            let clock = clock.clone();
+            let handle = spawn(move || run(worker_id, receiver, logic_sender, udp_sender, clock, send_rate, max_rewind));
+            (sender, handle)
+        }).unzip();
+        WorkerPool { workers, handles }
+    }
+
+    pub fn send(&self, board_id: u64, msg: WorkerMessage) {
+        let worker = (board_id % self.workers.len() as u64) as usize;
+        if let Err(e) = self.workers[worker].send(msg) {
+            log::error!("Board worker {worker} is gone, {e}");
+        }
+    }
+
+    pub fn tick(&self) {
+        for worker in &self.workers {
+            let _ = worker.send(WorkerMessage::Tick);
+        }
+    }
+
+    // waits for every worker, so it also tells when all earlier messages were handled
+    pub fn boards(&self) -> Vec<BoardInfo> {
+        self.collect(WorkerMessage::Boards)
+    }
+
+    pub fn drain(&self) -> Vec<SavedBoard> {
+        self.collect(WorkerMessage::Drain)
+    }
+
+    fn collect<T>(&self, message: fn(Sender<Vec<T>>) -> WorkerMessage) -> Vec<T> {
+        let (sender, receiver) = channel();
+        let asked = self.workers.iter().filter(|w| w.send(message(sender.clone())).is_ok()).count();
+        drop(sender);
+        receiver.iter().take(asked).flatten().collect()
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.workers.clear();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run(worker_id: usize, receiver: Receiver<WorkerMessage>, logic_sender: LogicSender, udp_sender: Sender<SenderMsg>, clock: Arc<dyn Clock>, send_rate: u32, max_rewind: Duration) {
+    let mut boards: HashMap<u64, Board> = HashMap::new();
+    log::debug!("Board worker {worker_id} started");
+    for message in receiver {
+        match message {
+            WorkerMessage::Tick => {
+                // one time for all boards of the tick, a slow board does not shift the deadlines of the next ones
+                let now = clock.now();
+                for (&board_id, board) in boards.iter_mut() {
+                    for _ in 0..board.schedule.frames_due(now) {
+                        let step_start = Instant::now();
+                        board.rewind.step(&mut board.state);
+                        METRICS.observe_step(step_start.elapsed());
+                    }
+                    if let Some(jitter) = board.schedule.send_due(now) {
+                        METRICS.observe_send_jitter(jitter);
+                        let with_checksum = board.snapshots_sent.is_multiple_of(CHECKSUM_EVERY);
+                        board.snapshots_sent += 1;
+                        let serialized = GameStateSerialized::new(&board.state);
+                        for (player_id, is_player1, (seq, applied_at)) in [(board.player1, true, board.acks[0]), (board.player2, false, board.acks[1])] {
+                            let age = board.state.frame().saturating_sub(applied_at).min(u16::MAX as u64) as u16;
+                            let own = OwnPlayer::new(&board.state, is_player1, seq, age);
+                            let checksum = with_checksum.then(|| board.state.snapshot_checksum(is_player1));
+                            notify(&udp_sender, SenderMsg::GameLogicState(player_id, GameStateSerialized { own, checksum, ..serialized }));
+                        }
+                    }
+                    if board.state.points().2 && !board.game_over {
+                        board.game_over = true;
+                        if let Err(e) = logic_sender.send(LogicMessage::GameOver(board_id)) {
+                            log::error!("Cannot report game over, {e}");
+                        }
+                    }
+                }
+            }
+            WorkerMessage::NewBoard(board_id, player1, player2, state) => {
+                let game_over = state.points().2;
+                let schedule = BoardSchedule::new(state.frame_rate(), send_rate, clock.now());
+                let mut rewind = RewindBuffer::new((max_rewind.as_secs_f64() * state.frame_rate() as f64) as u64);
+                rewind.reset(&state);
+                boards.insert(board_id, Board { player1, player2, state: *state, schedule, game_over, acks: [(0, 0); 2], rewind, snapshots_sent: 0 });
+            }
+            WorkerMessage::Input(board_id, player_id, key, seq, frame) => match boards.get_mut(&board_id) {
+                None => log::error!("Board id {board_id} not found in worker {worker_id}"),
+                Some(Board { player1, player2, state: board, acks, rewind, game_over, .. }) => {
+                    if player_id != *player1 && player_id != *player2 {
+                        log::error!("Player id {player_id} not found, {} {}", *player1, *player2);
+                    } else {
+                        let player = player_id == *player1;
+                        // a finished game is not reopened by an input from before its last point
+                        let intended = (frame != 0 && !*game_over).then_some(frame as u64);
+                        let current = board.frame();
+                        let applied_at = rewind.apply(board, player, key, intended);
+                        METRICS.frames_rewound.fetch_add(current - applied_at, Ordering::Relaxed);
+                        let ack = &mut acks[if player { 0 } else { 1 }];
+                        if seq > ack.0 {
+                            *ack = (seq, applied_at);
+                        }
+                    }
+                }
+            },
+            WorkerMessage::Resync(board_id, player_id) => match boards.get(&board_id) {
+                None => log::error!("Board id {board_id} not found in worker {worker_id}"),
+                Some(board) => {
+                    let (ack_seq, applied_at) = board.acks[if player_id == board.player1 { 0 } else { 1 }];
+                    let ack_age = board.state.frame().saturating_sub(applied_at).min(u16::MAX as u64) as u16;
+                    let resync = ResyncState { board: board.state.board_state(), frame: board.state.frame(), ack_seq, ack_age };
+                    notify(&udp_sender, SenderMsg::Board(player_id, resync));
+                }
+            },
+            WorkerMessage::RemoveBoard(board_id) => {
+                boards.remove(&board_id);
+            }
+            WorkerMessage::Boards(reply) => {
+                let _ = reply.send(boards.iter().map(|(&board_id, b)| {
+                    let (score1, score2, game_over) = b.state.points();
+                    BoardInfo { board_id, player1: b.player1, player2: b.player2, score1, score2, frame: b.state.frame(), game_over }
+                }).collect());
+            }
+            WorkerMessage::Drain(reply) => {
+                let _ = reply.send(boards.drain()
+                    .map(|(board_id, b)| SavedBoard { board_id, player1: b.player1, player2: b.player2, token1: 0, token2: 0, snapshot: b.state.snapshot() })
+                    .collect());
+            }
+        }
+    }
+    log::debug!("Board worker {worker_id} stopped");
+}
+
+fn notify(sender: &Sender<SenderMsg>, msg: SenderMsg) {
+    match sender.send(msg) {
+        Ok(_) => {}
+        Err(e) => log::warn!("Cannot send UPD game state, {e}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::board_worker::{WorkerMessage, WorkerPool};
+    use crate::scheduler::VirtualClock;
+    use crate::server_logic;
+    use crate::udp_server::{Key, SenderMsg};
+
+    #[test]
+    fn test_boards_sharded_over_workers() {
+        let (logic_sender, _logic_receiver) = server_logic::channel(100);
+        let (udp_sender, udp_receiver) = channel();
+        let clock = Arc::new(VirtualClock::default());
+        let pool = WorkerPool::start(3, logic_sender, udp_sender, clock.clone(), 60, Duration::from_millis(100));
+        for board_id in 0..10 {
+            pool.send(board_id, WorkerMessage::NewBoard(board_id, board_id * 2, board_id * 2 + 1, Box::default()));
+        }
+        pool.send(4, WorkerMessage::Input(4, 9, Key::Jump, 1, 0));
+        pool.send(5, WorkerMessage::RemoveBoard(5));
+        let mut boards: Vec<u64> = pool.boards().iter().map(|b| b.board_id).collect();
+        boards.sort();
+        assert_eq!(boards, [0, 1, 2, 3, 4, 6, 7, 8, 9]);
+
+        clock.advance(Duration::from_millis(50));
+        pool.tick();
+        // frames at 0, 16.7, 33.3 and 50 ms
+        assert!(pool.boards().iter().all(|b| b.frame == 4));
+        // every board sends its state to both players, the first snapshot of a board carries its checksum
+        let states: Vec<_> = udp_receiver.try_iter().filter_map(|m| match m {
+            SenderMsg::GameLogicState(_, state) => Some(state),
+            _ => None,
+        }).collect();
+        assert_eq!(states.len(), 18);
+        assert!(states.iter().all(|s| s.checksum.is_some()));
+
+        assert_eq!(pool.drain().len(), 9);
+        assert!(pool.boards().is_empty());
+    }
+}
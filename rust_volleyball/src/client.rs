@@ -0,0 +1,551 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+use rand::Rng;
+use crate::GameConfig;
+use crate::clock_sync::{micros, ClockSync};
+use crate::migration;
+use crate::reliable::ReliableChannel;
+use crate::server_logic::{GameStateSerialized, ResyncState};
+use crate::udp_server::{encode_packet, parse_server_packet, Key, PacketMsg, ServerPacket, SessionReply, SessionRequest};
+
+// the server drops players that stay quiet for longer than its idle timeout
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+// the game request or the ids answer may get lost
+const JOIN_RETRY: Duration = Duration::from_millis(250);
+// how long connect_udp waits for the server to welcome the player
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// nothing heard for that long during a game, maybe the NAT gave the socket a new address; the states come many times a
+// second then
+const MIGRATE_AFTER: Duration = Duration::from_secs(1);
+// the same in the lobby and for a quiet UDP-only session, only the answers to the pings come then, about one per
+// PING_INTERVAL, a late or lost one is no reason to move
+const MIGRATE_AFTER_IDLE: Duration = Duration::from_secs(4);
+// resync requests while the boards keep differing, the server's answer may get lost
+const RESYNC_INTERVAL: Duration = Duration::from_secs(1);
+// the session pings are not acknowledged for that long, the server does not know the session (any more), e.g. after
+// a restart; it does not answer packets of unknown sessions
+const SESSION_LOST: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq)]
+pub enum ClientEvent {
+    Joined { player_id: u64, board_id: u64 },
+    State(GameStateSerialized),
+    // the whole board, the answer to request_resync
+    Resync(ResyncState),
+    // running games still finish, no new ones are started; the resume token for Client::resume after the restart,
+    // 0 when the player is on no board
+    ServerShutdown(u64),
+    // the TCP or UDP-only session was closed by the server
+    Disconnected,
+}
+
+// the game protocol from the player side, a TCP session for the player id and UDP for everything else;
+// with connect_udp the session runs over the UDP socket as well
+pub struct Client {
+    tcp: Option<TcpStream>,
+    session: Option<UdpSession>,
+    udp: UdpSocket,
+    player_id: u64,
+    board_id: Option<u64>,
+    input_seq: u32,
+    joining: bool,
+    // sent with the repeated game requests, 0 for a new game
+    resume_token: u64,
+    last_join_request: Instant,
+    last_ping: Instant,
+    last_received: Instant,
+    // signs the migrations, (key, counter of the last one, when it was sent)
+    migration: (u64, u64, Instant),
+    disconnected: bool,
+    // the local clock for ping timestamps
+    started: Instant,
+    clock: ClockSync,
+    // frame of the newest snapshot and when it arrived
+    last_frame: Option<(u64, Instant)>,
+    frame_rate: u32,
+    last_resync_request: Option<Instant>,
+}
+
+// the control messages of a UDP-only session, under a token the server tells the sessions apart by
+struct UdpSession {
+    token: u64,
+    channel: ReliableChannel<SessionRequest>,
+    last_heard: Instant,
+}
+
+impl Client {
+    // opens the TCP session and requests a player id
+    pub fn connect(tcp_addr: SocketAddr, udp_addr: SocketAddr) -> std::io::Result<Client> {
+        let mut tcp = TcpStream::connect(tcp_addr)?;
+        let player_id = request_id(&mut tcp)?;
+        let key = request_migration_key(&mut tcp)?;
+        tcp.set_nonblocking(true)?;
+        let udp = bind_udp(udp_addr)?;
+        log::info!("Connected as player {player_id}");
+        Ok(Client::new(Some(tcp), None, udp, player_id, key))
+    }
+
+    // for servers with only the UDP port open: the session is opened on the UDP socket and the player id comes over it
+    pub fn connect_udp(udp_addr: SocketAddr) -> std::io::Result<Client> {
+        let udp = bind_udp(udp_addr)?;
+        let mut session = UdpSession { token: rand::rng().random(), channel: ReliableChannel::default(), last_heard: Instant::now() };
+        session.channel.send(SessionRequest::Connect(0));
+        let (player_id, key) = session.welcome(&udp)?;
+        log::info!("Connected as player {player_id}, UDP session {}", session.token);
+        Ok(Client::new(None, Some(session), udp, player_id, key))
+    }
+
+    fn new(tcp: Option<TcpStream>, session: Option<UdpSession>, udp: UdpSocket, player_id: u64, key: u64) -> Client {
+        Client {
+            tcp,
+            session,
+            udp,
+            player_id,
+            board_id: None,
+            input_seq: 0,
+            joining: false,
+            resume_token: 0,
+            last_join_request: Instant::now(),
+            last_ping: Instant::now(),
+            last_received: Instant::now(),
+            migration: (key, 0, Instant::now()),
+            disconnected: false,
+            started: Instant::now(),
+            clock: ClockSync::default(),
+            last_frame: None,
+            frame_rate: GameConfig::default().frame_rate,
+            last_resync_request: None,
+        }
+    }
+
+    pub fn player_id(&self) -> u64 {
+        self.player_id
+    }
+
+    // None until the server pairs this player with an opponent
+    pub fn board_id(&self) -> Option<u64> {
+        self.board_id
+    }
+
+    // smoothed round trip to the server, None until the first pong
+    pub fn rtt(&self) -> Option<Duration> {
+        self.clock.rtt()
+    }
+
+    // the server clock now, in microseconds since the server started, None until the first pong
+    pub fn server_time(&self) -> Option<u64> {
+        self.clock.to_remote(self.local_time())
+    }
+
+    // the local moment a server timestamp corresponds to
+    pub fn local_instant(&self, server_time: u64) -> Option<Instant> {
+        self.clock.to_local(server_time).map(|local| self.started + Duration::from_micros(local))
+    }
+
+    // the server's simulation rate, for the frame estimate
+    pub fn set_frame_rate(&mut self, frame_rate: u32) {
+        self.frame_rate = frame_rate;
+    }
+
+    // the frame the board is at on the server right now: the newest snapshot moved forward by the time since it was sent
+    pub fn server_frame(&self) -> Option<u64> {
+        let (frame, received) = self.last_frame?;
+        let since_sent = received.elapsed() + self.clock.rtt().unwrap_or_default() / 2;
+        Some(frame + (since_sent.as_secs_f64() * self.frame_rate as f64) as u64)
+    }
+
+    // asks for a game, the request is repeated by poll until a Joined event arrives
+    pub fn join_queue(&mut self) -> std::io::Result<()> {
+        self.join(0)
+    }
+
+    // asks for the board the server saved when it stopped, with the token of its shutdown notice; the board goes on
+    // once the opponent is back as well, a token the server does not know gets a new game
+    pub fn resume(&mut self, resume_token: u64) -> std::io::Result<()> {
+        self.join(resume_token)
+    }
+
+    fn join(&mut self, resume_token: u64) -> std::io::Result<()> {
+        self.joining = true;
+        self.resume_token = resume_token;
+        self.board_id = None;
+        self.last_frame = None;
+        self.send_join_request()
+    }
+
+    fn send_join_request(&mut self) -> std::io::Result<()> {
+        let request = match self.resume_token {
+            0 => PacketMsg::GameRequest(self.player_id),
+            token => PacketMsg::Resume(self.player_id, token),
+        };
+        self.send(&request)?;
+        self.last_join_request = Instant::now();
+        Ok(())
+    }
+
+    // stamped with the estimated server frame, the server applies it there if that is not too long ago;
+    // returns the sequence number the server acknowledges the input with
+    pub fn send_input(&mut self, key: Key) -> std::io::Result<u32> {
+        let board_id = self.board_id.ok_or(std::io::Error::new(ErrorKind::NotConnected, "not on a board yet"))?;
+        self.input_seq += 1;
+        let frame = self.server_frame().unwrap_or(0) as u32;
+        self.send(&PacketMsg::Input(self.player_id, board_id, key, self.input_seq, frame))?;
+        Ok(self.input_seq)
+    }
+
+    // asks for the whole board after a checksum mismatch, see Predictor::take_resync_request; at most once a second
+    pub fn request_resync(&mut self) -> std::io::Result<()> {
+        let board_id = self.board_id.ok_or(std::io::Error::new(ErrorKind::NotConnected, "not on a board yet"))?;
+        if self.last_resync_request.is_some_and(|sent| sent.elapsed() < RESYNC_INTERVAL) {
+            return Ok(());
+        }
+        self.last_resync_request = Some(Instant::now());
+        self.send(&PacketMsg::Resync(self.player_id, board_id))
+    }
+
+    // a new UDP socket to the same server, e.g. after the device changed networks; the server is told to send there
+    pub fn rebind(&mut self) -> std::io::Result<()> {
+        self.udp = bind_udp(self.udp.peer_addr()?)?;
+        self.migrate()
+    }
+
+    // tells the server the player is at the socket's current address now, signed with the key from the session;
+    // done by poll as well when the server goes quiet
+    pub fn migrate(&mut self) -> std::io::Result<()> {
+        let (key, counter, _) = self.migration;
+        self.migration = (key, counter + 1, Instant::now());
+        self.send(&PacketMsg::Migrate(self.player_id, counter + 1, migration::tag(key, self.player_id, counter + 1)))
+    }
+
+    // waits up to timeout for the next event, pings and join retries are sent on the way
+    pub fn poll(&mut self, timeout: Duration) -> std::io::Result<Option<ClientEvent>> {
+        self.keep_alive()?;
+        if let Some(event) = self.read_tcp()? {
+            return Ok(Some(event));
+        }
+        if let Some(session) = &self.session && !self.disconnected && session.last_heard.elapsed() >= SESSION_LOST {
+            log::warn!("UDP session {} not acknowledged for {SESSION_LOST:?}", session.token);
+            self.disconnected = true;
+            return Ok(Some(ClientEvent::Disconnected));
+        }
+        if timeout.is_zero() {
+            self.udp.set_nonblocking(true)?;
+        } else {
+            self.udp.set_nonblocking(false)?;
+            self.udp.set_read_timeout(Some(timeout))?;
+        }
+        let mut buf = [0; 256];
+        let len = match self.udp.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(None),
+            // an ICMP port unreachable from an earlier datagram, the server is not up yet or was restarted
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        self.last_received = Instant::now();
+        Ok(self.on_datagram(&buf[..len]))
+    }
+
+    // events that already arrived, does not block
+    pub fn events(&mut self) -> impl Iterator<Item = std::io::Result<ClientEvent>> + '_ {
+        std::iter::from_fn(|| self.poll(Duration::ZERO).transpose())
+    }
+
+    fn send(&self, msg: &PacketMsg) -> std::io::Result<()> {
+        self.udp.send(&encode_packet(msg)).map(|_| ())
+    }
+
+    fn keep_alive(&mut self) -> std::io::Result<()> {
+        if self.last_ping.elapsed() >= PING_INTERVAL {
+            // 0 would ask for no pong
+            let sent = self.local_time().max(1);
+            self.send(&PacketMsg::Ping(self.player_id, self.board_id.unwrap_or(0), sent))?;
+            // the session's own ping, what goes over TCP otherwise
+            if let Some(session) = &mut self.session && !self.disconnected {
+                session.channel.send(SessionRequest::Ping);
+            }
+            self.last_ping = Instant::now();
+        }
+        if self.joining && self.last_join_request.elapsed() >= JOIN_RETRY {
+            self.send_join_request()?;
+        }
+        // a UDP-only session's pings are acknowledged about once a second, the session follows a migration only
+        let session_quiet = self.session.as_ref().is_some_and(|s| s.last_heard.elapsed() >= MIGRATE_AFTER_IDLE);
+        let waiting = self.joining || self.board_id.is_some() || session_quiet;
+        let migrate_after = if self.board_id.is_some() { MIGRATE_AFTER } else { MIGRATE_AFTER_IDLE };
+        if waiting && !self.disconnected && self.last_received.elapsed() >= migrate_after && self.migration.2.elapsed() >= migrate_after {
+            log::debug!("Nothing from the server for {:?}, migrating", self.last_received.elapsed());
+            self.migrate()?;
+        }
+        if let Some(session) = &mut self.session {
+            session.flush(&self.udp)?;
+        }
+        Ok(())
+    }
+
+    fn local_time(&self) -> u64 {
+        micros(self.started.elapsed())
+    }
+
+    fn read_tcp(&mut self) -> std::io::Result<Option<ClientEvent>> {
+        let Some(tcp) = &mut self.tcp else { return Ok(None) };
+        if self.disconnected {
+            return Ok(None);
+        }
+        let mut buf = [0; 32];
+        match tcp.read(&mut buf) {
+            Ok(0) => {
+                self.disconnected = true;
+                Ok(Some(ClientEvent::Disconnected))
+            }
+            Ok(len) => match parse_server_packet(&buf[..len]) {
+                Ok(ServerPacket::ShutdownNotice(resume_token)) => Ok(Some(ClientEvent::ServerShutdown(resume_token))),
+                _ => {
+                    log::warn!("Unexpected TCP message: {:?}", &buf[..len]);
+                    Ok(None)
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) if matches!(e.kind(), ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted) => {
+                self.disconnected = true;
+                Ok(Some(ClientEvent::Disconnected))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn on_datagram(&mut self, data: &[u8]) -> Option<ClientEvent> {
+        match parse_server_packet(data) {
+            Ok(ServerPacket::Ids(player_id, board_id)) => {
+                // the server answers every repeated game request, only the first answer is news
+                if player_id != self.player_id || self.board_id == Some(board_id) {
+                    return None;
+                }
+                self.joining = false;
+                self.board_id = Some(board_id);
+                Some(ClientEvent::Joined { player_id, board_id })
+            }
+            Ok(ServerPacket::State(state)) => {
+                if self.last_frame.is_none_or(|(frame, _)| state.frame > frame) {
+                    self.last_frame = Some((state.frame, Instant::now()));
+                }
+                Some(ClientEvent::State(state))
+            }
+            Ok(ServerPacket::Board(resync)) => Some(ClientEvent::Resync(resync)),
+            Ok(ServerPacket::ShutdownNotice(resume_token)) => Some(ClientEvent::ServerShutdown(resume_token)),
+            Ok(ServerPacket::Pong(sent, server_time)) => {
+                if let Some(rtt) = self.clock.observe(sent, server_time, self.local_time()) {
+                    log::debug!("Round trip {rtt:?}");
+                }
+                None
+            }
+            // the server measures its side of the round trip from the answer
+            Ok(ServerPacket::Ping(server_time)) => {
+                if let Err(e) = self.send(&PacketMsg::Pong(self.player_id, server_time, self.local_time())) {
+                    log::warn!("Cannot answer server ping, {e}");
+                }
+                None
+            }
+            Ok(ServerPacket::Session(seq, ack, reply)) => {
+                let session = self.session.as_mut()?;
+                if self.disconnected {
+                    return None;
+                }
+                session.last_heard = Instant::now();
+                match session.channel.receive(seq, ack, reply) {
+                    Some(SessionReply::ShutdownNotice(resume_token)) => Some(ClientEvent::ServerShutdown(resume_token)),
+                    Some(SessionReply::Disconnect) => {
+                        self.disconnected = true;
+                        Some(ClientEvent::Disconnected)
+                    }
+                    Some(SessionReply::Welcome(..) | SessionReply::Retry(_)) | None => None,
+                }
+            }
+            Err(e) => {
+                log::warn!("Unexpected UDP message, {e}: {data:?}");
+                None
+            }
+        }
+    }
+}
+
+impl UdpSession {
+    // waits for the answer to the Connect request: player id and migration key
+    fn welcome(&mut self, udp: &UdpSocket) -> std::io::Result<(u64, u64)> {
+        let give_up = Instant::now() + CONNECT_TIMEOUT;
+        udp.set_read_timeout(Some(Duration::from_millis(50)))?;
+        let mut buf = [0; 128];
+        while Instant::now() < give_up {
+            self.flush(udp)?;
+            let len = match udp.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::ConnectionRefused) => continue,
+                Err(e) => return Err(e),
+            };
+            // snapshots cannot come before the player id, anything else is not for this session
+            match parse_server_packet(&buf[..len]) {
+                // the same connect again, with the cookie that shows the server the address is this client's
+                Ok(ServerPacket::Session(0, _, Some(SessionReply::Retry(cookie)))) => {
+                    self.channel = ReliableChannel::default();
+                    self.channel.send(SessionRequest::Connect(cookie));
+                }
+                Ok(ServerPacket::Session(seq, ack, reply)) => if let Some(SessionReply::Welcome(player_id, key)) = self.channel.receive(seq, ack, reply) {
+                    // the acknowledgement goes out with the next poll
+                    self.last_heard = Instant::now();
+                    return Ok((player_id, key));
+                },
+                _ => {}
+            }
+        }
+        Err(std::io::Error::new(ErrorKind::TimedOut, "no answer to the UDP session request"))
+    }
+
+    fn flush(&mut self, udp: &UdpSocket) -> std::io::Result<()> {
+        for (seq, request) in self.channel.outgoing(Instant::now()) {
+            udp.send(&encode_packet(&PacketMsg::Session(self.token, seq, self.channel.ack(), request)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Client {
+    // a TCP session ends with the connection, a UDP-only one is closed explicitly; if the packet gets lost the server
+    // times the session out
+    fn drop(&mut self) {
+        if let Some(session) = &mut self.session && !self.disconnected {
+            session.channel.send(SessionRequest::Disconnect);
+            let _ = session.flush(&self.udp);
+        }
+    }
+}
+
+fn request_migration_key(tcp: &mut TcpStream) -> std::io::Result<u64> {
+    tcp.write_all(&encode_packet(&PacketMsg::MigrationKeyRequest))?;
+    let mut key = [0; 8];
+    tcp.read_exact(&mut key)?;
+    Ok(u64::from_le_bytes(key))
+}
+
+fn bind_udp(udp_addr: SocketAddr) -> std::io::Result<UdpSocket> {
+    let local = if udp_addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let udp = UdpSocket::bind(local)?;
+    udp.connect(udp_addr)?;
+    Ok(udp)
+}
+
+fn request_id(tcp: &mut TcpStream) -> std::io::Result<u64> {
+    tcp.write_all(&encode_packet(&PacketMsg::PlayerIdRequest))?;
+    let mut player_id = [0; 8];
+    tcp.read_exact(&mut player_id)?;
+    Ok(u64::from_le_bytes(player_id))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use crate::client::{Client, ClientEvent};
+    use crate::config::ServerConfig;
+    use crate::scheduler::SystemClock;
+    use crate::server::Server;
+    use crate::transport::TcpStreamListener;
+    use crate::udp_server::Key;
+
+    fn wait_for(client: &mut Client, accept: impl Fn(&ClientEvent) -> bool) -> ClientEvent {
+        let give_up = Instant::now() + Duration::from_secs(10);
+        loop {
+            assert!(Instant::now() < give_up, "player {} is still waiting", client.player_id());
+            if let Some(event) = client.poll(Duration::from_millis(20)).unwrap() && accept(&event) {
+                return event;
+            }
+        }
+    }
+
+    #[test]
+    fn test_client_over_loopback() {
+        let localhost: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let socket = UdpSocket::bind(localhost).unwrap();
+        let udp_addr = socket.local_addr().unwrap();
+        let listener = TcpStreamListener::bind(localhost).unwrap();
+        let tcp_addr = listener.local_addr().unwrap();
+        let config = ServerConfig {
+            workers: 1,
+            snapshot_path: std::env::temp_dir().join(format!("volleyball_client_{}.bin", std::process::id())),
+            ..Default::default()
+        };
+        let _server = Server::start(config, socket, listener, Arc::new(SystemClock::new())).unwrap();
+
+        let mut first = Client::connect(tcp_addr, udp_addr).unwrap();
+        let mut second = Client::connect(tcp_addr, udp_addr).unwrap();
+        assert_ne!(first.player_id(), second.player_id());
+        assert!(first.send_input(Key::Jump).is_err());
+
+        first.join_queue().unwrap();
+        second.join_queue().unwrap();
+        let joined = wait_for(&mut first, |e| matches!(e, ClientEvent::Joined { .. }));
+        wait_for(&mut second, |e| matches!(e, ClientEvent::Joined { .. }));
+        assert_eq!(joined, ClientEvent::Joined { player_id: first.player_id(), board_id: first.board_id().unwrap() });
+        assert_eq!(first.board_id(), second.board_id());
+
+        first.send_input(Key::Right(true)).unwrap();
+        let ClientEvent::State(start) = wait_for(&mut first, |e| matches!(e, ClientEvent::State(_))) else { unreachable!() };
+        std::thread::sleep(Duration::from_millis(300));
+        let last = first.events().filter_map(|e| match e.unwrap() {
+            ClientEvent::State(state) => Some(state),
+            _ => None,
+        }).last().unwrap();
+        assert!(last.player1_pos.0 > start.player1_pos.0 || last.player2_pos.0 > start.player2_pos.0, "nobody moved right");
+        assert!(first.server_frame().unwrap() >= last.frame && last.frame > start.frame);
+
+        // the whole board on request, once a second at most
+        first.request_resync().unwrap();
+        let ClientEvent::Resync(resync) = wait_for(&mut first, |e| matches!(e, ClientEvent::Resync(_))) else { unreachable!() };
+        assert!(resync.frame >= last.frame);
+
+        // the first ping goes out after a second, the server runs on the same clock so the offset is about its start
+        let give_up = Instant::now() + Duration::from_secs(5);
+        while first.rtt().is_none() {
+            assert!(Instant::now() < give_up, "no pong");
+            first.poll(Duration::from_millis(20)).unwrap();
+        }
+        assert!(first.rtt().unwrap() < Duration::from_millis(500));
+        let server_time = first.server_time().unwrap();
+        let local = first.local_instant(server_time).unwrap();
+        assert!(local.duration_since(Instant::now()) < Duration::from_millis(50) && Instant::now().duration_since(local) < Duration::from_millis(50));
+
+        // a new socket, as after a network change, the snapshots follow the signed migration
+        first.rebind().unwrap();
+        wait_for(&mut first, |e| matches!(e, ClientEvent::State(_)));
+    }
+
+    #[test]
+    fn test_client_udp_only() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let udp_addr = socket.local_addr().unwrap();
+        let config = ServerConfig {
+            workers: 1,
+            snapshot_path: std::env::temp_dir().join(format!("volleyball_client_udp_{}.bin", std::process::id())),
+            ..Default::default()
+        };
+        let _server = Server::start_udp_only(config, socket, Arc::new(SystemClock::new())).unwrap();
+
+        let mut first = Client::connect_udp(udp_addr).unwrap();
+        let mut second = Client::connect_udp(udp_addr).unwrap();
+        assert_ne!(first.player_id(), second.player_id());
+        first.join_queue().unwrap();
+        second.join_queue().unwrap();
+        wait_for(&mut first, |e| matches!(e, ClientEvent::Joined { .. }));
+        wait_for(&mut second, |e| matches!(e, ClientEvent::Joined { .. }));
+        wait_for(&mut second, |e| matches!(e, ClientEvent::State(_)));
+
+        // the session follows the signed migration to a new socket, its disconnect comes from there
+        first.rebind().unwrap();
+        wait_for(&mut first, |e| matches!(e, ClientEvent::State(_)));
+
+        // the explicit disconnect ends the game for the opponent too, the server closes its session
+        drop(first);
+        wait_for(&mut second, |e| *e == ClientEvent::Disconnected);
+    }
+}
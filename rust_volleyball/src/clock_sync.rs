@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// the offset comes from the fastest of the last exchanges, a slow one was probably queued on one way only
+const OFFSET_SAMPLES: usize = 8;
+// weight of a new round trip in the smoothed value, as in TCP
+const RTT_GAIN: f64 = 0.125;
+
+// timestamps on the wire, microseconds on the sender's own clock
+pub fn micros(time: Duration) -> u64 {
+    time.as_micros() as u64
+}
+
+// round trip and clock offset to the other side, from ping/pong exchanges that carry both clocks
+#[derive(Default)]
+pub struct ClockSync {
+    smoothed_rtt: Option<f64>,
+    // (round trip, remote minus local) in microseconds
+    samples: VecDeque<(u64, i64)>,
+}
+
+impl ClockSync {
+    // sent and received on the local clock, remote is the other side's clock while it handled the ping
+    pub fn observe(&mut self, sent: u64, remote: u64, received: u64) -> Option<Duration> {
+        let rtt = received.checked_sub(sent)?;
+        // the remote clock is read about halfway through the round trip
+        let offset = remote as i64 - (sent + rtt / 2) as i64;
+        self.smoothed_rtt = Some(match self.smoothed_rtt {
+            Some(smoothed) => smoothed + RTT_GAIN * (rtt as f64 - smoothed),
+            None => rtt as f64,
+        });
+        if self.samples.len() == OFFSET_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((rtt, offset));
+        Some(Duration::from_micros(rtt))
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        self.smoothed_rtt.map(|rtt| Duration::from_micros(rtt as u64))
+    }
+
+    // remote clock minus local clock in microseconds
+    pub fn offset(&self) -> Option<i64> {
+        self.samples.iter().min_by_key(|(rtt, _)| *rtt).map(|(_, offset)| *offset)
+    }
+
+    pub fn to_remote(&self, local: u64) -> Option<u64> {
+        self.offset().map(|offset| local.saturating_add_signed(offset))
+    }
+
+    pub fn to_local(&self, remote: u64) -> Option<u64> {
+        self.offset().map(|offset| remote.saturating_add_signed(-offset))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use crate::clock_sync::ClockSync;
+
+    #[test]
+    fn test_clock_sync() {
+        let mut sync = ClockSync::default();
+        assert_eq!(sync.rtt(), None);
+        assert_eq!(sync.offset(), None);
+
+        // the remote clock is 5 s behind, 20 ms each way
+        let remote_behind = 5_000_000;
+        for i in 0..10 {
+            let sent = 10_000_000 + i * 1_000_000;
+            assert_eq!(sync.observe(sent, sent + 20_000 - remote_behind, sent + 40_000), Some(Duration::from_millis(40)));
+        }
+        assert_eq!(sync.rtt(), Some(Duration::from_millis(40)));
+        assert_eq!(sync.offset(), Some(-5_000_000));
+        assert_eq!(sync.to_remote(6_000_000), Some(1_000_000));
+        assert_eq!(sync.to_local(1_000_000), Some(6_000_000));
+
+        // one ping stuck 200 ms in a queue on the way back moves the average but not the offset
+        let sent = 30_000_000;
+        sync.observe(sent, sent + 20_000 - remote_behind, sent + 240_000);
+        assert_eq!(sync.rtt(), Some(Duration::from_millis(65)));
+        assert_eq!(sync.offset(), Some(-5_000_000));
+
+        // a pong from before the ping is ignored
+        assert_eq!(sync.observe(sent, 0, sent - 1), None);
+    }
+}
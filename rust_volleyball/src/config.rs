@@ -0,0 +1,436 @@
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use log::LevelFilter;
+use serde::Deserialize;
+use crate::GameConfig;
+use crate::netsim::NetConditions;
+
+pub const USAGE: &str = "usage: starter [options]
+  --config <path>          TOML config file, options below override it
+  --bind <ip>              address for the game ports, IPv4 or IPv6 (default 0.0.0.0)
+  --tcp-port <port>        TCP control port (default 12541)
+  --udp-port <port>        UDP game port (default 12542)
+  --udp-only               no TCP port, clients open their session on the UDP port (default off)
+  --dump-packets           log packets that cannot be parsed as hex, at most 10 per second (default off)
+  --session-timeout <secs> close UDP-only sessions silent for that long (default 10)
+  --max-sessions <n>       UDP-only sessions open at a time (default 1000)
+  --ws-port <port>         WebSocket port for browser clients, needs the websocket feature (default off)
+  --quic-port <port>       QUIC port, one connection per client instead of TCP and UDP, needs the quic feature
+                           (default off)
+  --quic-cert <path>       PEM certificate chain of the QUIC endpoint, with --quic-key a self-signed one for
+                           localhost is written there if both are missing (default quic_cert.pem)
+  --quic-key <path>        PEM private key of the QUIC endpoint (default quic_key.pem)
+  --handshake-timeout <s>  close WebSocket and QUIC connections that are not set up within that time (default 5)
+  --admin <ip:port>        admin console address (default 127.0.0.1:12543)
+  --metrics <ip:port>      metrics endpoint address (default 0.0.0.0:12544)
+  --tick-rate <hz>         scheduler wake-ups per second, a multiple of the rates below avoids jitter (default 120)
+  --sim-rate <hz>          simulation frames per second and board (default 60)
+  --send-rate <hz>         state snapshots per second and board (default 60)
+  --max-rewind <ms>        late inputs are applied up to that far back at the frame the player saw, 0 applies
+                           them on arrival (default 100)
+  --idle-timeout <secs>    evict players silent for that long (default 30)
+  --max-boards <n>         maximum number of running boards (default 1000)
+  --boards-per-address <n> running boards and lobby places one host (IP, IPv6 /64) can hold (default 2)
+  --input-rate <n>         inputs per second accepted from one source address (default 60)
+  --request-rate <n>       game requests and UDP session connects per second from one host (IP, IPv6 /64)
+                           (default 10)
+  --logic-queue <n>        packets waiting for the game logic, more are dropped (default 10000)
+  --workers <n>            board worker threads (default: number of CPUs)
+  --log-level <level>      off, error, warn, info, debug or trace (default debug)
+  --point-limit <n>        points needed to win a game (default 10)
+  --shutdown-deadline <s>  on SIGTERM, wait that long for running boards to finish (default 60)
+  --snapshot-path <path>   boards still running at shutdown are saved there (default board_snapshots.bin)
+  --netsim <conditions>    simulate a bad network on the UDP socket, e.g. latency_ms=80,jitter_ms=30,loss=0.05,
+                           duplicate=0.01,reorder=0.02,seed=1 (default off)
+  --help                   print this message
+";
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: IpAddr,
+    pub tcp_port: u16,
+    pub udp_port: u16,
+    // the TCP port is not opened, clients keep their session over UDP instead
+    pub udp_only: bool,
+    pub session_timeout_secs: u64,
+    pub max_sessions: usize,
+    // for debugging clients, see packet_dump
+    pub dump_packets: bool,
+    // browser clients speak the same protocol over WebSocket, only with the websocket cargo feature
+    pub ws_port: Option<u16>,
+    // the same for clients that speak QUIC, only with the quic cargo feature
+    pub quic_port: Option<u16>,
+    pub quic_cert: PathBuf,
+    pub quic_key: PathBuf,
+    // the WebSocket upgrade, the QUIC control stream
+    pub handshake_timeout_secs: u64,
+    pub admin: SocketAddr,
+    pub metrics: SocketAddr,
+    pub tick_rate: u32,
+    pub send_rate: u32,
+    // caps the lag compensation, so a player with a bad connection cannot rewrite what the opponent already saw for long
+    pub max_rewind_ms: u64,
+    pub idle_timeout_secs: u64,
+    pub max_boards: usize,
+    // flood protection, a source address is IP and port, a host the IP (/64 for IPv6), see flood::host
+    pub max_boards_per_address: usize,
+    pub input_rate: u32,
+    pub request_rate: u32,
+    pub logic_queue: usize,
+    // threads stepping the boards, boards are spread over them by id
+    pub workers: usize,
+    pub log_level: String,
+    pub shutdown_deadline_secs: u64,
+    pub snapshot_path: PathBuf,
+    // saved boards are dropped if none of their players returns within that time after start
+    pub resume_timeout_secs: u64,
+    pub game: GameConfig,
+    // for local testing only, shapes every datagram the server sends and receives
+    pub netsim: Option<NetConditions>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            tcp_port: 12541,
+            udp_port: 12542,
+            udp_only: false,
+            session_timeout_secs: 10,
+            max_sessions: 1000,
+            dump_packets: false,
+            ws_port: None,
+            quic_port: None,
+            quic_cert: PathBuf::from("quic_cert.pem"),
+            quic_key: PathBuf::from("quic_key.pem"),
+            handshake_timeout_secs: 5,
+            admin: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 12543),
+            metrics: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 12544),
+            tick_rate: 120,
+            send_rate: 60,
+            max_rewind_ms: 100,
+            idle_timeout_secs: 30,
+            max_boards: 1000,
+            max_boards_per_address: 2,
+            input_rate: 60,
+            request_rate: 10,
+            logic_queue: 10_000,
+            workers: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            log_level: "debug".to_string(),
+            shutdown_deadline_secs: 60,
+            snapshot_path: PathBuf::from("board_snapshots.bin"),
+            resume_timeout_secs: 300,
+            game: GameConfig::default(),
+            netsim: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Help,
+    Read(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    Argument(String),
+    Invalid(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Help => write!(f, "{USAGE}"),
+            ConfigError::Read(path, e) => write!(f, "cannot read config file {path}: {e}"),
+            ConfigError::Parse(path, e) => write!(f, "invalid config file {path}: {e}"),
+            ConfigError::Argument(msg) => write!(f, "{msg}\n\n{USAGE}"),
+            ConfigError::Invalid(msg) => write!(f, "invalid configuration: {msg}"),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<ServerConfig, ConfigError> {
+        let args: Vec<String> = args.into_iter().collect();
+        let mut config = match args.iter().position(|a| a == "--config") {
+            Some(i) => {
+                let path = args.get(i + 1).ok_or(ConfigError::Argument("--config needs a path".to_string()))?;
+                let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+                Self::from_toml(&text).map_err(|e| ConfigError::Parse(path.clone(), e))?
+            }
+            None => ServerConfig::default(),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            if flag == "--help" || flag == "-h" {
+                return Err(ConfigError::Help);
+            }
+            if flag == "--udp-only" || flag == "--dump-packets" {
+                config.udp_only |= flag == "--udp-only";
+                config.dump_packets |= flag == "--dump-packets";
+                continue;
+            }
+            let value = args.next().ok_or(ConfigError::Argument(format!("{flag} needs a value")))?;
+            match flag.as_str() {
+                "--config" => {}
+                "--bind" => config.bind = parse_arg(&flag, &value)?,
+                "--tcp-port" => config.tcp_port = parse_arg(&flag, &value)?,
+                "--udp-port" => config.udp_port = parse_arg(&flag, &value)?,
+                "--session-timeout" => config.session_timeout_secs = parse_arg(&flag, &value)?,
+                "--max-sessions" => config.max_sessions = parse_arg(&flag, &value)?,
+                "--ws-port" => config.ws_port = Some(parse_arg(&flag, &value)?),
+                "--quic-port" => config.quic_port = Some(parse_arg(&flag, &value)?),
+                "--quic-cert" => config.quic_cert = PathBuf::from(value),
+                "--quic-key" => config.quic_key = PathBuf::from(value),
+                "--handshake-timeout" => config.handshake_timeout_secs = parse_arg(&flag, &value)?,
+                "--admin" => config.admin = parse_arg(&flag, &value)?,
+                "--metrics" => config.metrics = parse_arg(&flag, &value)?,
+                "--tick-rate" => config.tick_rate = parse_arg(&flag, &value)?,
+                "--sim-rate" => config.game.frame_rate = parse_arg(&flag, &value)?,
+                "--send-rate" => config.send_rate = parse_arg(&flag, &value)?,
+                "--max-rewind" => config.max_rewind_ms = parse_arg(&flag, &value)?,
+                "--idle-timeout" => config.idle_timeout_secs = parse_arg(&flag, &value)?,
+                "--max-boards" => config.max_boards = parse_arg(&flag, &value)?,
+                "--boards-per-address" => config.max_boards_per_address = parse_arg(&flag, &value)?,
+                "--input-rate" => config.input_rate = parse_arg(&flag, &value)?,
+                "--request-rate" => config.request_rate = parse_arg(&flag, &value)?,
+                "--logic-queue" => config.logic_queue = parse_arg(&flag, &value)?,
+                "--workers" => config.workers = parse_arg(&flag, &value)?,
+                "--log-level" => config.log_level = value,
+                "--point-limit" => config.game.point_limit = parse_arg(&flag, &value)?,
+                "--shutdown-deadline" => config.shutdown_deadline_secs = parse_arg(&flag, &value)?,
+                "--snapshot-path" => config.snapshot_path = PathBuf::from(value),
+                "--netsim" => config.netsim = Some(value.parse().map_err(|e| ConfigError::Argument(format!("invalid value for {flag}: {e}")))?),
+                _ => return Err(ConfigError::Argument(format!("unknown option {flag}"))),
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> Result<ServerConfig, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+        if self.tick_rate == 0 || self.tick_rate > 1000 {
+            return invalid(format!("tick_rate must be between 1 and 1000, got {}", self.tick_rate));
+        }
+        if self.game.frame_rate == 0 || self.game.frame_rate > self.tick_rate {
+            return invalid(format!("game.frame_rate must be between 1 and tick_rate ({}), got {}", self.tick_rate, self.game.frame_rate));
+        }
+        if self.send_rate == 0 || self.send_rate > self.game.frame_rate {
+            return invalid(format!("send_rate must be between 1 and game.frame_rate ({}), got {}", self.game.frame_rate, self.send_rate));
+        }
+        if self.max_rewind_ms > 1000 {
+            return invalid(format!("max_rewind_ms must be at most 1000, got {}", self.max_rewind_ms));
+        }
+        if self.idle_timeout_secs == 0 {
+            return invalid("idle_timeout_secs must be greater than 0".to_string());
+        }
+        if self.session_timeout_secs == 0 {
+            return invalid("session_timeout_secs must be greater than 0".to_string());
+        }
+        if self.handshake_timeout_secs == 0 {
+            return invalid("handshake_timeout_secs must be greater than 0".to_string());
+        }
+        if self.max_sessions == 0 {
+            return invalid("max_sessions must be greater than 0".to_string());
+        }
+        if self.max_boards == 0 {
+            return invalid("max_boards must be greater than 0".to_string());
+        }
+        if self.max_boards_per_address == 0 || self.input_rate == 0 || self.request_rate == 0 || self.logic_queue == 0 {
+            return invalid(format!("max_boards_per_address, input_rate, request_rate and logic_queue must be greater than 0, got {}, {}, {} and {}",
+                self.max_boards_per_address, self.input_rate, self.request_rate, self.logic_queue));
+        }
+        if self.workers == 0 || self.workers > 256 {
+            return invalid(format!("workers must be between 1 and 256, got {}", self.workers));
+        }
+        if let Err(e) = LevelFilter::from_str(&self.log_level) {
+            return invalid(format!("log_level '{}': {e}", self.log_level));
+        }
+        let tcp = self.tcp_addr();
+        if [self.admin, self.metrics].contains(&tcp) || self.admin == self.metrics {
+            return invalid(format!("TCP listeners must not share an address, tcp: {tcp}, admin: {}, metrics: {}", self.admin, self.metrics));
+        }
+        if let Some(ws) = self.ws_addr() {
+            if !cfg!(feature = "websocket") {
+                return invalid("ws_port needs a server built with the websocket feature".to_string());
+            }
+            if [tcp, self.admin, self.metrics].contains(&ws) {
+                return invalid(format!("TCP listeners must not share an address, ws: {ws}, tcp: {tcp}, admin: {}, metrics: {}", self.admin, self.metrics));
+            }
+        }
+        if let Some(quic) = self.quic_addr() {
+            if !cfg!(feature = "quic") {
+                return invalid("quic_port needs a server built with the quic feature".to_string());
+            }
+            if quic == self.udp_addr() {
+                return invalid(format!("UDP sockets must not share an address, quic: {quic}, udp: {}", self.udp_addr()));
+            }
+        }
+        if self.snapshot_path.as_os_str().is_empty() {
+            return invalid("snapshot_path must not be empty".to_string());
+        }
+        if self.game.point_limit == 0 {
+            return invalid("game.point_limit must be greater than 0".to_string());
+        }
+        if [self.game.max_speed, self.game.move_force].iter().any(|v| v.is_nan() || *v <= 0.0) {
+            return invalid(format!("game.max_speed and game.move_force must be positive, got {} and {}", self.game.max_speed, self.game.move_force));
+        }
+        if let Some(netsim) = &self.netsim {
+            netsim.validate().map_err(ConfigError::Invalid)?;
+        }
+        Ok(())
+    }
+
+    pub fn tcp_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind, self.tcp_port)
+    }
+
+    pub fn udp_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind, self.udp_port)
+    }
+
+    pub fn ws_addr(&self) -> Option<SocketAddr> {
+        self.ws_port.map(|port| SocketAddr::new(self.bind, port))
+    }
+
+    pub fn quic_addr(&self) -> Option<SocketAddr> {
+        self.quic_port.map(|port| SocketAddr::new(self.bind, port))
+    }
+
+    pub fn tick_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.tick_rate as f64)
+    }
+
+    pub fn send_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.send_rate as f64)
+    }
+
+    pub fn max_rewind(&self) -> Duration {
+        Duration::from_millis(self.max_rewind_ms)
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+
+    pub fn session_timeout(&self) -> Duration {
+        Duration::from_secs(self.session_timeout_secs)
+    }
+
+    pub fn handshake_timeout(&self) -> Duration {
+        Duration::from_secs(self.handshake_timeout_secs)
+    }
+
+    pub fn shutdown_deadline(&self) -> Duration {
+        Duration::from_secs(self.shutdown_deadline_secs)
+    }
+
+    pub fn resume_timeout(&self) -> Duration {
+        Duration::from_secs(self.resume_timeout_secs)
+    }
+}
+
+fn parse_arg<T: FromStr>(flag: &str, value: &str) -> Result<T, ConfigError> where T::Err: Display {
+    value.parse().map_err(|e| ConfigError::Argument(format!("invalid value '{value}' for {flag}: {e}")))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv6Addr};
+    use crate::config::{ConfigError, ServerConfig};
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let config = ServerConfig::from_toml("
+            bind = \"::\"
+            udp_port = 4000
+            tick_rate = 60
+            send_rate = 30
+
+            [game]
+            point_limit = 5
+
+            [netsim]
+            latency_ms = 100
+            loss = 0.1
+        ").unwrap();
+        assert_eq!(config.bind, IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+        assert_eq!(config.udp_addr().to_string(), "[::]:4000");
+        assert_eq!(config.tcp_port, 12541);
+        assert_eq!(config.send_rate, 30);
+        assert_eq!(config.game.point_limit, 5);
+        assert_eq!(config.game.max_speed, 3.0);
+        assert_eq!(config.netsim.map(|n| (n.latency_ms, n.jitter_ms, n.loss)), Some((100, 0, 0.1)));
+        assert!(config.validate().is_ok());
+
+        assert!(ServerConfig::from_toml("tick_rte = 60").is_err());
+        assert!(ServerConfig::from_toml("udp_port = 70000").is_err());
+    }
+
+    #[test]
+    fn test_from_args() {
+        assert_eq!(ServerConfig::from_args(args(&[])).unwrap(), ServerConfig::default());
+
+        let config = ServerConfig::from_args(args(&["--bind", "::1", "--tcp-port", "5000", "--tick-rate", "60", "--send-rate", "30", "--point-limit", "3"])).unwrap();
+        assert_eq!(config.tcp_addr().to_string(), "[::1]:5000");
+        assert_eq!(config.tick_rate, 60);
+        assert_eq!(config.game.point_limit, 3);
+        assert_eq!(ServerConfig::from_args(args(&["--sim-rate", "60", "--send-rate", "30"])).unwrap().send_rate, 30);
+        assert_eq!(ServerConfig::from_args(args(&["--workers", "4"])).unwrap().workers, 4);
+        let config = ServerConfig::from_args(args(&["--udp-only", "--session-timeout", "5", "--max-sessions", "20"])).unwrap();
+        assert!(config.udp_only && !config.dump_packets);
+        assert_eq!(config.max_sessions, 20);
+        assert!(ServerConfig::from_args(args(&["--dump-packets"])).unwrap().dump_packets);
+        assert_eq!(config.session_timeout(), std::time::Duration::from_secs(5));
+        assert_eq!(ServerConfig::from_args(args(&["--max-rewind", "0"])).unwrap().max_rewind(), std::time::Duration::ZERO);
+        assert_eq!(ServerConfig::from_args(args(&["--handshake-timeout", "2"])).unwrap().handshake_timeout(), std::time::Duration::from_secs(2));
+        let config = ServerConfig::from_args(args(&["--input-rate", "30", "--request-rate", "4", "--logic-queue", "500", "--boards-per-address", "1"])).unwrap();
+        assert_eq!((config.input_rate, config.request_rate, config.logic_queue, config.max_boards_per_address), (30, 4, 500, 1));
+
+        assert!(matches!(ServerConfig::from_args(args(&["--help"])), Err(ConfigError::Help)));
+        assert!(matches!(ServerConfig::from_args(args(&["--tcp-port"])), Err(ConfigError::Argument(_))));
+        assert!(matches!(ServerConfig::from_args(args(&["--tcp-port", "x"])), Err(ConfigError::Argument(_))));
+        assert!(matches!(ServerConfig::from_args(args(&["--color", "red"])), Err(ConfigError::Argument(_))));
+        assert!(matches!(ServerConfig::from_args(args(&["--config", "/nonexistent/server.toml"])), Err(ConfigError::Read(..))));
+    }
+
+    #[test]
+    fn test_validate() {
+        let invalid = |list: &[&str]| matches!(ServerConfig::from_args(args(list)), Err(ConfigError::Invalid(_)));
+        assert!(invalid(&["--tick-rate", "0"]));
+        assert!(invalid(&["--tick-rate", "30", "--send-rate", "60"]));
+        assert!(invalid(&["--tick-rate", "100", "--sim-rate", "120"]));
+        assert!(invalid(&["--sim-rate", "30", "--send-rate", "60"]));
+        assert!(invalid(&["--max-boards", "0"]));
+        assert!(invalid(&["--idle-timeout", "0"]));
+        assert!(invalid(&["--session-timeout", "0"]));
+        assert!(invalid(&["--handshake-timeout", "0"]));
+        assert!(invalid(&["--max-sessions", "0"]));
+        assert!(invalid(&["--input-rate", "0"]));
+        assert!(invalid(&["--logic-queue", "0"]));
+        assert!(invalid(&["--workers", "0"]));
+        assert!(invalid(&["--log-level", "loud"]));
+        assert!(invalid(&["--admin", "0.0.0.0:12541"]));
+        assert!(invalid(&["--metrics", "127.0.0.1:12543"]));
+        assert!(invalid(&["--point-limit", "0"]));
+        assert!(invalid(&["--max-rewind", "5000"]));
+        assert!(invalid(&["--ws-port", "12541"]));
+        assert_eq!(invalid(&["--ws-port", "8080"]), !cfg!(feature = "websocket"));
+        assert!(invalid(&["--quic-port", "12542"]));
+        assert_eq!(invalid(&["--quic-port", "4433"]), !cfg!(feature = "quic"));
+        assert!(matches!(ServerConfig::from_toml("[netsim]\nloss = 1.5").unwrap().validate(), Err(ConfigError::Invalid(_))));
+        assert!(matches!(ServerConfig::from_args(args(&["--netsim", "loss=1.5"])), Err(ConfigError::Argument(_))));
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use crate::config::ServerConfig;
+use crate::udp_server::{PacketMsg, SessionRequest};
+
+// the UDP receiver looks at every datagram before it is handed on, the WebSocket and QUIC sessions at every packet of
+// their connection under its real peer address: blocked addresses are dropped, inputs and pings are limited per source
+// address, the requests that create state on the server or cost it work (game requests, migrations, resyncs, UDP session
+// connects) per host, a new source port or another connection does not give a new quota
+
+// a source that was quiet for that long has a full bucket again and is forgotten
+const SOURCE_EXPIRY: Duration = Duration::from_secs(2);
+// spoofed source addresses cannot grow the table without bound, new ones are dropped until old ones expire
+const MAX_SOURCES: usize = 100_000;
+
+// what stands for one host: the IP, for IPv6 its /64 prefix, as a host usually gets a whole /64; for the made-up
+// addresses of WebSocket and QUIC sessions see SessionRoutes::host
+pub fn host(addr: SocketAddr) -> IpAddr {
+    match addr.ip().to_canonical() {
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(ip.to_bits() & !0 << 64)),
+        ip => ip,
+    }
+}
+
+// addresses whose datagrams are dropped, shared with the admin console
+#[derive(Clone, Default)]
+pub struct BlockList {
+    inner: Arc<RwLock<HashSet<IpAddr>>>,
+}
+
+impl BlockList {
+    // false if it was blocked already
+    pub fn block(&self, ip: IpAddr) -> bool {
+        self.inner.write().unwrap().insert(ip.to_canonical())
+    }
+
+    // false if it was not blocked
+    pub fn unblock(&self, ip: IpAddr) -> bool {
+        self.inner.write().unwrap().remove(&ip.to_canonical())
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.inner.read().unwrap().contains(&ip.to_canonical())
+    }
+
+    pub fn list(&self) -> Vec<IpAddr> {
+        let mut list: Vec<IpAddr> = self.inner.read().unwrap().iter().copied().collect();
+        list.sort();
+        list
+    }
+}
+
+// up to `rate` tokens per second, a second's worth can be saved up
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32, now: Instant) -> TokenBucket {
+        TokenBucket { tokens: rate as f64, last: now }
+    }
+
+    fn take(&mut self, rate: u32, now: Instant) -> bool {
+        let rate = rate as f64;
+        self.tokens = (self.tokens + (now - self.last).as_secs_f64() * rate).min(rate);
+        self.last = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+struct Source {
+    bucket: TokenBucket,
+    last_seen: Instant,
+}
+
+// one for the UDP receiver and all sessions, the clones share the buckets
+#[derive(Clone)]
+pub struct PacketFilter {
+    blocklist: BlockList,
+    input_rate: u32,
+    request_rate: u32,
+    sources: Arc<Mutex<Sources>>,
+}
+
+struct Sources {
+    // by IP and port, players behind the same NAT each get their own input limit
+    inputs: HashMap<SocketAddr, Source>,
+    // by host, see host()
+    requests: HashMap<IpAddr, Source>,
+    last_expiry: Instant,
+}
+
+impl PacketFilter {
+    pub fn new(config: &ServerConfig, blocklist: BlockList) -> PacketFilter {
+        PacketFilter {
+            blocklist,
+            input_rate: config.input_rate,
+            request_rate: config.request_rate,
+            sources: Arc::new(Mutex::new(Sources { inputs: HashMap::new(), requests: HashMap::new(), last_expiry: Instant::now() })),
+        }
+    }
+
+    // Err with the reason for the drop counter if the packet is not to be handed on
+    pub fn admit(&self, addr: SocketAddr, msg: &PacketMsg, now: Instant) -> Result<(), &'static str> {
+        if self.blocklist.contains(addr.ip()) {
+            return Err("blocked");
+        }
+        let request = match msg {
+            // pings and pongs cost the game logic as much as an input
+            PacketMsg::Input(..) | PacketMsg::Ping(..) | PacketMsg::Pong(..) => false,
+            PacketMsg::GameRequest(_) | PacketMsg::Resume(..) | PacketMsg::Migrate(..) | PacketMsg::Resync(..) | PacketMsg::Session(.., Some(SessionRequest::Connect(_))) => true,
+            _ => return Ok(()),
+        };
+        let mut sources = self.sources.lock().unwrap();
+        if now - sources.last_expiry >= SOURCE_EXPIRY {
+            sources.last_expiry = now;
+            sources.inputs.retain(|_, source| now - source.last_seen < SOURCE_EXPIRY);
+            sources.requests.retain(|_, source| now - source.last_seen < SOURCE_EXPIRY);
+        }
+        match request {
+            true => take(&mut sources.requests, host(addr), self.request_rate, now),
+            false => take(&mut sources.inputs, addr, self.input_rate, now),
+        }
+    }
+}
+
+fn take<K: Hash + Eq>(sources: &mut HashMap<K, Source>, key: K, rate: u32, now: Instant) -> Result<(), &'static str> {
+    if sources.len() >= MAX_SOURCES && !sources.contains_key(&key) {
+        return Err("too_many_sources");
+    }
+    let source = sources.entry(key).or_insert_with(|| Source { bucket: TokenBucket::new(rate, now), last_seen: now });
+    source.last_seen = now;
+    if source.bucket.take(rate, now) { Ok(()) } else { Err("rate_limited") }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+    use crate::config::ServerConfig;
+    use crate::flood::{host, BlockList, PacketFilter};
+    use crate::udp_server::{Key, PacketMsg};
+
+    #[test]
+    fn test_packet_filter() {
+        let config = ServerConfig { input_rate: 10, request_rate: 2, ..Default::default() };
+        let blocklist = BlockList::default();
+        let filter = PacketFilter::new(&config, blocklist.clone());
+        let (flooder, player): (SocketAddr, SocketAddr) = ("10.0.0.1:5000".parse().unwrap(), "10.0.0.1:5001".parse().unwrap());
+        let input = PacketMsg::Input(1, 2, Key::Jump, 1, 0);
+        let start = Instant::now();
+
+        // a second's worth of inputs, then one more every 100ms
+        let admitted = (0..50).filter(|_| filter.admit(flooder, &input, start).is_ok()).count();
+        assert_eq!(admitted, 10);
+        assert_eq!(filter.admit(flooder, &input, start), Err("rate_limited"));
+        assert!(filter.admit(flooder, &input, start + Duration::from_millis(100)).is_ok());
+        // every source has its own input bucket, game requests are counted apart from inputs and per host
+        assert!(filter.admit(player, &input, start).is_ok());
+        assert!((0..2).all(|_| filter.admit(flooder, &PacketMsg::GameRequest(1), start).is_ok()));
+        assert_eq!(filter.admit(flooder, &PacketMsg::GameRequest(1), start), Err("rate_limited"));
+        assert_eq!(filter.admit(player, &PacketMsg::GameRequest(2), start), Err("rate_limited"));
+        assert!(filter.admit("10.0.0.2:5000".parse().unwrap(), &PacketMsg::GameRequest(3), start).is_ok());
+        let (v6, v6_neighbour) = ("[2001:db8::1]:5000".parse().unwrap(), "[2001:db8::2:1]:6000".parse().unwrap());
+        assert!((0..2).all(|_| filter.admit(v6, &PacketMsg::GameRequest(4), start).is_ok()));
+        assert_eq!(filter.admit(v6_neighbour, &PacketMsg::GameRequest(4), start), Err("rate_limited"));
+        assert!(filter.admit("[2001:db8:0:1::1]:5000".parse().unwrap(), &PacketMsg::GameRequest(4), start).is_ok());
+        // pings and pongs share the bucket of the inputs
+        assert_eq!(filter.admit(flooder, &PacketMsg::Ping(1, 2, 3), start), Err("rate_limited"));
+        assert_eq!(filter.admit(flooder, &PacketMsg::Pong(1, 2, 3), start), Err("rate_limited"));
+        assert!(filter.admit(player, &PacketMsg::Ping(1, 2, 3), start).is_ok());
+
+        // blocking works on the whole IP, also when it comes as an IPv4-mapped IPv6 address
+        assert!(blocklist.block("10.0.0.1".parse().unwrap()));
+        assert_eq!(filter.admit(player, &PacketMsg::Ping(1, 2, 3), start), Err("blocked"));
+        assert_eq!(filter.admit("[::ffff:10.0.0.1]:80".parse().unwrap(), &input, start), Err("blocked"));
+        assert!(blocklist.unblock("::ffff:10.0.0.1".parse().unwrap()));
+        assert!(blocklist.list().is_empty());
+        assert!(filter.admit(player, &input, start + Duration::from_secs(3)).is_ok());
+    }
+
+    #[test]
+    fn test_host() {
+        let host = |addr: &str| host(addr.parse().unwrap()).to_string();
+        assert_eq!(host("10.0.0.1:5000"), "10.0.0.1");
+        assert_eq!(host("[::ffff:10.0.0.1]:5000"), "10.0.0.1");
+        assert_eq!(host("[2001:db8:1:2:3:4:5:6]:5000"), "2001:db8:1:2::");
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use crate::server_logic::GameStateSerialized;
+
+// how long the last movement is continued when snapshots stop arriving, after that the bodies stand still
+const MAX_EXTRAPOLATION: Duration = Duration::from_millis(100);
+// a body that moves further than that between two snapshots was reset for the next point, it jumps instead of sliding
+const TELEPORT_DISTANCE: f32 = 1.0;
+
+// keeps the received snapshots and renders the board a fixed delay behind them, so there is usually a newer one to move towards
+pub struct SnapshotBuffer {
+    delay: Duration,
+    snapshots: VecDeque<(Instant, GameStateSerialized)>,
+}
+
+impl SnapshotBuffer {
+    // two or three snapshot intervals are enough to hide jitter and a lost packet
+    pub fn new(delay: Duration) -> SnapshotBuffer {
+        SnapshotBuffer { delay, snapshots: VecDeque::new() }
+    }
+
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+
+    // snapshots that arrive out of order are dropped
+    pub fn push(&mut self, received: Instant, state: GameStateSerialized) {
+        if self.snapshots.back().is_some_and(|(at, _)| *at > received) {
+            return;
+        }
+        self.snapshots.push_back((received, state));
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    // the board as it was `delay` ago, None before the first snapshot
+    pub fn sample(&mut self, now: Instant) -> Option<GameStateSerialized> {
+        let render_at = now.checked_sub(self.delay)?;
+        // only the last snapshot before the render time is still needed
+        while self.snapshots.len() > 2 && self.snapshots[1].0 <= render_at {
+            self.snapshots.pop_front();
+        }
+        match self.snapshots.len() {
+            0 => None,
+            1 => Some(self.snapshots[0].1),
+            _ => {
+                let (from_at, from) = self.snapshots[0];
+                let (to_at, to) = self.snapshots[1];
+                if render_at <= from_at {
+                    return Some(from);
+                }
+                if teleported(&from, &to) {
+                    return Some(if render_at < to_at { from } else { to });
+                }
+                let span = (to_at - from_at).as_secs_f32();
+                // past the newest snapshot the same line is continued, for a while
+                let elapsed = (render_at - from_at).min(to_at - from_at + MAX_EXTRAPOLATION).as_secs_f32();
+                Some(blend(&from, &to, if span > 0.0 { elapsed / span } else { 1.0 }))
+            }
+        }
+    }
+}
+
+fn teleported(from: &GameStateSerialized, to: &GameStateSerialized) -> bool {
+    [(from.ball_pos, to.ball_pos), (from.player1_pos, to.player1_pos), (from.player2_pos, to.player2_pos)].iter()
+        .any(|(a, b)| (b.0 - a.0).hypot(b.1 - a.1) > TELEPORT_DISTANCE)
+}
+
+// positions along the line between the two, everything else from the newer one once it is reached
+fn blend(from: &GameStateSerialized, to: &GameStateSerialized, t: f32) -> GameStateSerialized {
+    let lerp = |a: (f32, f32), b: (f32, f32)| (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+    GameStateSerialized {
+        ball_pos: lerp(from.ball_pos, to.ball_pos),
+        player1_pos: lerp(from.player1_pos, to.player1_pos),
+        player2_pos: lerp(from.player2_pos, to.player2_pos),
+        ..if t < 1.0 { *from } else { *to }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use crate::interpolation::SnapshotBuffer;
+    use crate::server_logic::{GameStateSerialized, OwnPlayer};
+
+    fn snapshot(ball_x: f32, score1: u32) -> GameStateSerialized {
+        GameStateSerialized {
+            ball_pos: (ball_x, 2.0),
+            ball_radius: 0.3,
+            player_radius: 0.5,
+            player1_pos: (6.0, 0.6),
+            player2_pos: (2.0 + ball_x / 10.0, 0.6),
+            score1,
+            score2: 0,
+            game_over: false,
+            ball_velocity: (0.0, 0.0),
+            ball_gravity: false,
+            own: OwnPlayer::default(),
+            frame: 0,
+            checksum: None,
+        }
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let start = Instant::now();
+        let ms = |m: u64| start + Duration::from_millis(m);
+        let mut buffer = SnapshotBuffer::new(Duration::from_millis(100));
+        assert_eq!(buffer.sample(ms(200)), None);
+
+        // 20 snapshots per second, the ball moves 1 m/s, the one at 150 ms is lost
+        for t in (0..=400).step_by(50).filter(|t| *t != 150) {
+            buffer.push(ms(t), snapshot(t as f32 / 1000.0, 0));
+        }
+        // rendered at 60 Hz, 100 ms behind, always on the line even across the lost snapshot
+        for frame in 7..30 {
+            let now = frame * 1000 / 60;
+            let state = buffer.sample(ms(now)).unwrap();
+            let expected = (now - 100) as f32 / 1000.0;
+            assert!((state.ball_pos.0 - expected).abs() < 1e-4, "ball at {} instead of {expected}", state.ball_pos.0);
+            assert!((state.player2_pos.0 - (2.0 + expected / 10.0)).abs() < 1e-4);
+            assert_eq!(state.player1_pos, (6.0, 0.6));
+        }
+
+        // the stream stops at 400 ms, the ball keeps moving for 100 ms more and then stays
+        assert!((buffer.sample(ms(550)).unwrap().ball_pos.0 - 0.45).abs() < 1e-4);
+        assert!((buffer.sample(ms(800)).unwrap().ball_pos.0 - 0.5).abs() < 1e-4);
+
+        // a reset for the next point jumps, without sliding through the court
+        let mut buffer = SnapshotBuffer::new(Duration::from_millis(100));
+        buffer.push(ms(0), snapshot(8.0, 0));
+        buffer.push(ms(50), snapshot(2.5, 1));
+        assert_eq!(buffer.sample(ms(120)), Some(snapshot(8.0, 0)));
+        assert_eq!(buffer.sample(ms(160)), Some(snapshot(2.5, 1)));
+
+        // late snapshots are not rendered
+        buffer.push(ms(40), snapshot(3.0, 1));
+        assert_eq!(buffer.sample(ms(160)), Some(snapshot(2.5, 1)));
+    }
+}
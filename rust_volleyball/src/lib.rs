@@ -1,30 +1,139 @@
 pub mod udp_server;
+pub mod protocol;
 pub mod tcp_server;
 pub mod server_logic;
+pub mod board_worker;
+pub mod scheduler;
+pub mod transport;
+pub mod netsim;
+pub mod server;
+pub mod admin;
+pub mod metrics;
+pub mod config;
+pub mod shutdown;
+pub mod client;
+pub mod prediction;
+pub mod interpolation;
+pub mod clock_sync;
+pub mod rewind;
+pub mod p2p;
+pub mod session;
+pub mod reliable;
+pub mod udp_session;
+pub mod flood;
+pub mod migration;
+pub mod packet_dump;
+#[cfg(feature = "websocket")]
+pub mod ws_server;
+#[cfg(feature = "quic")]
+pub mod quic_server;
 
 use std::collections::HashMap;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::time::Instant;
 use rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
 
-const TIME_STEP: f32 = 1.0 / 60.0;
-
-const MAX_SPEED: f32 = 3.0;
 const ALMOST_ZERO: f32 = 0.001;
-const MOVE_FORCE: f32 = 10.0;
 const START_BALL_1: f32 = 5.5;
 const START_BALL_2: f32 = 2.5;
 const START_BALL_HEIGHT: f32 = 2.0;
 const START_PLAYER_1: f32 = 6.0;
 const START_PLAYER_2: f32 = 2.0;
 const START_PLAYER_HEIGHT: f32 = 0.6;
-const POINT_LIMIT: u32 = 10;
 
-// scoring, reset ball after that number of frames
-const POINT_RESET: u64 = 180;
-const GRAVITY_AFTER: u64 = 180;
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    pub point_limit: u32,
+    // scoring, reset ball after that number of frames
+    pub point_reset_frames: u64,
+    pub gravity_after_frames: u64,
+    pub max_speed: f32,
+    pub move_force: f32,
+    // simulation frames per second, the physics time step is its inverse
+    pub frame_rate: u32,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            point_limit: 10,
+            point_reset_frames: 180,
+            gravity_after_frames: 180,
+            max_speed: 3.0,
+            move_force: 10.0,
+            frame_rate: 60,
+        }
+    }
+}
+
+// everything that changes while a board runs, enough to continue the game in a fresh GameState
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameSnapshot {
+    config: GameConfig,
+    rigid_body_set: RigidBodySet,
+    collider_set: ColliderSet,
+    island_manager: IslandManager,
+    broad_phase: BroadPhaseMultiSap,
+    narrow_phase: NarrowPhase,
+    impulse_joint_set: ImpulseJointSet,
+    multi_body_joint_set: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+    frame_counter: u64,
+    points1: u32,
+    points2: u32,
+    points_added: bool,
+    ball_for_1: bool,
+    ball_touch: bool,
+    reset_frame: u64,
+    enable_gravity_frame: u64,
+    game_over: bool,
+    // [left, right] pressed, player 1 first
+    player_input: [[bool; 2]; 2],
+}
+
+impl GameSnapshot {
+    pub fn frame(&self) -> u64 {
+        self.frame_counter
+    }
+
+    // the same value GameState::checksum gives for the state the snapshot was taken of
+    pub fn checksum(&self) -> u32 {
+        checksum(&self.rigid_body_set, (self.points1, self.points2),
+            [self.points_added, self.ball_for_1, self.ball_touch, self.game_over], self.player_input,
+            countdowns(self.frame_counter, self.reset_frame, self.enable_gravity_frame))
+    }
+}
+
+// what GameState::checksum covers, small enough for one datagram; a client's board is brought back in line with it
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BoardState {
+    // player 1, player 2, ball
+    pub bodies: [BodyState; 3],
+    pub points: (u32, u32),
+    // points added, ball for player 1, ball touched, game over
+    pub flags: [bool; 4],
+    // [left, right] pressed, player 1 first
+    pub input: [[bool; 2]; 2],
+    // frames until the post-point reset and until the serve, 0 when none is pending
+    pub reset_in: u32,
+    pub gravity_in: u32,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BodyState {
+    pub position: (f32, f32),
+    // cosine and sine, the angle would not give the same bits back
+    pub rotation: (f32, f32),
+    pub velocity: (f32, f32),
+    pub spin: f32,
+    pub force: (f32, f32),
+    pub gravity: f32,
+}
 
 pub struct GameState {
+    config: GameConfig,
     rigid_body_set: RigidBodySet,
     collider_set: ColliderSet,
     gravity: Vector<f32>,
@@ -64,6 +173,8 @@ pub struct GameState {
     reset_frame: u64,
     enable_gravity_frame: u64,
     game_over: bool,
+    // a predicting client leaves points, and the resets after them, to the server
+    scoring: bool,
     event_handler_receiver: Receiver<CollisionEvent>,
 
     player_input: HashMap<RigidBodyHandle, [bool; 2]>,
@@ -71,13 +182,22 @@ pub struct GameState {
 
 impl GameState {
     pub fn new() -> GameState {
+        Self::with_config(GameConfig::default())
+    }
+
+    pub fn with_config(config: GameConfig) -> GameState {
+        Self::with_serve(config, rand::random())
+    }
+
+    // the first serve is random otherwise, peers that simulate the same game have to agree on it
+    pub fn with_serve(config: GameConfig, ball_touch_1: bool) -> GameState {
         let (sender, receiver) = channel();
-        let ball_touch_1 = rand::random();
         let mut game_state = GameState {
+            config,
             rigid_body_set: RigidBodySet::new(),
             collider_set: ColliderSet::new(),
             gravity: vector![0.0, -9.81],
-            integration_parameters: IntegrationParameters::default(),
+            integration_parameters: IntegrationParameters { dt: 1.0 / config.frame_rate as f32, ..Default::default() },
             physics_pipeline: PhysicsPipeline::new(),
             island_manager: IslandManager::new(),
             broad_phase: DefaultBroadPhase::new(),
@@ -111,7 +231,8 @@ impl GameState {
             ball_for_1: ball_touch_1,
             ball_touch: ball_touch_1,
             reset_frame: 0,
-            enable_gravity_frame: GRAVITY_AFTER,
+            enable_gravity_frame: config.gravity_after_frames,
+            scoring: true,
             game_over: false,
             event_handler_receiver: receiver,
 
@@ -227,156 +348,341 @@ impl GameState {
         (self.points1, self.points2, self.game_over)
     }
 
+    pub fn frame(&self) -> u64 {
+        self.frame_counter
+    }
+
+    pub fn frame_rate(&self) -> u32 {
+        self.config.frame_rate
+    }
+
+    fn time_step(&self) -> f32 {
+        1.0 / self.config.frame_rate as f32
+    }
+
+    pub fn snapshot(&self) -> GameSnapshot {
+        GameSnapshot {
+            config: self.config,
+            rigid_body_set: self.rigid_body_set.clone(),
+            collider_set: self.collider_set.clone(),
+            island_manager: self.island_manager.clone(),
+            broad_phase: self.broad_phase.clone(),
+            narrow_phase: self.narrow_phase.clone(),
+            impulse_joint_set: self.impulse_joint_set.clone(),
+            multi_body_joint_set: self.multi_body_joint_set.clone(),
+            ccd_solver: self.ccd_solver.clone(),
+            frame_counter: self.frame_counter,
+            points1: self.points1,
+            points2: self.points2,
+            points_added: self.points_added,
+            ball_for_1: self.ball_for_1,
+            ball_touch: self.ball_touch,
+            reset_frame: self.reset_frame,
+            enable_gravity_frame: self.enable_gravity_frame,
+            game_over: self.game_over,
+            player_input: [self.player_input[&self.player1_handle], self.player_input[&self.player2_handle]],
+        }
+    }
+
+    // body and collider handles only depend on the insertion order in with_config(), so they stay valid
+    pub fn restore(&mut self, snapshot: &GameSnapshot) {
+        let snapshot = snapshot.clone();
+        self.config = snapshot.config;
+        self.integration_parameters.dt = self.time_step();
+        self.rigid_body_set = snapshot.rigid_body_set;
+        self.collider_set = snapshot.collider_set;
+        self.island_manager = snapshot.island_manager;
+        self.broad_phase = snapshot.broad_phase;
+        self.narrow_phase = snapshot.narrow_phase;
+        self.impulse_joint_set = snapshot.impulse_joint_set;
+        self.multi_body_joint_set = snapshot.multi_body_joint_set;
+        self.ccd_solver = snapshot.ccd_solver;
+        self.frame_counter = snapshot.frame_counter;
+        self.points1 = snapshot.points1;
+        self.points2 = snapshot.points2;
+        self.points_added = snapshot.points_added;
+        self.ball_for_1 = snapshot.ball_for_1;
+        self.ball_touch = snapshot.ball_touch;
+        self.reset_frame = snapshot.reset_frame;
+        self.enable_gravity_frame = snapshot.enable_gravity_frame;
+        self.game_over = snapshot.game_over;
+        self.player_input.insert(self.player1_handle, snapshot.player_input[0]);
+        self.player_input.insert(self.player2_handle, snapshot.player_input[1]);
+        self.time = 0.0;
+        self.last_update = Instant::now();
+        while self.event_handler_receiver.try_recv().is_ok() {}
+    }
+
+    // hash of what the next frames depend on, two boards with the same checksum at the same frame continue the same way
+    pub fn checksum(&self) -> u32 {
+        checksum(&self.rigid_body_set, (self.points1, self.points2),
+            [self.points_added, self.ball_for_1, self.ball_touch, self.game_over],
+            [self.player_input[&self.player1_handle], self.player_input[&self.player2_handle]],
+            countdowns(self.frame_counter, self.reset_frame, self.enable_gravity_frame))
+    }
+
+    // hash of what a state snapshot for that player sets on the predicting client's board, velocities in the whole
+    // mm/s the packet carries them in; the rest of the board depends on the opponent's inputs and the score
+    pub fn snapshot_checksum(&self, is_player1: bool) -> u32 {
+        let own = if is_player1 { self.player1_handle } else { self.player2_handle };
+        let mm = |v: f32| ((v * 1000.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16).to_le_bytes();
+        let mut hash = FNV_OFFSET;
+        for handle in [self.ball_handle, self.player1_handle, self.player2_handle] {
+            let position = self.rigid_body_set[handle].translation();
+            hash = fnv1a(hash, &position.x.to_bits().to_le_bytes());
+            hash = fnv1a(hash, &position.y.to_bits().to_le_bytes());
+        }
+        for handle in [self.ball_handle, own] {
+            let velocity = self.rigid_body_set[handle].linvel();
+            hash = fnv1a(hash, &mm(velocity.x));
+            hash = fnv1a(hash, &mm(velocity.y));
+        }
+        let [left, right] = self.player_input[&own];
+        fnv1a(hash, &[(self.rigid_body_set[self.ball_handle].gravity_scale() != 0.0) as u8, left as u8, right as u8])
+    }
+
+    pub fn board_state(&self) -> BoardState {
+        let body = |handle: RigidBodyHandle| {
+            let body = &self.rigid_body_set[handle];
+            let (position, rotation, velocity, force) = (body.translation(), body.rotation(), body.linvel(), body.user_force());
+            BodyState {
+                position: (position.x, position.y),
+                rotation: (rotation.re, rotation.im),
+                velocity: (velocity.x, velocity.y),
+                spin: body.angvel(),
+                force: (force.x, force.y),
+                gravity: body.gravity_scale(),
+            }
+        };
+        let (reset_in, gravity_in) = countdowns(self.frame_counter, self.reset_frame, self.enable_gravity_frame);
+        BoardState {
+            bodies: [body(self.player1_handle), body(self.player2_handle), body(self.ball_handle)],
+            points: (self.points1, self.points2),
+            flags: [self.points_added, self.ball_for_1, self.ball_touch, self.game_over],
+            input: [self.player_input[&self.player1_handle], self.player_input[&self.player2_handle]],
+            reset_in,
+            gravity_in,
+        }
+    }
+
+    // takes over everything the checksum covers, the frame and what it does not cover stay
+    pub fn set_board_state(&mut self, board: &BoardState) {
+        for (handle, state) in [self.player1_handle, self.player2_handle, self.ball_handle].into_iter().zip(board.bodies) {
+            let body = &mut self.rigid_body_set[handle];
+            body.set_translation(vector![state.position.0, state.position.1], true);
+            body.set_rotation(Rotation::from_cos_sin_unchecked(state.rotation.0, state.rotation.1), true);
+            body.set_linvel(vector![state.velocity.0, state.velocity.1], true);
+            body.set_angvel(state.spin, true);
+            body.reset_forces(true);
+            body.add_force(vector![state.force.0, state.force.1], true);
+            body.set_gravity_scale(state.gravity, true);
+        }
+        let due = |frames: u32| if frames == 0 { 0 } else { self.frame_counter + frames as u64 };
+        (self.reset_frame, self.enable_gravity_frame) = (due(board.reset_in), due(board.gravity_in));
+        (self.points1, self.points2) = board.points;
+        [self.points_added, self.ball_for_1, self.ball_touch, self.game_over] = board.flags;
+        self.player_input.insert(self.player1_handle, board.input[0]);
+        self.player_input.insert(self.player2_handle, board.input[1]);
+    }
+
+    pub fn set_scoring(&mut self, enabled: bool) {
+        self.scoring = enabled;
+    }
+
+    // velocity and whether gravity pulls it, gravity is off after a reset until the serve
+    pub fn ball_motion(&self) -> ((f32, f32), bool) {
+        let body = &self.rigid_body_set[self.ball_handle];
+        ((body.linvel().x, body.linvel().y), body.gravity_scale() != 0.0)
+    }
+
+    pub fn set_ball_motion(&mut self, velocity: (f32, f32), gravity: bool) {
+        let body = &mut self.rigid_body_set[self.ball_handle];
+        body.set_linvel(vector![velocity.0, velocity.1], true);
+        body.set_gravity_scale(if gravity { 1.0 } else { 0.0 }, true);
+    }
+
+    // velocity and [left, right] pressed
+    pub fn player_motion(&self, is_player1: bool) -> ((f32, f32), [bool; 2]) {
+        let handle = if is_player1 { self.player1_handle } else { self.player2_handle };
+        let v = self.rigid_body_set[handle].linvel();
+        ((v.x, v.y), self.player_input[&handle])
+    }
+
+    pub fn set_player_motion(&mut self, is_player1: bool, velocity: (f32, f32), held: [bool; 2]) {
+        let handle = if is_player1 { self.player1_handle } else { self.player2_handle };
+        self.rigid_body_set[handle].set_linvel(vector![velocity.0, velocity.1], true);
+        self.player_input.insert(handle, held);
+    }
+
+    // moves the bodies to positions received from the server, velocities and everything else stay
+    pub fn set_positions(&mut self, ball: (f32, f32), player1: (f32, f32), player2: (f32, f32)) {
+        for (handle, (x, y)) in [(self.ball_handle, ball), (self.player1_handle, player1), (self.player2_handle, player2)] {
+            self.rigid_body_set[handle].set_translation(vector![x, y], true);
+        }
+    }
+
+    pub fn from_snapshot(snapshot: &GameSnapshot) -> GameState {
+        let mut game_state = GameState::with_config(snapshot.config);
+        game_state.restore(snapshot);
+        game_state
+    }
+
     pub fn step(&mut self) -> bool {
         let frame_time = self.last_update.elapsed().as_secs_f32();
         // log::debug!("frame elapsed: {}", frame_time);
         self.time += frame_time;
         self.game_time += frame_time;
         let mut update_done = false;
-        while self.time - TIME_STEP > 0.0 {
-            self.frame_counter += 1;
-            self.time -= TIME_STEP;
-
-            self.control_player(self.player1_handle);
-            self.control_player(self.player2_handle);
-
-            self.physics_pipeline.step(
-                &self.gravity,
-                &self.integration_parameters,
-                &mut self.island_manager,
-                &mut self.broad_phase,
-                &mut self.narrow_phase,
-                &mut self.rigid_body_set,
-                &mut self.collider_set,
-                &mut self.impulse_joint_set,
-                &mut self.multi_body_joint_set,
-                &mut self.ccd_solver,
-                Some(&mut self.query_pipeline),
-                &self.physics_hooks,
-                &self.event_handler,
-            );
-
-            while let Ok(event) = self.event_handler_receiver.try_recv() {
-                match event {
-                    CollisionEvent::Started(handle1, handle2, _) => {
-                        // if [handle1, handle2].contains(&self.player1_collider_handle) || [handle1, handle2].contains(&self.player2_collider_handle) {
-                        //     self.ball_touch = BallTouch::None;
-                        // } else
-                        if [handle1, handle2].contains(&self.player1_collider_handle) {
-                            self.ball_touch = true;
-                            self.rigid_body_set[self.ball_handle].set_gravity_scale(1.0,true);
-                        }
-                        else if [handle1, handle2].contains(&self.player2_collider_handle) {
-                            self.ball_touch = false;
-                            self.rigid_body_set[self.ball_handle].set_gravity_scale(1.0,true);
-                        }
-                        else if [handle1, handle2].contains(&self.ground_handle) && !self.points_added {
-                            let add_point =
-                                if self.ball().0 < self.net().0 {
-                                    if self.ball_touch == false {
-                                        self.points1 += 1;
-                                        self.ball_for_1 = true;
-                                        true
-                                    }
-                                    else {
-                                        self.ball_touch = false;
-                                        false
-                                    }
-                                } else {
-                                    if self.ball_touch == true {
-                                        self.points2 += 1;
-                                        self.ball_for_1 = false;
-                                        true
-                                    }
-                                    else {
-                                        self.ball_touch = true;
-                                        false
-                                    }
-                                };
-                            if add_point {
-                                self.points_added = true;
-                                self.game_over = self.points1 >= POINT_LIMIT || self.points2 >= POINT_LIMIT;
-                                if !self.game_over {
-                                    self.reset_frame = self.frame_counter + POINT_RESET;
+        let time_step = self.time_step();
+        while self.time - time_step > 0.0 {
+            self.time -= time_step;
+            self.step_frame();
+            update_done = true;
+        }
+        self.last_update = Instant::now();
+        update_done
+    }
+
+    // advances the simulation by exactly one time step, independent of the wall clock
+    pub fn step_frame(&mut self) {
+        self.frame_counter += 1;
+
+        self.control_player(self.player1_handle);
+        self.control_player(self.player2_handle);
+
+        self.physics_pipeline.step(
+            &self.gravity,
+            &self.integration_parameters,
+            &mut self.island_manager,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.rigid_body_set,
+            &mut self.collider_set,
+            &mut self.impulse_joint_set,
+            &mut self.multi_body_joint_set,
+            &mut self.ccd_solver,
+            Some(&mut self.query_pipeline),
+            &self.physics_hooks,
+            &self.event_handler,
+        );
+
+        while let Ok(event) = self.event_handler_receiver.try_recv() {
+            match event {
+                CollisionEvent::Started(handle1, handle2, _) => {
+                    // if [handle1, handle2].contains(&self.player1_collider_handle) || [handle1, handle2].contains(&self.player2_collider_handle) {
+                    //     self.ball_touch = BallTouch::None;
+                    // } else
+                    if [handle1, handle2].contains(&self.player1_collider_handle) {
+                        self.ball_touch = true;
+                        self.rigid_body_set[self.ball_handle].set_gravity_scale(1.0,true);
+                    }
+                    else if [handle1, handle2].contains(&self.player2_collider_handle) {
+                        self.ball_touch = false;
+                        self.rigid_body_set[self.ball_handle].set_gravity_scale(1.0,true);
+                    }
+                    else if [handle1, handle2].contains(&self.ground_handle) && !self.points_added && self.scoring {
+                        let add_point =
+                            if self.ball().0 < self.net().0 {
+                                if !self.ball_touch {
+                                    self.points1 += 1;
+                                    self.ball_for_1 = true;
+                                    true
                                 }
+                                else {
+                                    self.ball_touch = false;
+                                    false
+                                }
+                            } else {
+                                if self.ball_touch {
+                                    self.points2 += 1;
+                                    self.ball_for_1 = false;
+                                    true
+                                }
+                                else {
+                                    self.ball_touch = true;
+                                    false
+                                }
+                            };
+                        if add_point {
+                            self.points_added = true;
+                            self.game_over = self.points1 >= self.config.point_limit || self.points2 >= self.config.point_limit;
+                            if !self.game_over {
+                                self.reset_frame = self.frame_counter + self.config.point_reset_frames;
                             }
                         }
                     }
-                    CollisionEvent::Stopped(_, _, _) => {}
                 }
+                CollisionEvent::Stopped(_, _, _) => {}
             }
+        }
 
-            // if contact(&self.narrow_phase, self.ball_collider_handle, self.player1_collider_handle) ||
-            //     contact(&self.narrow_phase, self.ball_collider_handle, self.player2_collider_handle) {
-            //     println!("1 {:?} {}", self.ball_touch, self.frame_counter);
-            //     self.ball_touch = BallTouch::None;
-            // }
-            //
-            // if contact(&self.narrow_phase, self.ball_collider_handle, self.ground_handle) && !self.points_added {
-            //     println!("200 {:?} {}", self.ball_touch, self.frame_counter);
-            //     let add_point =
-            //         if self.ball().0 < self.net().0 {
-            //             if self.ball_touch == BallTouch::Left {
-            //                 self.points1 += 1;
-            //                 self.ball_for_1 = true;
-            //                 true
-            //             }
-            //             else {
-            //                 self.ball_touch = BallTouch::Left;
-            //                 false
-            //             }
-            //         } else {
-            //             if self.ball_touch == BallTouch::Right {
-            //                 self.points2 += 1;
-            //                 self.ball_for_1 = false;
-            //                 true
-            //             }
-            //             else {
-            //                 self.ball_touch = BallTouch::Right;
-            //                 false
-            //             }
-            //         };
-            //     if add_point {
-            //         self.points_added = true;
-            //         self.game_over = self.points1 >= POINT_LIMIT || self.points2 >= POINT_LIMIT;
-            //         if !self.game_over {
-            //             self.reset_frame = self.frame_counter + POINT_RESET;
-            //         }
-            //     }
-            // }
-
-            if self.frame_counter == self.reset_frame {
-                self.points_added = false;
-                self.ball_touch = self.ball_for_1;
-                self.player_input.insert(self.player1_handle, [false, false]);
-                self.player_input.insert(self.player2_handle, [false, false]);
-                self.rigid_body_set[self.player1_handle].set_translation(vector![START_PLAYER_1, START_PLAYER_HEIGHT], true);
-                self.rigid_body_set[self.player2_handle].set_translation(vector![START_PLAYER_2, START_PLAYER_HEIGHT], true);
-                self.rigid_body_set[self.player1_handle].set_linvel(vector![0.0, 0.0], true);
-                self.rigid_body_set[self.player2_handle].set_linvel(vector![0.0, 0.0], true);
-                self.rigid_body_set[self.player1_handle].reset_forces(true);
-                self.rigid_body_set[self.player2_handle].reset_forces(true);
-                let ball = &mut self.rigid_body_set[self.ball_handle];
-                ball.set_gravity_scale(0.0, true);
-                let ball_x = if self.ball_for_1 {START_BALL_1} else {START_BALL_2};
-                ball.set_translation(vector![ball_x, START_BALL_HEIGHT], true);
-                ball.reset_forces(true);
-                ball.set_linvel(vector![0.0, 0.0], true);
-                ball.set_angvel(0.0, true);
-                self.enable_gravity_frame = self.frame_counter + GRAVITY_AFTER;
-            }
-            else if self.frame_counter == self.enable_gravity_frame {
-                self.rigid_body_set[self.ball_handle].set_gravity_scale(1.0,true);
-                self.rigid_body_set[self.ball_handle].apply_impulse(vector![0.0, -0.1], true);
-            }
-
-            update_done = true;
+        // if contact(&self.narrow_phase, self.ball_collider_handle, self.player1_collider_handle) ||
+        //     contact(&self.narrow_phase, self.ball_collider_handle, self.player2_collider_handle) {
+        //     println!("1 {:?} {}", self.ball_touch, self.frame_counter);
+        //     self.ball_touch = BallTouch::None;
+        // }
+        //
+        // if contact(&self.narrow_phase, self.ball_collider_handle, self.ground_handle) && !self.points_added {
+        //     println!("200 {:?} {}", self.ball_touch, self.frame_counter);
+        //     let add_point =
+        //         if self.ball().0 < self.net().0 {
+        //             if self.ball_touch == BallTouch::Left {
+        //                 self.points1 += 1;
+        //                 self.ball_for_1 = true;
+        //                 true
+        //             }
+        //             else {
+        //                 self.ball_touch = BallTouch::Left;
+        //                 false
+        //             }
+        //         } else {
+        //             if self.ball_touch == BallTouch::Right {
+        //                 self.points2 += 1;
+        //                 self.ball_for_1 = false;
+        //                 true
+        //             }
+        //             else {
+        //                 self.ball_touch = BallTouch::Right;
+        //                 false
+        //             }
+        //         };
+        //     if add_point {
+        //         self.points_added = true;
+        //         self.game_over = self.points1 >= POINT_LIMIT || self.points2 >= POINT_LIMIT;
+        //         if !self.game_over {
+        //             self.reset_frame = self.frame_counter + POINT_RESET;
+        //         }
+        //     }
+        // }
+
+        if self.frame_counter == self.reset_frame {
+            self.points_added = false;
+            self.ball_touch = self.ball_for_1;
+            self.player_input.insert(self.player1_handle, [false, false]);
+            self.player_input.insert(self.player2_handle, [false, false]);
+            self.rigid_body_set[self.player1_handle].set_translation(vector![START_PLAYER_1, START_PLAYER_HEIGHT], true);
+            self.rigid_body_set[self.player2_handle].set_translation(vector![START_PLAYER_2, START_PLAYER_HEIGHT], true);
+            self.rigid_body_set[self.player1_handle].set_linvel(vector![0.0, 0.0], true);
+            self.rigid_body_set[self.player2_handle].set_linvel(vector![0.0, 0.0], true);
+            self.rigid_body_set[self.player1_handle].reset_forces(true);
+            self.rigid_body_set[self.player2_handle].reset_forces(true);
+            let ball = &mut self.rigid_body_set[self.ball_handle];
+            ball.set_gravity_scale(0.0, true);
+            let ball_x = if self.ball_for_1 {START_BALL_1} else {START_BALL_2};
+            ball.set_translation(vector![ball_x, START_BALL_HEIGHT], true);
+            ball.reset_forces(true);
+            ball.set_linvel(vector![0.0, 0.0], true);
+            ball.set_angvel(0.0, true);
+            self.enable_gravity_frame = self.frame_counter + self.config.gravity_after_frames;
+        }
+        else if self.frame_counter == self.enable_gravity_frame {
+            self.rigid_body_set[self.ball_handle].set_gravity_scale(1.0,true);
+            self.rigid_body_set[self.ball_handle].apply_impulse(vector![0.0, -0.1], true);
         }
-        self.last_update = Instant::now();
-        update_done
     }
 
     fn control_player(&mut self, handle: RigidBodyHandle) {
+        let (max_speed, move_force) = (self.config.max_speed, self.config.move_force);
         let player_body = &mut self.rigid_body_set[handle];
         let pressing_left = self.player_input[&handle][0];
         let pressing_right = self.player_input[&handle][1];
@@ -385,14 +691,14 @@ impl GameState {
         let f = player_body.user_force().x;
 
         if pressing_right && f == 0.0 {
-            player_body.add_force(vector![MOVE_FORCE, 0.0], true);
+            player_body.add_force(vector![move_force, 0.0], true);
         }
         else if pressing_left && f == 0.0 {
-            player_body.add_force(vector![-MOVE_FORCE, 0.0], true);
+            player_body.add_force(vector![-move_force, 0.0], true);
         }
 
-        if v.x.abs() > MAX_SPEED {
-            player_body.set_linvel(vector![if v.x > 0.0 { MAX_SPEED } else { -MAX_SPEED }, v.y], true);
+        if v.x.abs() > max_speed {
+            player_body.set_linvel(vector![if v.x > 0.0 { max_speed } else { -max_speed }, v.y], true);
             player_body.reset_forces(true);
         }
         if !pressing_left && !pressing_right {
@@ -449,23 +755,121 @@ impl GameState {
     }
 }
 
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 struct MyEventHandler {
     sender: Sender<CollisionEvent>
 }
 
 impl EventHandler for MyEventHandler {
-    fn handle_collision_event(&self, bodies: &RigidBodySet, colliders: &ColliderSet, event: CollisionEvent, contact_pair: Option<&ContactPair>) {
+    fn handle_collision_event(&self, _bodies: &RigidBodySet, _colliders: &ColliderSet, event: CollisionEvent, _contact_pair: Option<&ContactPair>) {
         let _ = self.sender.send(event);
     }
 
-    fn handle_contact_force_event(&self, dt: Real, bodies: &RigidBodySet, colliders: &ColliderSet, contact_pair: &ContactPair, total_force_magnitude: Real) {
+    fn handle_contact_force_event(&self, _dt: Real, _bodies: &RigidBodySet, _colliders: &ColliderSet, _contact_pair: &ContactPair, _total_force_magnitude: Real) {
         log::error!("Force event handler not implemented");
     }
 }
 
+// frames until the reset and the serve, counted from the current frame so boards of other frame numbers compare
+fn countdowns(frame: u64, reset_frame: u64, enable_gravity_frame: u64) -> (u32, u32) {
+    let left = |due: u64| due.saturating_sub(frame).min(u32::MAX as u64) as u32;
+    (left(reset_frame), left(enable_gravity_frame))
+}
+
+const FNV_OFFSET: u32 = 0x811c9dc5;
+
+// FNV-1a, unlike the std hasher it is the same in every build
+fn fnv1a(hash: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(hash, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x01000193))
+}
+
+// over the bodies in handle order and the game flags
+fn checksum(bodies: &RigidBodySet, points: (u32, u32), flags: [bool; 4], input: [[bool; 2]; 2], countdowns: (u32, u32)) -> u32 {
+    let mut hash = FNV_OFFSET;
+    let mut write = |bytes: &[u8]| hash = fnv1a(hash, bytes);
+    for (_, body) in bodies.iter() {
+        let (position, velocity, force) = (body.translation(), body.linvel(), body.user_force());
+        for value in [position.x, position.y, body.rotation().angle(), velocity.x, velocity.y, body.angvel(), force.x, force.y, body.gravity_scale()] {
+            write(&value.to_bits().to_le_bytes());
+        }
+    }
+    write(&points.0.to_le_bytes());
+    write(&points.1.to_le_bytes());
+    write(&flags.map(|f| f as u8));
+    write(&input.as_flattened().iter().map(|i| *i as u8).collect::<Vec<u8>>());
+    write(&countdowns.0.to_le_bytes());
+    write(&countdowns.1.to_le_bytes());
+    hash
+}
+
 fn contact(narrow_phase: &NarrowPhase, handle1: ColliderHandle, handle2: ColliderHandle) -> bool {
     if let Some(pair) = narrow_phase.contact_pair(handle1, handle2) {
         return pair.has_any_active_contact;
     }
     false
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{GameSnapshot, GameState};
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut original = GameState::new();
+        original.add_force(true, true);
+        for _ in 0..200 {
+            original.step_frame();
+        }
+        original.apply_impulse(false, false);
+
+        let bytes = bincode::serialize(&original.snapshot()).unwrap();
+        let snapshot: GameSnapshot = bincode::deserialize(&bytes).unwrap();
+        let mut restored = GameState::from_snapshot(&snapshot);
+        assert_eq!(restored.frame(), original.frame());
+
+        for _ in 0..300 {
+            original.step_frame();
+            restored.step_frame();
+        }
+        assert_eq!(restored.players(), original.players());
+        assert_eq!(restored.ball(), original.ball());
+        assert_eq!(restored.points(), original.points());
+        assert_eq!(restored.checksum(), original.checksum());
+        assert_eq!(restored.snapshot().checksum(), original.checksum());
+
+        original.add_force(false, false);
+        assert_ne!(restored.checksum(), original.checksum());
+        restored.step_frame();
+        assert_ne!(restored.checksum(), original.checksum());
+        // the board state alone gives the same checksum on a board of another frame
+        let mut fresh = GameState::new();
+        fresh.set_board_state(&original.board_state());
+        assert_eq!(fresh.checksum(), original.checksum());
+    }
+
+    #[test]
+    fn test_board_state_keeps_serve_timing() {
+        // taken before the serve, gravity is still off and due some frames later
+        let mut original = GameState::new();
+        for _ in 0..10 {
+            original.step_frame();
+        }
+        let mut other = GameState::new();
+        for _ in 0..3 {
+            other.step_frame();
+        }
+        other.set_board_state(&original.board_state());
+        assert_eq!(other.checksum(), original.checksum());
+        for _ in 0..300 {
+            original.step_frame();
+            other.step_frame();
+        }
+        assert_eq!(other.ball(), original.ball());
+        assert_eq!(other.checksum(), original.checksum());
+    }
+}
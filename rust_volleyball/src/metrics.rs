@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+pub static METRICS: Metrics = Metrics::new();
+
+// upper bounds in seconds, a board step is normally well below a millisecond
+const STEP_BUCKETS: [f64; 10] = [0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.1];
+// deviation of the time between two snapshots from the send interval, one scheduler tick is the usual worst case
+const JITTER_BUCKETS: [f64; 10] = [0.00001, 0.0001, 0.0005, 0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1];
+// round trips to the players, from a LAN to a bad mobile connection
+const RTT_BUCKETS: [f64; 10] = [0.005, 0.01, 0.02, 0.05, 0.075, 0.1, 0.15, 0.25, 0.5, 1.0];
+
+pub struct Metrics {
+    pub tcp_sessions: AtomicI64,
+    pub ws_sessions: AtomicI64,
+    pub quic_sessions: AtomicI64,
+    pub boards: AtomicI64,
+    pub lobby_players: AtomicI64,
+    pub udp_packets_in: AtomicU64,
+    pub udp_packets_out: AtomicU64,
+    pub logic_backlog: AtomicI64,
+    pub frames_skipped: AtomicU64,
+    pub frames_rewound: AtomicU64,
+    // (transport, kind) -> count, errors are rare enough for a lock
+    parse_errors: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+    // reason -> count, only ever incremented under a flood or a stuck game logic
+    dropped: Mutex<BTreeMap<&'static str, u64>>,
+    step_duration: Histogram,
+    send_jitter: Histogram,
+    send_jitter_max_nanos: AtomicU64,
+    rtt: Histogram,
+}
+
+struct Histogram {
+    bounds: &'static [f64; 10],
+    buckets: [AtomicU64; 10],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    const fn new(bounds: &'static [f64; 10]) -> Histogram {
+        Histogram {
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; 10],
+            count: AtomicU64::new(0),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(i) = self.bounds.iter().position(|&bound| seconds <= bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {}", self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9);
+        let _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+impl Metrics {
+    const fn new() -> Metrics {
+        Metrics {
+            tcp_sessions: AtomicI64::new(0),
+            ws_sessions: AtomicI64::new(0),
+            quic_sessions: AtomicI64::new(0),
+            boards: AtomicI64::new(0),
+            lobby_players: AtomicI64::new(0),
+            udp_packets_in: AtomicU64::new(0),
+            udp_packets_out: AtomicU64::new(0),
+            logic_backlog: AtomicI64::new(0),
+            frames_skipped: AtomicU64::new(0),
+            frames_rewound: AtomicU64::new(0),
+            parse_errors: Mutex::new(BTreeMap::new()),
+            dropped: Mutex::new(BTreeMap::new()),
+            step_duration: Histogram::new(&STEP_BUCKETS),
+            send_jitter: Histogram::new(&JITTER_BUCKETS),
+            send_jitter_max_nanos: AtomicU64::new(0),
+            rtt: Histogram::new(&RTT_BUCKETS),
+        }
+    }
+
+    pub fn parse_error(&self, transport: &'static str, kind: &'static str) {
+        match self.parse_errors.lock() {
+            Ok(mut errors) => *errors.entry((transport, kind)).or_default() += 1,
+            Err(e) => log::error!("Parse error counter poisoned, {e}"),
+        }
+    }
+
+    // a packet that was received but not handed on
+    pub fn dropped(&self, reason: &'static str) {
+        match self.dropped.lock() {
+            Ok(mut dropped) => *dropped.entry(reason).or_default() += 1,
+            Err(e) => log::error!("Drop counter poisoned, {e}"),
+        }
+    }
+
+    pub fn observe_step(&self, duration: Duration) {
+        self.step_duration.observe(duration);
+    }
+
+    pub fn observe_send_jitter(&self, jitter: Duration) {
+        self.send_jitter.observe(jitter);
+        self.send_jitter_max_nanos.fetch_max(jitter.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn observe_rtt(&self, rtt: Duration) {
+        self.rtt.observe(rtt);
+    }
+
+    // (mean, max) since the start
+    pub fn send_jitter(&self) -> (Duration, Duration) {
+        let count = self.send_jitter.count.load(Ordering::Relaxed);
+        let mean = self.send_jitter.sum_nanos.load(Ordering::Relaxed).checked_div(count).unwrap_or(0);
+        (Duration::from_nanos(mean), Duration::from_nanos(self.send_jitter_max_nanos.load(Ordering::Relaxed)))
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        gauge(&mut out, "volleyball_tcp_sessions", "Connected TCP sessions", self.tcp_sessions.load(Ordering::Relaxed));
+        gauge(&mut out, "volleyball_ws_sessions", "Connected WebSocket sessions", self.ws_sessions.load(Ordering::Relaxed));
+        gauge(&mut out, "volleyball_quic_sessions", "Connected QUIC sessions", self.quic_sessions.load(Ordering::Relaxed));
+        gauge(&mut out, "volleyball_boards", "Active boards", self.boards.load(Ordering::Relaxed));
+        gauge(&mut out, "volleyball_lobby_players", "Players waiting in the lobby", self.lobby_players.load(Ordering::Relaxed));
+        gauge(&mut out, "volleyball_logic_queue_length", "Messages waiting for the game logic thread", self.logic_backlog.load(Ordering::Relaxed));
+        counter(&mut out, "volleyball_udp_packets_received_total", "UDP packets received", self.udp_packets_in.load(Ordering::Relaxed));
+        counter(&mut out, "volleyball_udp_packets_sent_total", "UDP packets sent", self.udp_packets_out.load(Ordering::Relaxed));
+        counter(&mut out, "volleyball_frames_skipped_total", "Frames dropped by boards that fell too far behind", self.frames_skipped.load(Ordering::Relaxed));
+        counter(&mut out, "volleyball_frames_rewound_total", "Frames simulated again to apply late inputs at the frame the player saw", self.frames_rewound.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP volleyball_parse_errors_total Packets that could not be parsed or were not expected");
+        let _ = writeln!(out, "# TYPE volleyball_parse_errors_total counter");
+        if let Ok(errors) = self.parse_errors.lock() {
+            for ((transport, kind), count) in errors.iter() {
+                let _ = writeln!(out, "volleyball_parse_errors_total{{transport=\"{transport}\",kind=\"{kind}\"}} {count}");
+            }
+        }
+
+        let _ = writeln!(out, "# HELP volleyball_dropped_packets_total Packets dropped by the block list, the rate limits or full queues");
+        let _ = writeln!(out, "# TYPE volleyball_dropped_packets_total counter");
+        if let Ok(dropped) = self.dropped.lock() {
+            for (reason, count) in dropped.iter() {
+                let _ = writeln!(out, "volleyball_dropped_packets_total{{reason=\"{reason}\"}} {count}");
+            }
+        }
+
+        self.step_duration.render(&mut out, "volleyball_board_step_seconds", "Time spent in a single board step");
+        self.send_jitter.render(&mut out, "volleyball_send_jitter_seconds", "Deviation of the time between two snapshots of a board from the send interval");
+        gauge(&mut out, "volleyball_send_jitter_max_seconds", "Largest snapshot jitter seen", self.send_jitter().1.as_secs_f64());
+        self.rtt.render(&mut out, "volleyball_rtt_seconds", "Round trips of server pings to the players");
+        out
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}");
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}");
+}
+
+pub fn start(listener: std::net::TcpListener) {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .unwrap()
+        .block_on(async { run(listener).await });
+    log::error!("Metrics endpoint stopped");
+}
+
+async fn run(listener: std::net::TcpListener) {
+    listener.set_nonblocking(true).expect("Cannot set metrics listener to non-blocking");
+    let listener = tokio::net::TcpListener::from_std(listener).expect("Cannot use metrics listener");
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream).await {
+                        log::warn!("Metrics request from {addr} failed, {e}");
+                    }
+                });
+            }
+            Err(e) => log::error!("Could not accept metrics connection: {e}"),
+        }
+    }
+}
+
+async fn handle_connection(mut stream: tokio::net::TcpStream) -> std::io::Result<()> {
+    // only the request line matters, scrapers send small requests
+    let mut buffer = vec![0; 2048];
+    let mut len = 0;
+    while !buffer[..len].windows(4).any(|w| w == b"\r\n\r\n") && len < buffer.len() {
+        match stream.read(&mut buffer[len..]).await? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    let request = String::from_utf8_lossy(&buffer[..len]);
+    let response = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => {
+            let body = METRICS.render();
+            format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::spawn;
+    use std::time::Duration;
+    use crate::metrics::{start, METRICS};
+
+    fn scrape(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_scrape() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        spawn(move || start(listener));
+
+        METRICS.parse_error("udp", "malformed");
+        METRICS.dropped("rate_limited");
+        METRICS.observe_step(Duration::from_micros(300));
+        METRICS.observe_send_jitter(Duration::from_micros(1500));
+        METRICS.observe_rtt(Duration::from_millis(40));
+        let response = scrape(addr, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("# TYPE volleyball_tcp_sessions gauge"));
+        assert!(response.contains("volleyball_udp_packets_received_total "));
+        assert!(response.contains("volleyball_parse_errors_total{transport=\"udp\",kind=\"malformed\"} "));
+        assert!(response.contains("volleyball_dropped_packets_total{reason=\"rate_limited\"} "));
+        assert!(response.contains("volleyball_board_step_seconds_bucket{le=\"0.0005\"} "));
+        assert!(response.contains("volleyball_board_step_seconds_bucket{le=\"+Inf\"} "));
+        assert!(response.contains("volleyball_logic_queue_length "));
+        assert!(response.contains("volleyball_send_jitter_seconds_bucket{le=\"0.002\"} "));
+        assert!(response.contains("volleyball_send_jitter_max_seconds "));
+        assert!(response.contains("volleyball_rtt_seconds_bucket{le=\"0.05\"} "));
+
+        assert!(scrape(addr, "/").starts_with("HTTP/1.1 404"));
+    }
+}
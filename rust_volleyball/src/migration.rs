@@ -0,0 +1,50 @@
+use std::sync::OnceLock;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+
+// game packets are only taken from the address a player registered with its game request; a player whose NAT mapping
+// changed proves with a key it got over its session (TCP, or the Welcome of a UDP-only session) that the new address
+// is its own, and the server moves the player there
+
+// keys are derived from it, nothing has to be stored per player; a restarted server gives out new keys
+static SECRET: OnceLock<[u8; 32]> = OnceLock::new();
+
+pub fn key(player_id: u64) -> u64 {
+    let secret = SECRET.get_or_init(|| rand::rng().random());
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+    mac.update(&player_id.to_le_bytes());
+    u64::from_le_bytes(mac.finalize().into_bytes()[..8].try_into().unwrap())
+}
+
+// what the client puts in PacketMsg::Migrate
+pub fn tag(key: u64, player_id: u64, counter: u64) -> u64 {
+    u64::from_le_bytes(mac(key, player_id, counter).finalize().into_bytes()[..8].try_into().unwrap())
+}
+
+// the counter is checked by the caller, it has to be above the one of the last migration
+pub fn verify(player_id: u64, counter: u64, tag: u64) -> bool {
+    mac(key(player_id), player_id, counter).verify_truncated_left(&tag.to_le_bytes()).is_ok()
+}
+
+fn mac(key: u64, player_id: u64, counter: u64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key.to_le_bytes()).unwrap();
+    mac.update(&player_id.to_le_bytes());
+    mac.update(&counter.to_le_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod test {
+    use crate::migration::{key, tag, verify};
+
+    #[test]
+    fn test_migration_tag() {
+        let key = key(7);
+        assert_ne!(key, crate::migration::key(8));
+        assert!(verify(7, 1, tag(key, 7, 1)));
+        assert!(!verify(7, 2, tag(key, 7, 1)));
+        assert!(!verify(8, 1, tag(key, 7, 1)));
+        assert!(!verify(7, 1, tag(key ^ 1, 7, 1)));
+    }
+}
@@ -0,0 +1,283 @@
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde::Deserialize;
+use crate::transport::{DatagramSocket, DelayQueue};
+
+// how long a reordered datagram is held back, enough for a few 60 Hz snapshots to overtake it
+const REORDER_HOLD: Duration = Duration::from_millis(50);
+// pause of the reader thread after a failed receive, doubled while the failures go on
+const RECV_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_RECV_BACKOFF: Duration = Duration::from_secs(1);
+
+// bad network conditions applied to every datagram, in both directions
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetConditions {
+    pub latency_ms: u64,
+    // extra random delay on top of the latency, up to that much
+    pub jitter_ms: u64,
+    // probabilities between 0 and 1
+    pub loss: f64,
+    pub duplicate: f64,
+    pub reorder: f64,
+    pub seed: u64,
+}
+
+impl NetConditions {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, p) in [("loss", self.loss), ("duplicate", self.duplicate), ("reorder", self.reorder)] {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format!("netsim {name} must be between 0 and 1, got {p}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for NetConditions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "latency_ms={},jitter_ms={},loss={},duplicate={},reorder={},seed={}",
+            self.latency_ms, self.jitter_ms, self.loss, self.duplicate, self.reorder, self.seed)
+    }
+}
+
+// "latency_ms=80,jitter_ms=30,loss=0.05", keys left out stay at zero
+impl FromStr for NetConditions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut conditions = NetConditions::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').ok_or(format!("expected key=value, got '{pair}'"))?;
+            let invalid = |e: &dyn Display| format!("invalid value '{value}' for {key}: {e}");
+            match key {
+                "latency_ms" => conditions.latency_ms = value.parse().map_err(|e| invalid(&e))?,
+                "jitter_ms" => conditions.jitter_ms = value.parse().map_err(|e| invalid(&e))?,
+                "loss" => conditions.loss = value.parse().map_err(|e| invalid(&e))?,
+                "duplicate" => conditions.duplicate = value.parse().map_err(|e| invalid(&e))?,
+                "reorder" => conditions.reorder = value.parse().map_err(|e| invalid(&e))?,
+                "seed" => conditions.seed = value.parse().map_err(|e| invalid(&e))?,
+                _ => return Err(format!("unknown netsim key '{key}'")),
+            }
+        }
+        conditions.validate()?;
+        Ok(conditions)
+    }
+}
+
+// decides what happens to each datagram, shared by both directions so one seed gives one repeatable pattern
+struct Shaper {
+    conditions: NetConditions,
+    rng: StdRng,
+}
+
+impl Shaper {
+    // delivery delays, empty when the datagram is lost, two entries when it is duplicated
+    fn delays(&mut self) -> Vec<Duration> {
+        if self.rng.random_bool(self.conditions.loss) {
+            return vec![];
+        }
+        let copies = if self.rng.random_bool(self.conditions.duplicate) { 2 } else { 1 };
+        (0..copies).map(|_| {
+            let mut delay = Duration::from_millis(self.conditions.latency_ms + self.rng.random_range(0..=self.conditions.jitter_ms));
+            if self.rng.random_bool(self.conditions.reorder) {
+                delay += REORDER_HOLD;
+            }
+            delay
+        }).collect()
+    }
+}
+
+struct Delayed {
+    deliver_at: Instant,
+    addr: SocketAddr,
+    data: Vec<u8>,
+}
+
+type Incoming = Arc<Mutex<Receiver<(Vec<u8>, SocketAddr)>>>;
+
+// hands datagrams to `deliver` once their time has come, on its own thread
+fn delay_line(deliver: impl Fn(&[u8], SocketAddr) + Send + 'static) -> Sender<Delayed> {
+    let (sender, receiver) = channel::<Delayed>();
+    spawn(move || {
+        let mut pending = DelayQueue::default();
+        loop {
+            let now = Instant::now();
+            while let Some((addr, data)) = pending.pop_due(now) {
+                deliver(&data, addr);
+            }
+            let received = match pending.next_due() {
+                Some(deliver_at) => receiver.recv_timeout(deliver_at.saturating_duration_since(now)),
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(d) => pending.push(d.deliver_at, d.addr, d.data),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    });
+    sender
+}
+
+// wraps the game socket, incoming datagrams are shaped by a reader thread and outgoing ones by a sender thread
+pub struct ShapedSocket<S: DatagramSocket> {
+    inner: S,
+    shaper: Arc<Mutex<Shaper>>,
+    outgoing: Sender<Delayed>,
+    incoming: Incoming,
+}
+
+impl<S: DatagramSocket> ShapedSocket<S> {
+    pub fn new(inner: S, conditions: NetConditions) -> std::io::Result<ShapedSocket<S>> {
+        let shaper = Arc::new(Mutex::new(Shaper { conditions, rng: StdRng::seed_from_u64(conditions.seed) }));
+
+        let out_socket = inner.try_clone()?;
+        let outgoing = delay_line(move |data, addr| {
+            if let Err(e) = out_socket.send_to(data, addr) {
+                log::warn!("Network simulator cannot send, {e}");
+            }
+        });
+
+        let (incoming_sender, incoming) = channel();
+        let delayed_incoming = delay_line(move |data, addr| {
+            let _ = incoming_sender.send((data.to_vec(), addr));
+        });
+        let in_socket = inner.try_clone()?;
+        let in_shaper = shaper.clone();
+        spawn(move || {
+            let mut buf = [0; 1500];
+            let mut backoff = RECV_BACKOFF;
+            loop {
+                match in_socket.recv_from(&mut buf) {
+                    Ok((len, addr)) => {
+                        backoff = RECV_BACKOFF;
+                        let now = Instant::now();
+                        for delay in in_shaper.lock().unwrap().delays() {
+                            if delayed_incoming.send(Delayed { deliver_at: now + delay, addr, data: buf[..len].to_vec() }).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    // a read timeout of the socket
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                    // the error may persist, so it is not retried at full speed
+                    Err(e) => {
+                        log::error!("Network simulator cannot receive, {e}");
+                        sleep(backoff);
+                        backoff = (backoff * 2).min(MAX_RECV_BACKOFF);
+                    }
+                }
+            }
+        });
+
+        Ok(ShapedSocket { inner, shaper, outgoing, incoming: Arc::new(Mutex::new(incoming)) })
+    }
+}
+
+impl<S: DatagramSocket> DatagramSocket for ShapedSocket<S> {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+        let now = Instant::now();
+        for delay in self.shaper.lock().unwrap().delays() {
+            let _ = self.outgoing.send(Delayed { deliver_at: now + delay, addr, data: buf.to_vec() });
+        }
+        Ok(buf.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        let (data, addr) = self.incoming.lock().unwrap().recv()
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::NotConnected, "network simulator stopped"))?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, addr))
+    }
+
+    fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(ShapedSocket {
+            inner: self.inner.try_clone()?,
+            shaper: self.shaper.clone(),
+            outgoing: self.outgoing.clone(),
+            incoming: self.incoming.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use crate::netsim::{NetConditions, ShapedSocket};
+    use crate::transport::{DatagramSocket, MemoryNetwork};
+
+    #[test]
+    fn test_parse_conditions() {
+        let conditions: NetConditions = "latency_ms=80, jitter_ms=30,loss=0.05,seed=3".parse().unwrap();
+        assert_eq!(conditions, NetConditions { latency_ms: 80, jitter_ms: 30, loss: 0.05, seed: 3, ..Default::default() });
+        assert_eq!(conditions.to_string().parse::<NetConditions>(), Ok(conditions));
+        assert!("loss=2".parse::<NetConditions>().is_err());
+        assert!("latency=80".parse::<NetConditions>().is_err());
+        assert!("latency_ms".parse::<NetConditions>().is_err());
+    }
+
+    #[test]
+    fn test_shaped_socket() {
+        let network = MemoryNetwork::new(1);
+        let client = network.bind(network.next_addr()).unwrap();
+        let conditions = NetConditions { latency_ms: 20, jitter_ms: 10, loss: 0.2, duplicate: 0.2, reorder: 0.1, seed: 5 };
+        let server = ShapedSocket::new(network.bind(network.next_addr()).unwrap(), conditions).unwrap();
+        let server_addr = server.inner.local_addr();
+
+        let sent = Instant::now();
+        server.send_to(&[0], client.local_addr()).unwrap();
+        let mut buf = [0; 8];
+        while client.recv_timeout(&mut buf, Duration::from_millis(200)).unwrap().is_some() {}
+
+        // outgoing, from the shaped server to the client
+        let mut received = vec![];
+        for i in 0..200u8 {
+            server.send_to(&[i], client.local_addr()).unwrap();
+        }
+        while let Some((1, _)) = client.recv_timeout(&mut buf, Duration::from_millis(200)).unwrap() {
+            received.push(buf[0]);
+        }
+        let mut unique = received.clone();
+        unique.sort();
+        unique.dedup();
+        assert!((130..=180).contains(&unique.len()), "{} of 200 datagrams arrived", unique.len());
+        assert!(received.len() > unique.len(), "nothing was duplicated");
+        assert!(received.windows(2).any(|w| w[0] > w[1]), "nothing was reordered");
+        assert!(sent.elapsed() >= Duration::from_millis(20));
+
+        // incoming, from the client to the shaped server
+        for i in 0..200u8 {
+            client.send_to(&[i], server_addr).unwrap();
+        }
+        let server_recv = server.try_clone().unwrap();
+        let counter = std::thread::spawn(move || {
+            let mut buf = [0; 8];
+            let mut count = 0;
+            while server_recv.recv_from(&mut buf).is_ok() {
+                count += 1;
+                if buf[0] == u8::MAX {
+                    break;
+                }
+            }
+            count
+        });
+        // a marker that is sent until it gets through, everything before it had time to arrive
+        std::thread::sleep(Duration::from_millis(300));
+        while !counter.is_finished() {
+            client.send_to(&[u8::MAX], server_addr).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let count = counter.join().unwrap();
+        assert!((130..=260).contains(&count), "{count} datagrams arrived");
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::{BTreeMap, VecDeque};
+use bincode::Options;
+use crate::{GameConfig, GameSnapshot, GameState};
+use crate::prediction::apply;
+use crate::udp_server::{Key, ParseError};
+
+// frames a peer may run ahead of the last input it got from the other one, it waits after that
+pub const MAX_PREDICTION: u64 = 8;
+// one datagram carries every input the other peer has not confirmed yet, waiting bounds how many those are
+const MAX_INPUTS: usize = 64;
+// confirmed frames whose checksums are compared with the other peer
+const CHECKSUM_INTERVAL: u64 = 30;
+// inputs are kept that long after they were confirmed, a resync replays them on top of the other peer's snapshot
+const RESYNC_WINDOW: u64 = 120;
+const INPUTS_HEADER: [u8; 4] = [12, 64, 13, 50];
+const RESYNC_HEADER: [u8; 4] = [12, 64, 13, 51];
+const INPUTS_OFFSET: usize = 34;
+// a board is some kilobytes, it goes in parts that fit a datagram on any path: header, frame, part, parts, bytes
+const RESYNC_OFFSET: usize = 14;
+const RESYNC_PART: usize = 1024;
+// a board claiming more is not decoded, whatever the other peer sends
+const MAX_SNAPSHOT_BYTES: usize = 64 * 1024;
+
+// key events of one player in one frame, as bits: left pressed, left released, right pressed, right released, jump
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FrameInput(u8);
+
+impl FrameInput {
+    const KEYS: [Key; 5] = [Key::Left(true), Key::Left(false), Key::Right(true), Key::Right(false), Key::Jump];
+
+    pub fn add(&mut self, key: Key) {
+        if let Some(i) = Self::KEYS.iter().position(|k| *k == key) {
+            self.0 |= 1 << i;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    fn apply(self, state: &mut GameState, is_player1: bool) {
+        for (i, key) in Self::KEYS.into_iter().enumerate() {
+            if self.0 & 1 << i != 0 {
+                apply(state, key, is_player1);
+            }
+        }
+    }
+}
+
+// GGPO style rollback between two peers without a server: both simulate the whole board, the other peer is predicted to
+// press nothing new, and when its real input for an earlier frame differs the board goes back and simulates again
+pub struct RollbackSession {
+    state: GameState,
+    local_is_player1: bool,
+    // local inputs are scheduled that many frames ahead, it hides a part of the latency from the other peer
+    input_delay: u64,
+    pending: FrameInput,
+    local_inputs: BTreeMap<u64, FrameInput>,
+    remote_inputs: BTreeMap<u64, FrameInput>,
+    // the remote inputs of all frames below that are known
+    remote_confirmed: u64,
+    // the other peer has all local inputs below that
+    peer_ack: u64,
+    // taken before the inputs of each frame from the first one that may still be rolled back
+    snapshots: VecDeque<GameSnapshot>,
+    rolled_back: u64,
+    // checksums of the newest confirmed frames, and the newest one from the other peer not compared yet
+    checksums: VecDeque<(u64, u32)>,
+    remote_checksum: Option<(u64, u32)>,
+    // set while the boards differ, until player 2 took over the board of player 1
+    desync: Option<u64>,
+    desyncs: u64,
+    resync_requested: bool,
+    last_resync_sent: Option<u64>,
+    // the parts of the newest board from the other peer received so far, by its frame
+    resync_parts: Option<(u64, Vec<Option<Vec<u8>>>)>,
+}
+
+impl RollbackSession {
+    // player 1 serves first, both peers need the same config
+    pub fn new(config: GameConfig, local_is_player1: bool, input_delay: u64) -> RollbackSession {
+        RollbackSession {
+            state: GameState::with_serve(config, true),
+            local_is_player1,
+            input_delay,
+            pending: FrameInput::default(),
+            local_inputs: BTreeMap::new(),
+            remote_inputs: BTreeMap::new(),
+            remote_confirmed: 0,
+            peer_ack: 0,
+            snapshots: VecDeque::new(),
+            rolled_back: 0,
+            checksums: VecDeque::new(),
+            remote_checksum: None,
+            desync: None,
+            desyncs: 0,
+            resync_requested: false,
+            last_resync_sent: None,
+            resync_parts: None,
+        }
+    }
+
+    // collected until the next frame is simulated
+    pub fn add_local_input(&mut self, key: Key) {
+        self.pending.add(key);
+    }
+
+    // false while waiting for the other peer, nothing is simulated then
+    pub fn advance_frame(&mut self) -> bool {
+        let frame = self.state.frame();
+        if frame >= self.remote_confirmed + MAX_PREDICTION {
+            return false;
+        }
+        self.local_inputs.insert(frame + self.input_delay, std::mem::take(&mut self.pending));
+        self.simulate_frame();
+        self.trim();
+        true
+    }
+
+    // the datagram for the other peer, sent every frame, a lost one is covered by the next
+    pub fn encode(&self) -> Vec<u8> {
+        let start = self.peer_ack;
+        // the frames before the delay have no local input, the next one is not decided yet
+        let inputs: Vec<u8> = (start..self.state.frame() + self.input_delay)
+            .take(MAX_INPUTS)
+            .map(|f| self.local_inputs.get(&f).copied().unwrap_or_default().0)
+            .collect();
+        let (checked_frame, checksum) = self.checksums.back().copied().unwrap_or_default();
+        let mut packet = Vec::with_capacity(INPUTS_OFFSET + inputs.len());
+        packet.extend_from_slice(&INPUTS_HEADER);
+        packet.extend_from_slice(&self.remote_confirmed.to_le_bytes());
+        packet.extend_from_slice(&start.to_le_bytes());
+        packet.extend_from_slice(&checked_frame.to_le_bytes());
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        // player 2 asks for the board of player 1 until it gets it
+        packet.push((self.desync.is_some() && !self.local_is_player1) as u8);
+        packet.push(inputs.len() as u8);
+        packet.extend_from_slice(&inputs);
+        packet
+    }
+
+    // player 1's confirmed board for a peer that asked for it, at most twice a second, in as many datagrams as it takes
+    pub fn resync_packets(&mut self) -> Vec<Vec<u8>> {
+        let frame = self.state.frame();
+        if !self.resync_requested || self.last_resync_sent.is_some_and(|sent| frame < sent + CHECKSUM_INTERVAL) {
+            return vec![];
+        }
+        self.resync_requested = false;
+        self.last_resync_sent = Some(frame);
+        let confirmed = self.remote_confirmed.min(frame);
+        let snapshot = match self.snapshots.front() {
+            Some(snapshot) if snapshot.frame() == confirmed => snapshot.clone(),
+            _ => self.state.snapshot(),
+        };
+        let bytes = match snapshot_options().serialize(&snapshot) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                log::error!("Cannot serialize the board of frame {}, {e}", snapshot.frame());
+                return vec![];
+            }
+        };
+        let parts = bytes.len().div_ceil(RESYNC_PART);
+        log::info!("Sending the board of frame {} in {parts} parts to resync the other peer", snapshot.frame());
+        bytes.chunks(RESYNC_PART).enumerate().map(|(i, part)| {
+            let mut packet = RESYNC_HEADER.to_vec();
+            packet.extend_from_slice(&snapshot.frame().to_le_bytes());
+            packet.extend_from_slice(&[i as u8, parts as u8]);
+            packet.extend_from_slice(part);
+            packet
+        }).collect()
+    }
+
+    // takes the inputs of the other peer, rolls back at once if one of them was predicted wrong
+    pub fn receive(&mut self, data: &[u8]) -> Result<(), ParseError> {
+        if data.len() > 4 && data[..4] == RESYNC_HEADER {
+            return self.receive_resync_part(data);
+        }
+        if data.len() < INPUTS_OFFSET || data.len() != INPUTS_OFFSET + data[INPUTS_OFFSET - 1] as usize {
+            return Err(ParseError::WrongLength(data.len()));
+        }
+        if data[..4] != INPUTS_HEADER {
+            return Err(ParseError::BadMagic);
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(data[i..i + 8].try_into().unwrap());
+        self.peer_ack = self.peer_ack.max(u64_at(4));
+        let start = u64_at(12);
+        let checked_frame = u64_at(20);
+        if checked_frame > 0 && self.remote_checksum.is_none_or(|(frame, _)| checked_frame > frame) {
+            self.remote_checksum = Some((checked_frame, u32::from_le_bytes(data[28..32].try_into().unwrap())));
+        }
+        if data[32] & 1 != 0 && self.local_is_player1 {
+            self.resync_requested = true;
+        }
+        let current = self.state.frame();
+        let mut rollback: Option<u64> = None;
+        for (frame, &input) in (start..).zip(&data[INPUTS_OFFSET..]) {
+            if frame < self.remote_confirmed || start > self.remote_confirmed {
+                continue;
+            }
+            let input = FrameInput(input);
+            // the prediction for a simulated frame was no new key
+            if frame < current && !input.is_empty() && rollback.is_none() {
+                rollback = Some(frame);
+            }
+            self.remote_inputs.insert(frame, input);
+            self.remote_confirmed = frame + 1;
+        }
+        if let Some(frame) = rollback {
+            self.rollback(frame, current);
+        }
+        self.trim();
+        Ok(())
+    }
+
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.state.frame()
+    }
+
+    // frames simulated again because of late remote inputs
+    pub fn rolled_back(&self) -> u64 {
+        self.rolled_back
+    }
+
+    // every input of both players up to the current frame is known, the state will not change any more
+    pub fn is_confirmed(&self) -> bool {
+        self.remote_confirmed >= self.state.frame()
+    }
+
+    // the frame the boards were found to differ at, until they agree again
+    pub fn desync(&self) -> Option<u64> {
+        self.desync
+    }
+
+    pub fn desyncs(&self) -> u64 {
+        self.desyncs
+    }
+
+    // the board is decoded once all its parts are there, the parts of an older board are dropped
+    fn receive_resync_part(&mut self, data: &[u8]) -> Result<(), ParseError> {
+        if data.len() <= RESYNC_OFFSET || data.len() > RESYNC_OFFSET + RESYNC_PART {
+            return Err(ParseError::WrongLength(data.len()));
+        }
+        let frame = u64::from_le_bytes(data[4..12].try_into().unwrap());
+        let (index, count) = (data[12] as usize, data[13] as usize);
+        if index >= count || count * RESYNC_PART > MAX_SNAPSHOT_BYTES {
+            return Err(ParseError::InvalidField("resync part"));
+        }
+        let parts = match &mut self.resync_parts {
+            Some((collecting, _)) if frame < *collecting => return Ok(()),
+            Some((collecting, parts)) if frame == *collecting && parts.len() == count => parts,
+            parts => &mut parts.insert((frame, vec![None; count])).1,
+        };
+        parts[index] = Some(data[RESYNC_OFFSET..].to_vec());
+        if parts.iter().any(Option::is_none) {
+            return Ok(());
+        }
+        let bytes: Vec<u8> = parts.iter().flatten().flatten().copied().collect();
+        self.resync_parts = None;
+        let snapshot: GameSnapshot = snapshot_options().deserialize(&bytes).map_err(|_| ParseError::InvalidField("snapshot"))?;
+        self.resync(&snapshot);
+        Ok(())
+    }
+
+    // player 1's board replaces the own one, the newer inputs are simulated again on top of it
+    fn resync(&mut self, snapshot: &GameSnapshot) {
+        let (frame, current) = (snapshot.frame(), self.state.frame());
+        if self.desync.is_none() {
+            return;
+        }
+        if frame + RESYNC_WINDOW < current {
+            log::warn!("Board of frame {frame} too old to resync at frame {current}");
+            return;
+        }
+        log::info!("Resync to the board of frame {frame} at frame {current}");
+        self.state.restore(snapshot);
+        self.snapshots.clear();
+        self.remote_confirmed = self.remote_confirmed.max(frame);
+        while self.state.frame() < current {
+            self.simulate_frame();
+        }
+        self.desync = None;
+        self.checksums.clear();
+        self.remote_checksum = None;
+    }
+
+    fn compare_checksums(&mut self) {
+        let Some((frame, remote)) = self.remote_checksum else { return };
+        let Some(&(_, local)) = self.checksums.iter().find(|(f, _)| *f == frame) else {
+            // the other peer is further, compared once this one confirms that frame
+            if self.checksums.front().is_some_and(|(f, _)| *f > frame) {
+                self.remote_checksum = None;
+            }
+            return;
+        };
+        self.remote_checksum = None;
+        if local == remote {
+            // player 1 learns this way that the other peer took over its board
+            self.desync = None;
+        } else if self.desync.is_none() {
+            log::warn!("Desync at frame {frame}: local checksum {local:08x}, remote {remote:08x}");
+            self.desync = Some(frame);
+            self.desyncs += 1;
+        }
+    }
+
+    fn rollback(&mut self, frame: u64, current: u64) {
+        let Some(index) = self.snapshots.iter().position(|s| s.frame() == frame) else {
+            log::error!("No snapshot of frame {frame} to roll back to");
+            return;
+        };
+        self.state.restore(&self.snapshots[index]);
+        self.snapshots.truncate(index);
+        self.rolled_back += current - frame;
+        while self.state.frame() < current {
+            self.simulate_frame();
+        }
+    }
+
+    fn simulate_frame(&mut self) {
+        let frame = self.state.frame();
+        self.snapshots.push_back(self.state.snapshot());
+        let local = self.local_inputs.get(&frame).copied().unwrap_or_default();
+        let remote = self.remote_inputs.get(&frame).copied().unwrap_or_default();
+        // player 1 first on both peers
+        let (input1, input2) = if self.local_is_player1 { (local, remote) } else { (remote, local) };
+        input1.apply(&mut self.state, true);
+        input2.apply(&mut self.state, false);
+        self.state.step_frame();
+    }
+
+    fn trim(&mut self) {
+        // frames from the first unconfirmed one on may be simulated again
+        let keep_from = self.remote_confirmed.min(self.state.frame());
+        while let Some(snapshot) = self.snapshots.front() && snapshot.frame() < keep_from {
+            if snapshot.frame().is_multiple_of(CHECKSUM_INTERVAL) {
+                if self.checksums.len() as u64 == RESYNC_WINDOW / CHECKSUM_INTERVAL {
+                    self.checksums.pop_front();
+                }
+                self.checksums.push_back((snapshot.frame(), snapshot.checksum()));
+            }
+            self.snapshots.pop_front();
+        }
+        self.compare_checksums();
+        let keep_inputs = keep_from.saturating_sub(RESYNC_WINDOW);
+        self.remote_inputs.retain(|&f, _| f >= keep_inputs);
+        let keep_local = keep_inputs.min(self.peer_ack);
+        self.local_inputs.retain(|&f, _| f >= keep_local);
+    }
+}
+
+// the encoding of bincode::serialize, with a limit on what the length fields of a board from the other peer may claim
+fn snapshot_options() -> impl Options {
+    bincode::DefaultOptions::new().with_fixint_encoding().allow_trailing_bytes().with_limit(MAX_SNAPSHOT_BYTES as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use crate::GameConfig;
+    use crate::p2p::RollbackSession;
+    use crate::udp_server::{Key, ParseError};
+
+    const KEYS: [Key; 5] = [Key::Left(true), Key::Left(false), Key::Right(true), Key::Right(false), Key::Jump];
+
+    // 3 or 4 frames each way, 50 ms with up to 17 ms of jitter, and 10% loss, at 60 frames per second, until both peers confirmed the end frame
+    fn play(end: u64, corrupt_at: Option<u64>) -> [RollbackSession; 2] {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut peers = [RollbackSession::new(GameConfig::default(), true, 2), RollbackSession::new(GameConfig::default(), false, 2)];
+        // delivery tick, receiver, datagram
+        let mut network: VecDeque<(u64, usize, Vec<u8>)> = VecDeque::new();
+        let mut tick = 0;
+        while peers.iter().any(|p| p.frame() < end || !p.is_confirmed() || p.desync().is_some()) {
+            assert!(tick < 3 * end, "peers stuck at frames {} and {}", peers[0].frame(), peers[1].frame());
+            if corrupt_at == Some(peers[1].frame()) {
+                // a bug that changes the board of one peer only, it is in every state that could be rolled back to
+                peers[1].state.points2 += 1;
+                for snapshot in peers[1].snapshots.iter_mut() {
+                    snapshot.points2 += 1;
+                }
+            }
+            for (i, peer) in peers.iter_mut().enumerate() {
+                if peer.frame() < end {
+                    if rng.random_bool(0.1) {
+                        peer.add_local_input(KEYS[rng.random_range(0..KEYS.len())]);
+                    }
+                    peer.advance_frame();
+                }
+                let datagrams = std::iter::once(peer.encode()).chain(peer.resync_packets());
+                for data in datagrams {
+                    if !rng.random_bool(0.1) {
+                        network.push_back((tick + 3 + rng.random_range(0..2), 1 - i, data));
+                    }
+                }
+            }
+            network.make_contiguous().sort_by_key(|(at, ..)| *at);
+            while let Some((at, to, _)) = network.front() && *at <= tick {
+                let to = *to;
+                let (_, _, data) = network.pop_front().unwrap();
+                peers[to].receive(&data).unwrap();
+            }
+            tick += 1;
+        }
+        peers
+    }
+
+    fn assert_same(peers: &[RollbackSession; 2]) {
+        let [first, second] = peers;
+        assert_eq!(first.frame(), second.frame());
+        assert_eq!(first.state().players(), second.state().players());
+        assert_eq!(first.state().ball(), second.state().ball());
+        assert_eq!(first.state().points(), second.state().points());
+        assert_eq!(first.state().checksum(), second.state().checksum());
+    }
+
+    #[test]
+    fn test_peers_agree() {
+        let peers = play(1200, None);
+        assert!(peers.iter().all(|p| p.rolled_back() > 0), "nothing was predicted wrong");
+        assert!(peers.iter().all(|p| p.desyncs() == 0));
+        assert_same(&peers);
+        assert!(peers[0].state().points().0 + peers[0].state().points().1 > 0, "no point was played");
+    }
+
+    #[test]
+    fn test_desync_resync() {
+        let peers = play(900, Some(400));
+        // both notice, player 2 takes over the board of player 1
+        assert!(peers.iter().all(|p| p.desyncs() == 1), "desyncs: {} {}", peers[0].desyncs(), peers[1].desyncs());
+        assert_same(&peers);
+    }
+
+    #[test]
+    fn test_resync_parts_checked() {
+        let mut peer = RollbackSession::new(GameConfig::default(), false, 2);
+        let part = |index: u8, count: u8, bytes: &[u8]| [&[12, 64, 13, 51], 7u64.to_le_bytes().as_slice(), &[index, count], bytes].concat();
+        assert_eq!(peer.receive(&part(0, 200, &[0; 16])), Err(ParseError::InvalidField("resync part")));
+        assert_eq!(peer.receive(&part(2, 2, &[0; 16])), Err(ParseError::InvalidField("resync part")));
+        assert_eq!(peer.receive(&part(0, 1, &[0; 2000])), Err(ParseError::WrongLength(2014)));
+        // bytes that are no board
+        assert_eq!(peer.receive(&part(0, 1, &u64::MAX.to_le_bytes())), Err(ParseError::InvalidField("snapshot")));
+    }
+}
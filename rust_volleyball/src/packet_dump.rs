@@ -0,0 +1,96 @@
+use std::fmt::{Display, Write as _};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::udp_server::ParseError;
+
+// packets that could not be parsed are logged byte for byte when enabled (--dump-packets), to see what a client
+// with a changed protocol really sends; a flood of them does not fill the log, only so many are dumped per second
+
+pub static DUMPS: PacketDumps = PacketDumps::new();
+
+const DUMPS_PER_SECOND: u32 = 10;
+
+pub struct PacketDumps {
+    enabled: AtomicBool,
+    // (start of the current second, dumped in it, suppressed since the last report)
+    window: Mutex<(Option<Instant>, u32, u64)>,
+}
+
+impl PacketDumps {
+    const fn new() -> PacketDumps {
+        PacketDumps { enabled: AtomicBool::new(false), window: Mutex::new((None, 0, 0)) }
+    }
+
+    pub fn enable(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn dump(&self, transport: &str, from: impl Display, error: &ParseError, data: &[u8]) {
+        if !self.enabled.load(Ordering::Relaxed) || !self.admit(Instant::now()) {
+            return;
+        }
+        log::info!("Invalid {transport} packet from {from}, {error}, {} bytes:\n{}", data.len(), hexdump(data));
+    }
+
+    // at exit, the count of the packets not dumped in the last second would be lost otherwise
+    pub fn flush(&self) {
+        let Ok(mut window) = self.window.lock() else { return };
+        let suppressed = std::mem::take(&mut window.2);
+        if suppressed > 0 {
+            log::info!("{suppressed} more invalid packets were not dumped");
+        }
+    }
+
+    fn admit(&self, now: Instant) -> bool {
+        let Ok(mut window) = self.window.lock() else { return false };
+        let (start, dumped, suppressed) = &mut *window;
+        if start.is_none_or(|start| now - start >= Duration::from_secs(1)) {
+            if *suppressed > 0 {
+                log::info!("{suppressed} more invalid packets were not dumped");
+            }
+            (*start, *dumped, *suppressed) = (Some(now), 0, 0);
+        }
+        if *dumped >= DUMPS_PER_SECOND {
+            *suppressed += 1;
+            return false;
+        }
+        *dumped += 1;
+        true
+    }
+}
+
+// 16 bytes per line: offset, hex and the printable characters
+pub fn hexdump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let _ = write!(out, "{:04x}  ", i * 16);
+        for byte in line {
+            let _ = write!(out, "{byte:02x} ");
+        }
+        let ascii: String = line.iter().map(|&b| if b.is_ascii_graphic() { b as char } else { '.' }).collect();
+        let _ = writeln!(out, "{:width$} {ascii}", "", width = (16 - line.len()) * 3);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use crate::packet_dump::{hexdump, PacketDumps};
+
+    #[test]
+    fn test_dumps() {
+        assert_eq!(hexdump(b":):P:D\x0b\x0d"), "0000  3a 29 3a 50 3a 44 0b 0d                          :):P:D..\n");
+        assert_eq!(hexdump(&[0; 17]).lines().nth(1), Some("0010  00                                               ."));
+
+        let dumps = PacketDumps::new();
+        let now = Instant::now();
+        assert_eq!((0..25).filter(|_| dumps.admit(now)).count(), 10);
+        assert!(!dumps.admit(now + Duration::from_millis(999)));
+        assert_eq!(dumps.window.lock().unwrap().2, 16);
+        dumps.flush();
+        assert_eq!(dumps.window.lock().unwrap().2, 0);
+        assert!(dumps.admit(now + Duration::from_secs(1)));
+    }
+}
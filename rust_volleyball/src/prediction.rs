@@ -0,0 +1,269 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use crate::{GameConfig, GameSnapshot, GameState};
+use crate::server_logic::{GameStateSerialized, ResyncState};
+use crate::udp_server::Key;
+
+// how far back a server snapshot can reach, a round trip longer than that only moves the bodies
+const HISTORY_SECONDS: usize = 2;
+
+// runs the board locally so the own player reacts to inputs at once, server snapshots correct it
+pub struct Predictor {
+    state: GameState,
+    is_player1: bool,
+    // local snapshots at the start of each frame, before the inputs of that frame
+    history: VecDeque<GameSnapshot>,
+    // own inputs the server has not acknowledged yet: sequence number, local frame, key
+    inputs: VecDeque<(u32, u64, Key)>,
+    // the newest acknowledged input and the local frame it was applied at
+    acked: Option<(u32, u64)>,
+    unsimulated: Duration,
+    // snapshot checksums that differed from the predicted board, and whether the whole board is wanted since the last
+    desyncs: u64,
+    resync_wanted: bool,
+}
+
+impl Predictor {
+    // the config has to match the server's, the side comes with every state packet
+    pub fn new(config: GameConfig, is_player1: bool) -> Predictor {
+        let mut state = GameState::with_config(config);
+        state.set_scoring(false);
+        let history = VecDeque::from([state.snapshot()]);
+        Predictor { state, is_player1, history, inputs: VecDeque::new(), acked: None, unsimulated: Duration::ZERO, desyncs: 0, resync_wanted: false }
+    }
+
+    // applies an own input right away, seq is the one it was sent with
+    pub fn input(&mut self, seq: u32, key: Key) {
+        apply(&mut self.state, key, self.is_player1);
+        self.inputs.push_back((seq, self.state.frame(), key));
+    }
+
+    pub fn step(&mut self) {
+        self.state.step_frame();
+        self.history.push_back(self.state.snapshot());
+        if self.history.len() > HISTORY_SECONDS * self.state.frame_rate() as usize {
+            self.history.pop_front();
+        }
+    }
+
+    // steps as many frames as fit in the wall time since the last call
+    pub fn advance(&mut self, elapsed: Duration) {
+        let time_step = Duration::from_secs(1) / self.state.frame_rate();
+        self.unsimulated += elapsed;
+        while self.unsimulated >= time_step {
+            self.unsimulated -= time_step;
+            self.step();
+        }
+    }
+
+    // takes over the server state at the matching local frame and replays the newer inputs from there; a checksum in
+    // the snapshot is compared with what the board holds there after the correction
+    pub fn reconcile(&mut self, server: &GameStateSerialized) {
+        let is_player1 = self.is_player1;
+        let placed = self.rebase(server.own.ack_seq, server.own.ack_age, |state| take_over(state, is_player1, server));
+        if let (Some((frame, predicted)), Some(checksum)) = (placed, server.checksum) && predicted != checksum {
+            log::warn!("Desync at server frame {}, local frame {frame}: server checksum {checksum:08x}, predicted {predicted:08x}", server.frame);
+            self.desyncs += 1;
+            self.resync_wanted = true;
+        }
+    }
+
+    // the whole board from the server, in place of the prediction of what the snapshots leave out
+    pub fn resync(&mut self, resync: &ResyncState) {
+        let placed = self.rebase(resync.ack_seq, resync.ack_age, |state| state.set_board_state(&resync.board));
+        log::info!("Resync to server frame {} at local frame {:?}", resync.frame, placed.map(|(frame, _)| frame));
+        self.resync_wanted = false;
+    }
+
+    // true once after a desync, the client asks the server for the whole board then
+    pub fn take_resync_request(&mut self) -> bool {
+        std::mem::take(&mut self.resync_wanted)
+    }
+
+    pub fn desyncs(&self) -> u64 {
+        self.desyncs
+    }
+
+    // corrects the board with a server state at the local frame the acknowledgement points to and replays the newer
+    // inputs; that frame and the snapshot checksum of the corrected board there, None when it could not be placed and
+    // the current frame was corrected
+    fn rebase(&mut self, ack: u32, ack_age: u16, correct: impl Fn(&mut GameState)) -> Option<(u64, u32)> {
+        if ack > 0 && self.acked.is_none_or(|(seq, _)| ack > seq) {
+            self.acked = self.inputs.iter().find(|(seq, ..)| *seq == ack).map(|&(seq, frame, _)| (seq, frame)).or(self.acked);
+            self.inputs.retain(|(seq, ..)| *seq > ack);
+        }
+        let base_frame = match self.acked {
+            Some((seq, frame)) if seq == ack => frame + ack_age as u64,
+            // without an acknowledged input the snapshot cannot be placed on the local timeline
+            _ => {
+                if self.inputs.is_empty() {
+                    correct(&mut self.state);
+                }
+                return None;
+            }
+        };
+        let current = self.state.frame();
+        let Some(index) = self.history.iter().position(|s| s.frame() == base_frame).filter(|_| base_frame <= current) else {
+            correct(&mut self.state);
+            return None;
+        };
+        self.state.restore(&self.history[index]);
+        correct(&mut self.state);
+        let checksum = self.state.snapshot_checksum(self.is_player1);
+        // later snapshots never reach further back than this one
+        self.history.drain(..index);
+        self.history.truncate(1);
+        self.history[0] = self.state.snapshot();
+        loop {
+            let frame = self.state.frame();
+            for &(_, _, key) in self.inputs.iter().filter(|(_, f, _)| *f == frame) {
+                apply(&mut self.state, key, self.is_player1);
+            }
+            if frame == current {
+                break;
+            }
+            self.step();
+        }
+        Some((base_frame, checksum))
+    }
+
+    // where the own player is drawn
+    pub fn player(&self) -> (f32, f32) {
+        let (x1, y1, _, x2, y2, _) = self.state.players();
+        if self.is_player1 { (x1, y1) } else { (x2, y2) }
+    }
+
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+}
+
+fn take_over(state: &mut GameState, is_player1: bool, server: &GameStateSerialized) {
+    state.set_positions(server.ball_pos, server.player1_pos, server.player2_pos);
+    state.set_ball_motion(server.ball_velocity, server.ball_gravity);
+    state.set_player_motion(is_player1, server.own.velocity, server.own.held);
+}
+
+pub(crate) fn apply(state: &mut GameState, key: Key, is_player1: bool) {
+    match key {
+        Key::Left(true) => state.add_force(false, is_player1),
+        Key::Left(false) => state.reset_force(false, is_player1),
+        Key::Right(true) => state.add_force(true, is_player1),
+        Key::Right(false) => state.reset_force(true, is_player1),
+        Key::Jump => state.apply_impulse(false, is_player1),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use crate::{GameConfig, GameState};
+    use crate::prediction::{apply, Predictor};
+    use crate::server_logic::{GameStateSerialized, OwnPlayer, ResyncState};
+    use crate::udp_server::{board_packet, parse_server_packet, Key, ServerPacket};
+
+    const KEYS: [Key; 5] = [Key::Left(true), Key::Left(false), Key::Right(true), Key::Right(false), Key::Jump];
+
+    #[test]
+    fn test_prediction_converges() {
+        // 100 ms each way at 60 frames per second
+        let latency = 6;
+        let frames = 900;
+        let mut rng = StdRng::seed_from_u64(3);
+        let config = GameConfig::default();
+        let mut server = GameState::with_config(config);
+        let mut predictor = Predictor::new(config, false);
+        let mut to_server: VecDeque<(u64, u32, Key)> = VecDeque::new();
+        let mut to_client: VecDeque<(u64, GameStateSerialized)> = VecDeque::new();
+        let mut ack = (0, 0);
+        let mut seq = 0;
+        let mut server_positions = vec![];
+        let mut predicted = vec![];
+        let mut last_received = None;
+        let mut received = vec![];
+
+        for frame in 0..frames + 2 * latency {
+            while let Some(&(at, input_seq, key)) = to_server.front() && at <= frame {
+                to_server.pop_front();
+                apply(&mut server, key, false);
+                ack = (input_seq, server.frame());
+            }
+            if rng.random_bool(0.05) {
+                apply(&mut server, KEYS[rng.random_range(0..KEYS.len())], true);
+            }
+            server.step_frame();
+            let age = (server.frame() - ack.1) as u16;
+            let own = OwnPlayer::new(&server, false, ack.0, age);
+            let checksum = frame.is_multiple_of(10).then(|| server.snapshot_checksum(false));
+            to_client.push_back((frame + 1 + latency, GameStateSerialized { own, checksum, ..GameStateSerialized::new(&server) }));
+            let (_, _, _, x, y, _) = server.players();
+            server_positions.push((x, y));
+
+            while let Some(&(at, state)) = to_client.front() && at <= frame {
+                to_client.pop_front();
+                predictor.reconcile(&state);
+                last_received = Some(state.player2_pos);
+            }
+            // the own player moves for most of the test and stands still at the end
+            if frame < frames - 120 && rng.random_bool(0.08) {
+                seq += 1;
+                let key = KEYS[rng.random_range(0..KEYS.len())];
+                predictor.input(seq, key);
+                to_server.push_back((frame + latency, seq, key));
+            }
+            predictor.step();
+            predicted.push(predictor.player());
+            received.push(last_received);
+        }
+
+        // what the client shows at frame t is what the server computes at frame t + latency
+        let distance = |a: (f32, f32), b: (f32, f32)| ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+        let errors: Vec<f32> = (60..frames as usize).map(|t| distance(predicted[t], server_positions[t + latency as usize])).collect();
+        let lagging: Vec<f32> = (60..frames as usize).map(|t| distance(received[t].unwrap(), server_positions[t + latency as usize])).collect();
+        // off only for a round trip after the server resets the players for the next point
+        let accurate = |e: &[f32]| e.iter().filter(|e| **e < 0.01).count() as f32 / e.len() as f32;
+        assert!(accurate(&errors) > 0.9, "prediction is accurate for {} of the frames", accurate(&errors));
+        assert!(accurate(&lagging) < 0.5, "snapshots alone are accurate for {} of the frames", accurate(&lagging));
+        assert!(errors.last().unwrap() < &0.001, "no convergence, off by {}", errors.last().unwrap());
+        // the opponent moves and points are scored, neither is in what the checksums cover
+        assert_eq!(predictor.desyncs(), 0);
+    }
+
+    #[test]
+    fn test_desync_and_resync() {
+        let config = GameConfig::default();
+        let mut server = GameState::with_config(config);
+        let mut predictor = Predictor::new(config, false);
+        predictor.input(1, Key::Right(true));
+        apply(&mut server, Key::Right(true), false);
+        apply(&mut server, Key::Left(true), true);
+        for _ in 0..30 {
+            server.step_frame();
+            predictor.step();
+        }
+        let own = OwnPlayer::new(&server, false, 1, server.frame() as u16);
+        let snapshot = GameStateSerialized { own, checksum: Some(server.snapshot_checksum(false)), ..GameStateSerialized::new(&server) };
+        // the opponent holds a key the prediction knows nothing of, the snapshot still agrees
+        predictor.reconcile(&snapshot);
+        assert_eq!(predictor.desyncs(), 0);
+
+        // a checksum the corrected board does not give, here of a board a frame later
+        let mut later = GameState::from_snapshot(&server.snapshot());
+        later.step_frame();
+        predictor.reconcile(&GameStateSerialized { checksum: Some(later.snapshot_checksum(false)), ..snapshot });
+        assert_eq!(predictor.desyncs(), 1);
+        assert!(predictor.take_resync_request());
+        assert!(!predictor.take_resync_request());
+
+        // through the packet, the whole board puts the prediction back on the server's
+        let resync = ResyncState { board: server.board_state(), frame: server.frame(), ack_seq: 1, ack_age: server.frame() as u16 };
+        let Ok(ServerPacket::Board(resync)) = parse_server_packet(&board_packet(&resync)) else { panic!("board did not parse") };
+        predictor.resync(&resync);
+        assert_eq!(predictor.state().checksum(), server.checksum());
+        predictor.reconcile(&snapshot);
+        assert_eq!(predictor.desyncs(), 1);
+        assert!(!predictor.take_resync_request());
+    }
+}
@@ -0,0 +1,744 @@
+// generated from protocol.toml by `cargo run --bin protocol_gen`, change the schema and run it again instead of
+// editing this file
+
+pub const CLIENT_PACKET_LEN: usize = 32;
+// the last byte is the protocol version
+pub const MAGIC: [u8; 6] = [58, 41, 58, 80, 58, 68];
+pub const PROTOCOL_VERSION: u8 = 68;
+pub const OPCODE_AT: usize = 6;
+// what server packets with a header start with
+pub const SERVER_MAGIC: [u8; 2] = [12, 64];
+
+pub const PLAYER_ID_REQUEST: [u8; 2] = [13, 22];
+pub const MIGRATION_KEY_REQUEST: [u8; 2] = [13, 23];
+pub const GAME_REQUEST: [u8; 2] = [11, 13];
+pub const RESUME_REQUEST: [u8; 2] = [11, 14];
+pub const LEFT_PRESSED: [u8; 2] = [17, 23];
+pub const LEFT_RELEASED: [u8; 2] = [25, 99];
+pub const RIGHT_PRESSED: [u8; 2] = [37, 31];
+pub const RIGHT_RELEASED: [u8; 2] = [67, 58];
+pub const JUMP: [u8; 2] = [97, 33];
+pub const PING: [u8; 2] = [96, 22];
+pub const PONG: [u8; 2] = [96, 23];
+pub const SESSION: [u8; 2] = [41, 7];
+pub const MIGRATE: [u8; 2] = [52, 9];
+pub const RESYNC_REQUEST: [u8; 2] = [11, 16];
+
+pub const IDS_HEADER: [u8; 4] = [12, 64, 13, 56];
+pub const IDS_LEN: usize = 32;
+pub const SESSION_REPLY_HEADER: [u8; 4] = [12, 64, 13, 57];
+pub const SESSION_REPLY_LEN: usize = 32;
+pub const SERVER_PONG_HEADER: [u8; 4] = [12, 64, 13, 80];
+pub const SERVER_PONG_LEN: usize = 32;
+pub const SERVER_PING_HEADER: [u8; 4] = [12, 64, 13, 81];
+pub const SERVER_PING_LEN: usize = 32;
+pub const SHUTDOWN_NOTICE_HEADER: [u8; 4] = [12, 64, 13, 99];
+pub const SHUTDOWN_NOTICE_LEN: usize = 32;
+pub const STATE_LEN: usize = 68;
+pub const BOARD_HEADER: [u8; 4] = [12, 64, 13, 66];
+pub const BOARD_LEN: usize = 152;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Player {
+    pub player_id: u64,
+}
+
+impl Player {
+    pub fn read(data: &[u8]) -> Player {
+        Player {
+            player_id: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[8..16].copy_from_slice(&self.player_id.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Resume {
+    pub player_id: u64,
+    // from the shutdown notice of the server's previous run
+    pub resume_token: u64,
+}
+
+impl Resume {
+    pub fn read(data: &[u8]) -> Resume {
+        Resume {
+            player_id: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+            resume_token: u64::from_le_bytes(data[16..24].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[8..16].copy_from_slice(&self.player_id.to_le_bytes());
+        packet[16..24].copy_from_slice(&self.resume_token.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Input {
+    pub player_id: u64,
+    pub board_id: u64,
+    // input sequence number, 0 when the client does not count its inputs
+    pub seq: u32,
+    // the server frame the client saw when pressing, 0 for now
+    pub frame: u32,
+}
+
+impl Input {
+    pub fn read(data: &[u8]) -> Input {
+        Input {
+            player_id: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+            board_id: u64::from_le_bytes(data[16..24].try_into().unwrap()),
+            seq: u32::from_le_bytes(data[24..28].try_into().unwrap()),
+            frame: u32::from_le_bytes(data[28..32].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[8..16].copy_from_slice(&self.player_id.to_le_bytes());
+        packet[16..24].copy_from_slice(&self.board_id.to_le_bytes());
+        packet[24..28].copy_from_slice(&self.seq.to_le_bytes());
+        packet[28..32].copy_from_slice(&self.frame.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PlayerBoard {
+    pub player_id: u64,
+    pub board_id: u64,
+}
+
+impl PlayerBoard {
+    pub fn read(data: &[u8]) -> PlayerBoard {
+        PlayerBoard {
+            player_id: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+            board_id: u64::from_le_bytes(data[16..24].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[8..16].copy_from_slice(&self.player_id.to_le_bytes());
+        packet[16..24].copy_from_slice(&self.board_id.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Ping {
+    pub player_id: u64,
+    pub board_id: u64,
+    // echoed in the pong, 0 when only a keepalive
+    pub client_time: u64,
+}
+
+impl Ping {
+    pub fn read(data: &[u8]) -> Ping {
+        Ping {
+            player_id: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+            board_id: u64::from_le_bytes(data[16..24].try_into().unwrap()),
+            client_time: u64::from_le_bytes(data[24..32].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[8..16].copy_from_slice(&self.player_id.to_le_bytes());
+        packet[16..24].copy_from_slice(&self.board_id.to_le_bytes());
+        packet[24..32].copy_from_slice(&self.client_time.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Pong {
+    pub player_id: u64,
+    // from the server ping
+    pub server_time: u64,
+    pub client_time: u64,
+}
+
+impl Pong {
+    pub fn read(data: &[u8]) -> Pong {
+        Pong {
+            player_id: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+            server_time: u64::from_le_bytes(data[16..24].try_into().unwrap()),
+            client_time: u64::from_le_bytes(data[24..32].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[8..16].copy_from_slice(&self.player_id.to_le_bytes());
+        packet[16..24].copy_from_slice(&self.server_time.to_le_bytes());
+        packet[24..32].copy_from_slice(&self.client_time.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    // chosen by the client, in place of the player id
+    pub token: u64,
+    // 0 for an acknowledgement only
+    pub seq: u32,
+    pub ack: u32,
+    // 0 none, 1 connect, 2 ping, 3 disconnect
+    pub request: u8,
+    // of a connect, from the server's retry, 0 before it
+    pub cookie: u32,
+}
+
+impl Session {
+    pub fn read(data: &[u8]) -> Session {
+        Session {
+            token: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+            seq: u32::from_le_bytes(data[16..20].try_into().unwrap()),
+            ack: u32::from_le_bytes(data[20..24].try_into().unwrap()),
+            request: data[24],
+            cookie: u32::from_le_bytes(data[28..32].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[8..16].copy_from_slice(&self.token.to_le_bytes());
+        packet[16..20].copy_from_slice(&self.seq.to_le_bytes());
+        packet[20..24].copy_from_slice(&self.ack.to_le_bytes());
+        packet[24] = self.request;
+        packet[28..32].copy_from_slice(&self.cookie.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Migrate {
+    pub player_id: u64,
+    // grows with every migration
+    pub counter: u64,
+    // migration::tag of the player id and the counter
+    pub tag: u64,
+}
+
+impl Migrate {
+    pub fn read(data: &[u8]) -> Migrate {
+        Migrate {
+            player_id: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+            counter: u64::from_le_bytes(data[16..24].try_into().unwrap()),
+            tag: u64::from_le_bytes(data[24..32].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[8..16].copy_from_slice(&self.player_id.to_le_bytes());
+        packet[16..24].copy_from_slice(&self.counter.to_le_bytes());
+        packet[24..32].copy_from_slice(&self.tag.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Ids {
+    pub player_id: u64,
+    pub board_id: u64,
+}
+
+impl Ids {
+    pub fn read(data: &[u8]) -> Ids {
+        Ids {
+            player_id: u64::from_le_bytes(data[4..12].try_into().unwrap()),
+            board_id: u64::from_le_bytes(data[12..20].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[4..12].copy_from_slice(&self.player_id.to_le_bytes());
+        packet[12..20].copy_from_slice(&self.board_id.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SessionReply {
+    pub seq: u32,
+    pub ack: u32,
+    // 0 none, 1 welcome, 2 shutdown notice, 3 disconnect, 4 retry
+    pub reply: u8,
+    // of the welcome
+    pub player_id: u64,
+    // migration key of the welcome, resume token of the shutdown notice, cookie of the retry
+    pub key: u64,
+}
+
+impl SessionReply {
+    pub fn read(data: &[u8]) -> SessionReply {
+        SessionReply {
+            seq: u32::from_le_bytes(data[4..8].try_into().unwrap()),
+            ack: u32::from_le_bytes(data[8..12].try_into().unwrap()),
+            reply: data[12],
+            player_id: u64::from_le_bytes(data[16..24].try_into().unwrap()),
+            key: u64::from_le_bytes(data[24..32].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[4..8].copy_from_slice(&self.seq.to_le_bytes());
+        packet[8..12].copy_from_slice(&self.ack.to_le_bytes());
+        packet[12] = self.reply;
+        packet[16..24].copy_from_slice(&self.player_id.to_le_bytes());
+        packet[24..32].copy_from_slice(&self.key.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ShutdownNotice {
+    // for a resume request after the restart, 0 when the player is on no board
+    pub resume_token: u64,
+}
+
+impl ShutdownNotice {
+    pub fn read(data: &[u8]) -> ShutdownNotice {
+        ShutdownNotice {
+            resume_token: u64::from_le_bytes(data[4..12].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[4..12].copy_from_slice(&self.resume_token.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ServerPong {
+    // from the client ping
+    pub client_time: u64,
+    pub server_time: u64,
+}
+
+impl ServerPong {
+    pub fn read(data: &[u8]) -> ServerPong {
+        ServerPong {
+            client_time: u64::from_le_bytes(data[4..12].try_into().unwrap()),
+            server_time: u64::from_le_bytes(data[12..20].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[4..12].copy_from_slice(&self.client_time.to_le_bytes());
+        packet[12..20].copy_from_slice(&self.server_time.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ServerPing {
+    pub server_time: u64,
+}
+
+impl ServerPing {
+    pub fn read(data: &[u8]) -> ServerPing {
+        ServerPing {
+            server_time: u64::from_le_bytes(data[4..12].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[4..12].copy_from_slice(&self.server_time.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct State {
+    pub ball_radius: f32,
+    pub ball_x: f32,
+    pub ball_y: f32,
+    pub player_radius: f32,
+    pub player1_x: f32,
+    pub player1_y: f32,
+    pub player2_x: f32,
+    pub player2_y: f32,
+    pub score1: u32,
+    pub score2: u32,
+    // 1 when over
+    pub game_over: u8,
+    // 1 or 2, the fields below are of that player
+    pub own_player: u8,
+    // bit 0 left, bit 1 right, as the server sees them
+    pub held: u8,
+    // 1 once gravity acts on the ball
+    pub ball_gravity: u8,
+    // the newest input the server applied
+    pub ack_seq: u32,
+    // frames simulated since then
+    pub ack_age: u16,
+    // 1 when the snapshot carries a checksum
+    pub has_checksum: u8,
+    // velocities in mm/s
+    pub ball_vx: f32,
+    pub ball_vy: f32,
+    pub own_vx: f32,
+    pub own_vy: f32,
+    // the board frame of the snapshot
+    pub frame: u32,
+    // of what the snapshot sets on the own board at that frame
+    pub checksum: u32,
+}
+
+impl State {
+    pub fn read(data: &[u8]) -> State {
+        State {
+            ball_radius: f32::from_le_bytes(data[0..4].try_into().unwrap()),
+            ball_x: f32::from_le_bytes(data[4..8].try_into().unwrap()),
+            ball_y: f32::from_le_bytes(data[8..12].try_into().unwrap()),
+            player_radius: f32::from_le_bytes(data[12..16].try_into().unwrap()),
+            player1_x: f32::from_le_bytes(data[16..20].try_into().unwrap()),
+            player1_y: f32::from_le_bytes(data[20..24].try_into().unwrap()),
+            player2_x: f32::from_le_bytes(data[24..28].try_into().unwrap()),
+            player2_y: f32::from_le_bytes(data[28..32].try_into().unwrap()),
+            score1: u32::from_le_bytes(data[32..36].try_into().unwrap()),
+            score2: u32::from_le_bytes(data[36..40].try_into().unwrap()),
+            game_over: data[40],
+            own_player: data[41],
+            held: data[42],
+            ball_gravity: data[43],
+            ack_seq: u32::from_le_bytes(data[44..48].try_into().unwrap()),
+            ack_age: u16::from_le_bytes(data[48..50].try_into().unwrap()),
+            has_checksum: data[50],
+            ball_vx: i16::from_le_bytes(data[52..54].try_into().unwrap()) as f32 / 1000.0,
+            ball_vy: i16::from_le_bytes(data[54..56].try_into().unwrap()) as f32 / 1000.0,
+            own_vx: i16::from_le_bytes(data[56..58].try_into().unwrap()) as f32 / 1000.0,
+            own_vy: i16::from_le_bytes(data[58..60].try_into().unwrap()) as f32 / 1000.0,
+            frame: u32::from_le_bytes(data[60..64].try_into().unwrap()),
+            checksum: u32::from_le_bytes(data[64..68].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[0..4].copy_from_slice(&self.ball_radius.to_le_bytes());
+        packet[4..8].copy_from_slice(&self.ball_x.to_le_bytes());
+        packet[8..12].copy_from_slice(&self.ball_y.to_le_bytes());
+        packet[12..16].copy_from_slice(&self.player_radius.to_le_bytes());
+        packet[16..20].copy_from_slice(&self.player1_x.to_le_bytes());
+        packet[20..24].copy_from_slice(&self.player1_y.to_le_bytes());
+        packet[24..28].copy_from_slice(&self.player2_x.to_le_bytes());
+        packet[28..32].copy_from_slice(&self.player2_y.to_le_bytes());
+        packet[32..36].copy_from_slice(&self.score1.to_le_bytes());
+        packet[36..40].copy_from_slice(&self.score2.to_le_bytes());
+        packet[40] = self.game_over;
+        packet[41] = self.own_player;
+        packet[42] = self.held;
+        packet[43] = self.ball_gravity;
+        packet[44..48].copy_from_slice(&self.ack_seq.to_le_bytes());
+        packet[48..50].copy_from_slice(&self.ack_age.to_le_bytes());
+        packet[50] = self.has_checksum;
+        packet[52..54].copy_from_slice(&((self.ball_vx * 1000.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16).to_le_bytes());
+        packet[54..56].copy_from_slice(&((self.ball_vy * 1000.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16).to_le_bytes());
+        packet[56..58].copy_from_slice(&((self.own_vx * 1000.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16).to_le_bytes());
+        packet[58..60].copy_from_slice(&((self.own_vy * 1000.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16).to_le_bytes());
+        packet[60..64].copy_from_slice(&self.frame.to_le_bytes());
+        packet[64..68].copy_from_slice(&self.checksum.to_le_bytes());
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Board {
+    // the board frame it was taken at
+    pub frame: u32,
+    // as in the state
+    pub ack_seq: u32,
+    pub ack_age: u16,
+    // bit 0 points added, bit 1 ball for player 1, bit 2 ball touched, bit 3 game over
+    pub flags: u8,
+    // bits 0 and 1 left and right of player 1, bits 2 and 3 of player 2
+    pub held: u8,
+    pub score1: u32,
+    pub score2: u32,
+    pub player1_x: f32,
+    pub player1_y: f32,
+    // the rotation as cosine and sine, exact where an angle is not
+    pub player1_cos: f32,
+    pub player1_sin: f32,
+    pub player1_vx: f32,
+    pub player1_vy: f32,
+    // angular velocity
+    pub player1_spin: f32,
+    // the force the inputs put on it
+    pub player1_fx: f32,
+    pub player1_fy: f32,
+    pub player2_x: f32,
+    pub player2_y: f32,
+    pub player2_cos: f32,
+    pub player2_sin: f32,
+    pub player2_vx: f32,
+    pub player2_vy: f32,
+    pub player2_spin: f32,
+    pub player2_fx: f32,
+    pub player2_fy: f32,
+    pub ball_x: f32,
+    pub ball_y: f32,
+    pub ball_cos: f32,
+    pub ball_sin: f32,
+    pub ball_vx: f32,
+    pub ball_vy: f32,
+    pub ball_spin: f32,
+    pub ball_fx: f32,
+    pub ball_fy: f32,
+    // gravity scales, the ball's is 0 from a reset until the serve
+    pub player1_gravity: f32,
+    pub player2_gravity: f32,
+    pub ball_gravity: f32,
+    // frames until the reset after a point, 0 when none is due
+    pub reset_in: u32,
+    // frames until the serve, 0 when none is due
+    pub gravity_in: u32,
+}
+
+impl Board {
+    pub fn read(data: &[u8]) -> Board {
+        Board {
+            frame: u32::from_le_bytes(data[4..8].try_into().unwrap()),
+            ack_seq: u32::from_le_bytes(data[8..12].try_into().unwrap()),
+            ack_age: u16::from_le_bytes(data[12..14].try_into().unwrap()),
+            flags: data[14],
+            held: data[15],
+            score1: u32::from_le_bytes(data[16..20].try_into().unwrap()),
+            score2: u32::from_le_bytes(data[20..24].try_into().unwrap()),
+            player1_x: f32::from_le_bytes(data[24..28].try_into().unwrap()),
+            player1_y: f32::from_le_bytes(data[28..32].try_into().unwrap()),
+            player1_cos: f32::from_le_bytes(data[32..36].try_into().unwrap()),
+            player1_sin: f32::from_le_bytes(data[36..40].try_into().unwrap()),
+            player1_vx: f32::from_le_bytes(data[40..44].try_into().unwrap()),
+            player1_vy: f32::from_le_bytes(data[44..48].try_into().unwrap()),
+            player1_spin: f32::from_le_bytes(data[48..52].try_into().unwrap()),
+            player1_fx: f32::from_le_bytes(data[52..56].try_into().unwrap()),
+            player1_fy: f32::from_le_bytes(data[56..60].try_into().unwrap()),
+            player2_x: f32::from_le_bytes(data[60..64].try_into().unwrap()),
+            player2_y: f32::from_le_bytes(data[64..68].try_into().unwrap()),
+            player2_cos: f32::from_le_bytes(data[68..72].try_into().unwrap()),
+            player2_sin: f32::from_le_bytes(data[72..76].try_into().unwrap()),
+            player2_vx: f32::from_le_bytes(data[76..80].try_into().unwrap()),
+            player2_vy: f32::from_le_bytes(data[80..84].try_into().unwrap()),
+            player2_spin: f32::from_le_bytes(data[84..88].try_into().unwrap()),
+            player2_fx: f32::from_le_bytes(data[88..92].try_into().unwrap()),
+            player2_fy: f32::from_le_bytes(data[92..96].try_into().unwrap()),
+            ball_x: f32::from_le_bytes(data[96..100].try_into().unwrap()),
+            ball_y: f32::from_le_bytes(data[100..104].try_into().unwrap()),
+            ball_cos: f32::from_le_bytes(data[104..108].try_into().unwrap()),
+            ball_sin: f32::from_le_bytes(data[108..112].try_into().unwrap()),
+            ball_vx: f32::from_le_bytes(data[112..116].try_into().unwrap()),
+            ball_vy: f32::from_le_bytes(data[116..120].try_into().unwrap()),
+            ball_spin: f32::from_le_bytes(data[120..124].try_into().unwrap()),
+            ball_fx: f32::from_le_bytes(data[124..128].try_into().unwrap()),
+            ball_fy: f32::from_le_bytes(data[128..132].try_into().unwrap()),
+            player1_gravity: f32::from_le_bytes(data[132..136].try_into().unwrap()),
+            player2_gravity: f32::from_le_bytes(data[136..140].try_into().unwrap()),
+            ball_gravity: f32::from_le_bytes(data[140..144].try_into().unwrap()),
+            reset_in: u32::from_le_bytes(data[144..148].try_into().unwrap()),
+            gravity_in: u32::from_le_bytes(data[148..152].try_into().unwrap()),
+        }
+    }
+
+    pub fn write(&self, packet: &mut [u8]) {
+        packet[4..8].copy_from_slice(&self.frame.to_le_bytes());
+        packet[8..12].copy_from_slice(&self.ack_seq.to_le_bytes());
+        packet[12..14].copy_from_slice(&self.ack_age.to_le_bytes());
+        packet[14] = self.flags;
+        packet[15] = self.held;
+        packet[16..20].copy_from_slice(&self.score1.to_le_bytes());
+        packet[20..24].copy_from_slice(&self.score2.to_le_bytes());
+        packet[24..28].copy_from_slice(&self.player1_x.to_le_bytes());
+        packet[28..32].copy_from_slice(&self.player1_y.to_le_bytes());
+        packet[32..36].copy_from_slice(&self.player1_cos.to_le_bytes());
+        packet[36..40].copy_from_slice(&self.player1_sin.to_le_bytes());
+        packet[40..44].copy_from_slice(&self.player1_vx.to_le_bytes());
+        packet[44..48].copy_from_slice(&self.player1_vy.to_le_bytes());
+        packet[48..52].copy_from_slice(&self.player1_spin.to_le_bytes());
+        packet[52..56].copy_from_slice(&self.player1_fx.to_le_bytes());
+        packet[56..60].copy_from_slice(&self.player1_fy.to_le_bytes());
+        packet[60..64].copy_from_slice(&self.player2_x.to_le_bytes());
+        packet[64..68].copy_from_slice(&self.player2_y.to_le_bytes());
+        packet[68..72].copy_from_slice(&self.player2_cos.to_le_bytes());
+        packet[72..76].copy_from_slice(&self.player2_sin.to_le_bytes());
+        packet[76..80].copy_from_slice(&self.player2_vx.to_le_bytes());
+        packet[80..84].copy_from_slice(&self.player2_vy.to_le_bytes());
+        packet[84..88].copy_from_slice(&self.player2_spin.to_le_bytes());
+        packet[88..92].copy_from_slice(&self.player2_fx.to_le_bytes());
+        packet[92..96].copy_from_slice(&self.player2_fy.to_le_bytes());
+        packet[96..100].copy_from_slice(&self.ball_x.to_le_bytes());
+        packet[100..104].copy_from_slice(&self.ball_y.to_le_bytes());
+        packet[104..108].copy_from_slice(&self.ball_cos.to_le_bytes());
+        packet[108..112].copy_from_slice(&self.ball_sin.to_le_bytes());
+        packet[112..116].copy_from_slice(&self.ball_vx.to_le_bytes());
+        packet[116..120].copy_from_slice(&self.ball_vy.to_le_bytes());
+        packet[120..124].copy_from_slice(&self.ball_spin.to_le_bytes());
+        packet[124..128].copy_from_slice(&self.ball_fx.to_le_bytes());
+        packet[128..132].copy_from_slice(&self.ball_fy.to_le_bytes());
+        packet[132..136].copy_from_slice(&self.player1_gravity.to_le_bytes());
+        packet[136..140].copy_from_slice(&self.player2_gravity.to_le_bytes());
+        packet[140..144].copy_from_slice(&self.ball_gravity.to_le_bytes());
+        packet[144..148].copy_from_slice(&self.reset_in.to_le_bytes());
+        packet[148..152].copy_from_slice(&self.gravity_in.to_le_bytes());
+    }
+}
+
+// over TCP, answered with the player id in 8 bytes
+pub fn encode_player_id_request() -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&PLAYER_ID_REQUEST);
+    packet
+}
+
+// over TCP, answered with the key for signing migrations in 8 bytes
+pub fn encode_migration_key_request() -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&MIGRATION_KEY_REQUEST);
+    packet
+}
+
+pub fn encode_game_request(fields: &Player) -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&GAME_REQUEST);
+    fields.write(&mut packet);
+    packet
+}
+
+// a game request back to the board saved by the last shutdown
+pub fn encode_resume_request(fields: &Resume) -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&RESUME_REQUEST);
+    fields.write(&mut packet);
+    packet
+}
+
+pub fn encode_left_pressed(fields: &Input) -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&LEFT_PRESSED);
+    fields.write(&mut packet);
+    packet
+}
+
+pub fn encode_left_released(fields: &Input) -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&LEFT_RELEASED);
+    fields.write(&mut packet);
+    packet
+}
+
+pub fn encode_right_pressed(fields: &Input) -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&RIGHT_PRESSED);
+    fields.write(&mut packet);
+    packet
+}
+
+pub fn encode_right_released(fields: &Input) -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&RIGHT_RELEASED);
+    fields.write(&mut packet);
+    packet
+}
+
+pub fn encode_jump(fields: &Input) -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&JUMP);
+    fields.write(&mut packet);
+    packet
+}
+
+// also the TCP keepalive, with all fields 0
+pub fn encode_ping(fields: &Ping) -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&PING);
+    fields.write(&mut packet);
+    packet
+}
+
+// the answer to a server ping
+pub fn encode_pong(fields: &Pong) -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&PONG);
+    fields.write(&mut packet);
+    packet
+}
+
+// a control message of a UDP-only session
+pub fn encode_session(fields: &Session) -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&SESSION);
+    fields.write(&mut packet);
+    packet
+}
+
+// the player is at the sender's address now
+pub fn encode_migrate(fields: &Migrate) -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&MIGRATE);
+    fields.write(&mut packet);
+    packet
+}
+
+// asks for the whole board after a checksum mismatch
+pub fn encode_resync_request(fields: &PlayerBoard) -> [u8; CLIENT_PACKET_LEN] {
+    let mut packet = [0; CLIENT_PACKET_LEN];
+    packet[..MAGIC.len()].copy_from_slice(&MAGIC);
+    packet[OPCODE_AT..OPCODE_AT + 2].copy_from_slice(&RESYNC_REQUEST);
+    fields.write(&mut packet);
+    packet
+}
+
+// the answer to a game request
+pub fn encode_ids(fields: &Ids) -> [u8; IDS_LEN] {
+    let mut packet = [0; IDS_LEN];
+    packet[..IDS_HEADER.len()].copy_from_slice(&IDS_HEADER);
+    fields.write(&mut packet);
+    packet
+}
+
+pub fn encode_session_reply(fields: &SessionReply) -> [u8; SESSION_REPLY_LEN] {
+    let mut packet = [0; SESSION_REPLY_LEN];
+    packet[..SESSION_REPLY_HEADER.len()].copy_from_slice(&SESSION_REPLY_HEADER);
+    fields.write(&mut packet);
+    packet
+}
+
+pub fn encode_server_pong(fields: &ServerPong) -> [u8; SERVER_PONG_LEN] {
+    let mut packet = [0; SERVER_PONG_LEN];
+    packet[..SERVER_PONG_HEADER.len()].copy_from_slice(&SERVER_PONG_HEADER);
+    fields.write(&mut packet);
+    packet
+}
+
+// to be echoed in a pong
+pub fn encode_server_ping(fields: &ServerPing) -> [u8; SERVER_PING_LEN] {
+    let mut packet = [0; SERVER_PING_LEN];
+    packet[..SERVER_PING_HEADER.len()].copy_from_slice(&SERVER_PING_HEADER);
+    fields.write(&mut packet);
+    packet
+}
+
+// over TCP when the server stops
+pub fn encode_shutdown_notice(fields: &ShutdownNotice) -> [u8; SHUTDOWN_NOTICE_LEN] {
+    let mut packet = [0; SHUTDOWN_NOTICE_LEN];
+    packet[..SHUTDOWN_NOTICE_HEADER.len()].copy_from_slice(&SHUTDOWN_NOTICE_HEADER);
+    fields.write(&mut packet);
+    packet
+}
+
+// a snapshot of the board, sent to each player
+pub fn encode_state(fields: &State) -> [u8; STATE_LEN] {
+    let mut packet = [0; STATE_LEN];
+    fields.write(&mut packet);
+    packet
+}
+
+// the answer to a resync request
+pub fn encode_board(fields: &Board) -> [u8; BOARD_LEN] {
+    let mut packet = [0; BOARD_LEN];
+    packet[..BOARD_HEADER.len()].copy_from_slice(&BOARD_HEADER);
+    fields.write(&mut packet);
+    packet
+}
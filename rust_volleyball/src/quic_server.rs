@@ -0,0 +1,265 @@
+use std::io::{Error, ErrorKind, Write};
+use std::net::UdpSocket;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use quinn::{Connection, Endpoint, EndpointConfig, TokioRuntime};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::pki_types::pem::PemObject;
+use crate::config::ServerConfig;
+use crate::flood::PacketFilter;
+use crate::metrics::METRICS;
+use crate::scheduler::Clock;
+use crate::server_logic::LogicSender;
+use crate::session::{Control, PlayerSession, SessionRoutes};
+
+// one QUIC connection per client instead of the TCP session and the UDP socket: the client opens a bidirectional stream
+// for what goes over TCP (id request, keepalive pings, shutdown notice), everything else travels in datagrams,
+// each packet byte for byte as on the native transports
+
+// TLS for the endpoint, a self-signed certificate for localhost is created on the first start if the files do not exist
+pub fn server_config(config: &ServerConfig) -> std::io::Result<quinn::ServerConfig> {
+    let (cert_path, key_path) = (&config.quic_cert, &config.quic_key);
+    if !cert_path.exists() && !key_path.exists() {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).map_err(Error::other)?;
+        std::fs::write(cert_path, generated.cert.pem())?;
+        write_private(key_path, generated.signing_key.serialize_pem().as_bytes())?;
+        log::warn!("Self-signed QUIC certificate for localhost written to {cert_path:?}, clients have to trust it");
+    }
+    let certs = CertificateDer::pem_file_iter(cert_path).and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| pem_error(cert_path, e))?;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| pem_error(key_path, e))?;
+    let mut server_config = quinn::ServerConfig::with_single_cert(certs, key).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    // the sessions evict silent players themselves, the connection only has to outlive them
+    let idle = config.idle_timeout() + Duration::from_secs(10);
+    let transport = Arc::get_mut(&mut server_config.transport).unwrap();
+    transport.max_idle_timeout(idle.try_into().ok());
+    Ok(server_config)
+}
+
+// only the server's user may read the file
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(data)
+}
+
+fn pem_error(path: &Path, e: rustls::pki_types::pem::Error) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{path:?}: {e}"))
+}
+
+pub fn start(socket: UdpSocket, quic_config: quinn::ServerConfig, sender: LogicSender, routes: SessionRoutes, filter: PacketFilter, clock: Arc<dyn Clock>, config: ServerConfig) {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(async {
+            // the socket is registered with the runtime it is used in
+            match Endpoint::new(EndpointConfig::default(), Some(quic_config), socket, Arc::new(TokioRuntime)) {
+                Ok(endpoint) => run(endpoint, sender, routes, filter, clock, config).await,
+                Err(e) => log::error!("Cannot start the QUIC endpoint, {e}"),
+            }
+        });
+    log::error!("QUIC server stopped");
+}
+
+async fn run(endpoint: Endpoint, sender: LogicSender, routes: SessionRoutes, filter: PacketFilter, clock: Arc<dyn Clock>, config: ServerConfig) {
+    let idle_timeout = config.idle_timeout();
+    // the client opens the control stream right after connecting
+    let handshake_timeout = config.handshake_timeout();
+
+    while let Some(incoming) = endpoint.accept().await {
+        let logic_sender = sender.clone();
+        let routes = routes.clone();
+        let filter = filter.clone();
+        let clock = clock.clone();
+        tokio::spawn(async move {
+            let connection = match incoming.await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    log::warn!("QUIC handshake failed, {e}");
+                    return;
+                }
+            };
+            let c = METRICS.quic_sessions.fetch_add(1, Ordering::Relaxed) + 1;
+            log::debug!("QUIC connection, counter: {c}");
+            handle_connection(connection, logic_sender, routes, filter, clock, idle_timeout, handshake_timeout).await;
+            let c = METRICS.quic_sessions.fetch_sub(1, Ordering::Relaxed) - 1;
+            log::debug!("QUIC disconnection, counter: {c}");
+        });
+    }
+}
+
+async fn handle_connection(connection: Connection, logic_sender: LogicSender, routes: SessionRoutes, filter: PacketFilter, clock: Arc<dyn Clock>, idle_timeout: Duration, handshake_timeout: Duration) {
+    let peer = connection.remote_address();
+    let (mut control_send, mut control_recv) = match tokio::time::timeout(handshake_timeout, connection.accept_bi()).await {
+        Ok(Ok(streams)) => streams,
+        Ok(Err(e)) => {
+            log::debug!("QUIC connection from {peer} closed before the control stream, {e}");
+            return;
+        }
+        Err(_) => {
+            log::debug!("No control stream from {peer}, closing");
+            connection.close(0u32.into(), b"no control stream");
+            return;
+        }
+    };
+    // read_exact loses what it read when cancelled, the stream is read in a task of its own
+    let (control_sender, mut control) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut buf = [0; 32];
+        while control_recv.read_exact(&mut buf).await.is_ok() && control_sender.send(buf).is_ok() {}
+    });
+
+    let mut ping_timer = tokio::time::interval(Duration::from_secs(10));
+    let Some((mut session, mut receiver, mut packets)) = PlayerSession::open("quic", peer, &routes, &filter, logic_sender, clock) else {
+        return;
+    };
+    let player_id = session.player_id;
+    log::info!("QUIC connection accepted: {peer:?}, player_id: {player_id}, session address: {}", session.addr);
+    loop {
+        // (packet, reliable)
+        let outgoing = tokio::select! {
+            _ = ping_timer.tick() => {
+                if session.idle(idle_timeout) {
+                    log::debug!("No ping, disconnect, {player_id}");
+                    session.disconnect();
+                    break;
+                }
+                None
+            }
+            Some(ch_recv) = receiver.recv() => match session.control(ch_recv) {
+                Control::Continue => None,
+                Control::Send(packet) => Some((packet, true)),
+                Control::Close => break,
+            },
+            Some(packet) = packets.recv() => Some((packet, false)),
+            packet = control.recv() => match packet {
+                Some(packet) => session.receive(&packet).map(|answer| (answer, true)),
+                None => {
+                    log::debug!("Control stream closed, {player_id}");
+                    session.disconnect();
+                    break;
+                }
+            },
+            datagram = connection.read_datagram() => match datagram {
+                Ok(data) => session.receive(&data).map(|answer| (answer, false)),
+                Err(e) => {
+                    log::debug!("Connection closed, {player_id}, {e}");
+                    session.disconnect();
+                    break;
+                }
+            },
+        };
+        match outgoing {
+            Some((packet, true)) => if let Err(e) = control_send.write_all(&packet).await {
+                log::warn!("Cannot send on the QUIC control stream, {player_id}, error: {e}");
+                session.disconnect();
+                break;
+            }
+            // like a UDP send, a datagram that does not fit or finds the connection gone is simply lost
+            Some((packet, false)) => if let Err(e) = connection.send_datagram(packet.into()) {
+                log::debug!("Cannot send QUIC datagram, {player_id}, error: {e}");
+            }
+            None => {}
+        }
+    }
+    drop(session);
+    connection.close(0u32.into(), b"bye");
+    log::info!("QUIC task finished");
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use quinn::{Connection, Endpoint, RecvStream, SendStream};
+    use rustls::RootCertStore;
+    use rustls::pki_types::CertificateDer;
+    use rustls::pki_types::pem::PemObject;
+    use crate::config::ServerConfig;
+    use crate::scheduler::SystemClock;
+    use crate::server::Server;
+    use crate::transport::MemoryNetwork;
+    use crate::udp_server::{encode_packet, parse_server_packet, PacketMsg, ServerPacket};
+
+    async fn next_datagram(connection: &Connection) -> ServerPacket {
+        let data = tokio::time::timeout(Duration::from_secs(5), connection.read_datagram()).await.unwrap().unwrap();
+        parse_server_packet(&data).unwrap()
+    }
+
+    // connects, opens the control stream and gets the player id over it
+    async fn join(client: &Endpoint, server: SocketAddr) -> (Connection, SendStream, RecvStream, u64) {
+        let connection = client.connect(server, "localhost").unwrap().await.unwrap();
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        send.write_all(&encode_packet(&PacketMsg::PlayerIdRequest)).await.unwrap();
+        let mut id = [0; 8];
+        recv.read_exact(&mut id).await.unwrap();
+        (connection, send, recv, u64::from_le_bytes(id))
+    }
+
+    #[test]
+    fn test_quic_players() {
+        let network = MemoryNetwork::new(4);
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+        let config = ServerConfig {
+            workers: 1,
+            snapshot_path: dir.join(format!("volleyball_quic_{id}.bin")),
+            quic_cert: dir.join(format!("volleyball_quic_cert_{id}.pem")),
+            quic_key: dir.join(format!("volleyball_quic_key_{id}.pem")),
+            ..Default::default()
+        };
+        let server = Server::start(config.clone(), network.bind(network.next_addr()).unwrap(), network.listen(network.next_addr()).unwrap(),
+            Arc::new(SystemClock::new())).unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        server.serve_quic(socket).unwrap();
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&config.quic_key).unwrap().permissions()) & 0o777, 0o600);
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            // the client trusts the certificate the server created
+            let mut roots = RootCertStore::empty();
+            roots.add(CertificateDer::from_pem_file(&config.quic_cert).unwrap()).unwrap();
+            let mut client = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+            client.set_default_client_config(quinn::ClientConfig::with_root_certificates(Arc::new(roots)).unwrap());
+
+            let (first, _first_send, _first_recv, first_id) = join(&client, server_addr).await;
+            let (second, _second_send, _second_recv, second_id) = join(&client, server_addr).await;
+            assert_ne!(first_id, second_id);
+            let mut board = None;
+            for (connection, player_id) in [(&first, first_id), (&second, second_id)] {
+                connection.send_datagram(encode_packet(&PacketMsg::GameRequest(player_id)).to_vec().into()).unwrap();
+                let ServerPacket::Ids(id, board_id) = next_datagram(connection).await else { panic!("no ids") };
+                assert_eq!(id, player_id);
+                assert!(board.is_none_or(|b| b == board_id));
+                board = Some(board_id);
+            }
+
+            // snapshots and pongs come in datagrams
+            loop {
+                if let ServerPacket::State(state) = next_datagram(&second).await {
+                    assert!(!state.own.is_player1);
+                    break;
+                }
+            }
+            first.send_datagram(encode_packet(&PacketMsg::Ping(first_id, board.unwrap(), 99)).to_vec().into()).unwrap();
+            loop {
+                match next_datagram(&first).await {
+                    ServerPacket::Pong(99, _) => break,
+                    ServerPacket::State(_) | ServerPacket::Ping(_) => {}
+                    other => panic!("unexpected packet {other:?}"),
+                }
+            }
+        });
+        let _ = std::fs::remove_file(&config.quic_cert);
+        let _ = std::fs::remove_file(&config.quic_key);
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// a message not acknowledged within that time is sent again, with every message after it
+const RESEND: Duration = Duration::from_millis(200);
+
+// reliable, ordered delivery of the few control messages over datagrams: each message is resent until the other side
+// acknowledges it, the receiving side only takes the next message in sequence and acknowledges the last one it took
+pub struct ReliableChannel<T> {
+    next_seq: u32,
+    // (seq, message, when it was last sent)
+    unacked: VecDeque<(u32, T, Option<Instant>)>,
+    // the newest message taken from the other side, messages start at 1
+    received: u32,
+    // something arrived that the other side needs an acknowledgement for
+    ack_pending: bool,
+}
+
+impl<T: Copy> Default for ReliableChannel<T> {
+    fn default() -> Self {
+        ReliableChannel { next_seq: 1, unacked: VecDeque::new(), received: 0, ack_pending: false }
+    }
+}
+
+impl<T: Copy> ReliableChannel<T> {
+    pub fn send(&mut self, msg: T) {
+        self.unacked.push_back((self.next_seq, msg, None));
+        self.next_seq += 1;
+    }
+
+    // a packet from the other side with its seq (0 for an acknowledgement only), returns the message if it was the next one;
+    // the other side's messages may be of another type than the own
+    pub fn receive<R>(&mut self, seq: u32, ack: u32, msg: Option<R>) -> Option<R> {
+        while self.unacked.front().is_some_and(|(s, ..)| *s <= ack) {
+            self.unacked.pop_front();
+        }
+        if seq == 0 {
+            return None;
+        }
+        // repeats and messages after a gap are acknowledged again, the other side resends from the gap
+        self.ack_pending = true;
+        if seq != self.received + 1 {
+            return None;
+        }
+        self.received = seq;
+        msg
+    }
+
+    // what to put on the wire now: (seq, message) pairs, (0, None) for an acknowledgement without a message;
+    // every packet carries ack() as well
+    pub fn outgoing(&mut self, now: Instant) -> Vec<(u32, Option<T>)> {
+        let resend = self.unacked.front().is_some_and(|(_, _, sent)| sent.is_none_or(|sent| now - sent >= RESEND));
+        let mut packets = vec![];
+        for (seq, msg, sent) in self.unacked.iter_mut() {
+            if resend || sent.is_none() {
+                *sent = Some(now);
+                packets.push((*seq, Some(*msg)));
+            }
+        }
+        if packets.is_empty() && self.ack_pending {
+            packets.push((0, None));
+        }
+        self.ack_pending = false;
+        packets
+    }
+
+    pub fn ack(&self) -> u32 {
+        self.received
+    }
+
+    // everything sent was acknowledged
+    pub fn is_idle(&self) -> bool {
+        self.unacked.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+    use crate::reliable::ReliableChannel;
+
+    #[test]
+    fn test_reliable_channel() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let (mut left, mut right) = (ReliableChannel::default(), ReliableChannel::default());
+        for i in 0..20u32 {
+            left.send(i);
+        }
+        right.send(100);
+        let mut now = Instant::now();
+        let (mut got_right, mut got_left) = (vec![], vec![]);
+        // half of the packets lost, the rest arrive shuffled
+        for _ in 0..200 {
+            now += Duration::from_millis(50);
+            let mut to_right: Vec<_> = left.outgoing(now).into_iter().map(|(seq, msg)| (seq, left.ack(), msg)).filter(|_| rng.random_bool(0.5)).collect();
+            let mut to_left: Vec<_> = right.outgoing(now).into_iter().map(|(seq, msg)| (seq, right.ack(), msg)).filter(|_| rng.random_bool(0.5)).collect();
+            if rng.random_bool(0.5) {
+                to_right.reverse();
+                to_left.reverse();
+            }
+            got_right.extend(to_right.into_iter().filter_map(|(seq, ack, msg)| right.receive(seq, ack, msg)));
+            got_left.extend(to_left.into_iter().filter_map(|(seq, ack, msg)| left.receive(seq, ack, msg)));
+        }
+        assert_eq!(got_right, (0..20).collect::<Vec<_>>());
+        assert_eq!(got_left, vec![100]);
+        assert!(left.is_idle() && right.is_idle());
+        // nothing left to send once both sides are acknowledged
+        now += Duration::from_secs(1);
+        assert!(left.outgoing(now).is_empty() && right.outgoing(now).is_empty());
+    }
+}
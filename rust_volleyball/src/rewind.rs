@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+use crate::{GameSnapshot, GameState};
+use crate::prediction::apply;
+use crate::udp_server::Key;
+
+// applies late inputs at the frame the player saw, by going back to a saved snapshot and simulating the frames again
+pub struct RewindBuffer {
+    // how far back an input may reach, the opponent's view of those frames changes after the fact
+    max_frames: u64,
+    // one snapshot per frame, taken right after the step and before the inputs of that frame
+    history: VecDeque<GameSnapshot>,
+    // inputs of the frames in the history: frame, is player 1, key
+    inputs: VecDeque<(u64, bool, Key)>,
+}
+
+impl RewindBuffer {
+    pub fn new(max_frames: u64) -> RewindBuffer {
+        RewindBuffer { max_frames, history: VecDeque::new(), inputs: VecDeque::new() }
+    }
+
+    pub fn step(&mut self, state: &mut GameState) {
+        state.step_frame();
+        self.record(state);
+    }
+
+    // after the state was replaced, e.g. by a new board or a restored one
+    pub fn reset(&mut self, state: &GameState) {
+        self.history.clear();
+        self.inputs.clear();
+        self.record(state);
+    }
+
+    // intended is the frame the input was meant for, None or a frame too far back or ahead is clamped to what the history allows;
+    // returns the frame the input was applied at
+    pub fn apply(&mut self, state: &mut GameState, is_player1: bool, key: Key, intended: Option<u64>) -> u64 {
+        let current = state.frame();
+        let frame = intended.unwrap_or(current).min(current);
+        let found = self.history.iter().position(|s| s.frame() >= frame).filter(|_| frame < current);
+        let Some(index) = found else {
+            apply(state, key, is_player1);
+            self.inputs.push_back((current, is_player1, key));
+            return current;
+        };
+        let snapshot = &self.history[index];
+        let frame = snapshot.frame();
+        state.restore(snapshot);
+        self.history.truncate(index + 1);
+        self.inputs.push_back((frame, is_player1, key));
+        loop {
+            let at = state.frame();
+            for &(_, player, key) in self.inputs.iter().filter(|(f, ..)| *f == at) {
+                apply(state, key, player);
+            }
+            if at == current {
+                return frame;
+            }
+            state.step_frame();
+            self.history.push_back(state.snapshot());
+        }
+    }
+
+    fn record(&mut self, state: &GameState) {
+        if self.max_frames == 0 {
+            return;
+        }
+        self.history.push_back(state.snapshot());
+        while self.history.len() as u64 > self.max_frames + 1 {
+            self.history.pop_front();
+        }
+        let oldest = self.history.front().map_or(0, |s| s.frame());
+        while self.inputs.front().is_some_and(|(f, ..)| *f < oldest) {
+            self.inputs.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{GameSnapshot, GameState};
+    use crate::prediction::apply;
+    use crate::rewind::RewindBuffer;
+    use crate::udp_server::Key;
+
+    // the same board if every input had arrived on time
+    fn on_time(start: &GameSnapshot, frames: u64, inputs: &[(u64, bool, Key)]) -> GameState {
+        let mut state = GameState::from_snapshot(start);
+        for frame in 0..frames {
+            for &(_, player, key) in inputs.iter().filter(|(f, ..)| *f == frame) {
+                apply(&mut state, key, player);
+            }
+            state.step_frame();
+        }
+        state
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut state = GameState::new();
+        // the serve side is random
+        let start = state.snapshot();
+        let mut rewind = RewindBuffer::new(6);
+        rewind.reset(&state);
+        state.add_force(true, true);
+        for _ in 0..40 {
+            rewind.step(&mut state);
+        }
+        // meant for frame 36, arrives at 40 after the opponent's input of frame 40, which stays where it was
+        assert_eq!(rewind.apply(&mut state, false, Key::Right(true), None), 40);
+        assert_eq!(rewind.apply(&mut state, true, Key::Jump, Some(36)), 36);
+        for _ in 0..20 {
+            rewind.step(&mut state);
+        }
+        let expected = on_time(&start, 60, &[(0, true, Key::Right(true)), (36, true, Key::Jump), (40, false, Key::Right(true))]);
+        assert_eq!(state.players(), expected.players());
+        assert_eq!(state.ball(), expected.ball());
+
+        // no further back than the cap, and never into the future
+        assert_eq!(rewind.apply(&mut state, false, Key::Jump, Some(10)), 54);
+        assert_eq!(rewind.apply(&mut state, true, Key::Left(true), Some(1000)), 60);
+        for _ in 0..10 {
+            rewind.step(&mut state);
+        }
+        let expected = on_time(&start, 70, &[(0, true, Key::Right(true)), (36, true, Key::Jump), (40, false, Key::Right(true)),
+            (54, false, Key::Jump), (60, true, Key::Left(true))]);
+        assert_eq!(state.players(), expected.players());
+
+        // without a history every input lands on the current frame
+        let mut off = RewindBuffer::new(0);
+        off.reset(&state);
+        assert_eq!(off.apply(&mut state, true, Key::Jump, Some(65)), 70);
+    }
+}
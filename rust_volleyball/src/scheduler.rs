@@ -0,0 +1,191 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use crate::metrics::METRICS;
+use crate::server_logic::{LogicMessage, LogicSender};
+
+// a board that fell further behind than that drops the missing frames, the worker must not stall on one board
+pub const MAX_CATCH_UP: u64 = 5;
+
+// time since the clock started, every deadline below is a point on a fixed grid of that time line
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Duration;
+    fn sleep_until(&self, deadline: Duration);
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock { start: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep_until(&self, deadline: Duration) {
+        if let Some(remaining) = deadline.checked_sub(self.now()) {
+            std::thread::sleep(remaining);
+        }
+    }
+}
+
+// only moves when told to, sleeping jumps straight to the deadline
+#[derive(Default)]
+pub struct VirtualClock {
+    now: Mutex<Duration>,
+}
+
+impl VirtualClock {
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep_until(&self, deadline: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now = (*now).max(deadline);
+    }
+}
+
+// i-th point of a grid with `rate` points per second, computed from the index so grids of different rates line up
+// exactly, e.g. every other 120 Hz tick is a 60 Hz frame
+fn deadline(index: u64, rate: u32) -> Duration {
+    Duration::from_nanos((index as u128 * 1_000_000_000).div_ceil(rate as u128) as u64)
+}
+
+// last grid point at or before now
+fn last_index(now: Duration, rate: u32) -> u64 {
+    (now.as_nanos() * rate as u128 / 1_000_000_000) as u64
+}
+
+// first grid point at or after now
+fn first_index(now: Duration, rate: u32) -> u64 {
+    (now.as_nanos() * rate as u128).div_ceil(1_000_000_000) as u64
+}
+
+// when a board simulates its next frame and sends its next snapshot, both at their own fixed rate
+pub struct BoardSchedule {
+    frame_rate: u32,
+    send_rate: u32,
+    next_frame: u64,
+    next_send: u64,
+    // (scheduled, actual) time of the previous snapshot
+    last_send: Option<(Duration, Duration)>,
+}
+
+impl BoardSchedule {
+    pub fn new(frame_rate: u32, send_rate: u32, now: Duration) -> BoardSchedule {
+        BoardSchedule {
+            frame_rate,
+            send_rate,
+            next_frame: first_index(now, frame_rate),
+            next_send: first_index(now, send_rate),
+            last_send: None,
+        }
+    }
+
+    // number of frames to simulate now
+    pub fn frames_due(&mut self, now: Duration) -> u64 {
+        let last = last_index(now, self.frame_rate);
+        if last < self.next_frame {
+            return 0;
+        }
+        let due = last + 1 - self.next_frame;
+        self.next_frame = last + 1;
+        if due > MAX_CATCH_UP {
+            METRICS.frames_skipped.fetch_add(due - MAX_CATCH_UP, Ordering::Relaxed);
+        }
+        due.min(MAX_CATCH_UP)
+    }
+
+    // Some when a snapshot is due, with how far the time since the previous snapshot is off its nominal interval
+    pub fn send_due(&mut self, now: Duration) -> Option<Duration> {
+        let last = last_index(now, self.send_rate);
+        if last < self.next_send {
+            return None;
+        }
+        self.next_send = last + 1;
+        let scheduled = deadline(last, self.send_rate);
+        let jitter = match self.last_send {
+            None => Duration::ZERO,
+            Some((previous_scheduled, previous_sent)) => (now - previous_sent).abs_diff(scheduled - previous_scheduled),
+        };
+        self.last_send = Some((scheduled, now));
+        Some(jitter)
+    }
+}
+
+// wakes the game logic on a fixed grid, boards keep their own deadlines so the tick only has to be frequent enough
+pub fn start(logic_sender: LogicSender, tick_rate: u32, clock: Arc<dyn Clock>) {
+    let mut tick = first_index(clock.now(), tick_rate);
+    loop {
+        clock.sleep_until(deadline(tick, tick_rate));
+        // ticks missed during a stall are not sent in a burst, the boards catch up on their own
+        tick = last_index(clock.now(), tick_rate) + 1;
+        if let Err(e) = logic_sender.send(LogicMessage::CalculateBoard) {
+            log::error!("Cannot send GameLogic tick, {e}");
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use crate::scheduler::{deadline, BoardSchedule, Clock, VirtualClock, MAX_CATCH_UP};
+
+    // ticks at tick_rate for a second, the way start() does, and returns (frames simulated, snapshots sent, max jitter)
+    fn run(tick_rate: u32, frame_rate: u32, send_rate: u32) -> (u64, u64, Duration) {
+        let clock = VirtualClock::default();
+        clock.advance(Duration::from_millis(7));
+        let mut schedule = BoardSchedule::new(frame_rate, send_rate, clock.now());
+        let (mut frames, mut sends, mut max_jitter) = (0, 0, Duration::ZERO);
+        for tick in 1..=tick_rate as u64 {
+            clock.sleep_until(deadline(tick, tick_rate));
+            frames += schedule.frames_due(clock.now());
+            if let Some(jitter) = schedule.send_due(clock.now()) {
+                sends += 1;
+                max_jitter = max_jitter.max(jitter);
+            }
+        }
+        (frames, sends, max_jitter)
+    }
+
+    #[test]
+    fn test_board_schedule() {
+        // frame and send rates divide the tick rate, snapshots go out exactly on time
+        assert_eq!(run(120, 60, 30), (60, 30, Duration::ZERO));
+        assert_eq!(run(120, 60, 60), (60, 60, Duration::ZERO));
+        // they do not, the rates still hold but snapshots are off by up to one tick
+        let (frames, sends, max_jitter) = run(100, 60, 30);
+        assert!((59..=61).contains(&frames));
+        assert!((29..=31).contains(&sends));
+        assert!(max_jitter > Duration::ZERO && max_jitter <= Duration::from_millis(10));
+
+        let clock = VirtualClock::default();
+        let mut schedule = BoardSchedule::new(60, 30, clock.now());
+        assert_eq!(schedule.frames_due(clock.now()), 1);
+        assert_eq!(schedule.frames_due(clock.now()), 0);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(schedule.frames_due(clock.now()), MAX_CATCH_UP);
+        clock.advance(Duration::from_millis(17));
+        assert_eq!(schedule.frames_due(clock.now()), 1);
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::Arc;
+use std::sync::mpsc::channel;
+use std::thread::{spawn, JoinHandle};
+use crate::config::ServerConfig;
+use crate::flood::{BlockList, PacketFilter};
+use crate::netsim::ShapedSocket;
+use crate::packet_dump::DUMPS;
+use crate::scheduler::Clock;
+use crate::server_logic::LogicSender;
+use crate::transport::{DatagramSocket, StreamListener, TcpStreamListener};
+use crate::{scheduler, server_logic, tcp_server, udp_server, udp_session};
+use crate::session::{RoutedSocket, SessionRoutes};
+#[cfg(feature = "websocket")]
+use crate::ws_server;
+#[cfg(feature = "quic")]
+use crate::quic_server;
+
+// the game server without the process level parts (admin console, metrics, signals), over any transport
+pub struct Server {
+    pub logic_sender: LogicSender,
+    // addresses the UDP receiver and the sessions drop everything from, for the admin console
+    pub blocklist: BlockList,
+    logic: JoinHandle<()>,
+    // what the connection based transports need, they may be started after the rest
+    #[cfg(any(feature = "websocket", feature = "quic"))]
+    sessions: (SessionRoutes, PacketFilter, Arc<dyn Clock>, ServerConfig),
+}
+
+impl Server {
+    pub fn start(config: ServerConfig, socket: impl DatagramSocket, listener: impl StreamListener, clock: Arc<dyn Clock>) -> std::io::Result<Server> {
+        Self::shape(config, socket, Some(listener), clock)
+    }
+
+    // without the TCP listener, the clients open their sessions on the UDP socket
+    pub fn start_udp_only(config: ServerConfig, socket: impl DatagramSocket, clock: Arc<dyn Clock>) -> std::io::Result<Server> {
+        Self::shape(config, socket, None::<TcpStreamListener>, clock)
+    }
+
+    fn shape(config: ServerConfig, socket: impl DatagramSocket, listener: Option<impl StreamListener>, clock: Arc<dyn Clock>) -> std::io::Result<Server> {
+        match config.netsim {
+            Some(conditions) => {
+                log::warn!("Network simulator on the UDP socket, {conditions}");
+                Self::spawn(config, ShapedSocket::new(socket, conditions)?, listener, clock)
+            }
+            None => Self::spawn(config, socket, listener, clock),
+        }
+    }
+
+    fn spawn(config: ServerConfig, socket: impl DatagramSocket, listener: Option<impl StreamListener>, clock: Arc<dyn Clock>) -> std::io::Result<Server> {
+        DUMPS.enable(config.dump_packets);
+        let socket_sender = socket.try_clone()?;
+        // state for players on a WebSocket or QUIC connection leaves through the same sender, it knows them by address
+        let routes = SessionRoutes::default();
+        let socket_sender = RoutedSocket::new(socket_sender, routes.clone());
+        let (logic_sender, logic_receiver) = server_logic::channel(config.logic_queue);
+        let (udp_sender, udp_receiver) = channel();
+        let (session_sender, session_receiver) = udp_session::channel();
+
+        spawn(move || udp_server::start_sender(socket_sender, udp_receiver));
+        let udp_logic_sender = logic_sender.clone();
+        let udp_clock = clock.clone();
+        let session_socket = socket.try_clone()?;
+        let blocklist = BlockList::default();
+        let filter = PacketFilter::new(&config, blocklist.clone());
+        let udp_filter = filter.clone();
+        spawn(move || udp_server::start(socket, udp_logic_sender, session_sender, udp_filter, udp_clock));
+        let session_logic_sender = logic_sender.clone();
+        let session_config = config.clone();
+        spawn(move || udp_session::start(session_socket, session_receiver, session_logic_sender, session_config));
+        if let Some(listener) = listener {
+            let tcp_logic_sender = logic_sender.clone();
+            let tcp_config = config.clone();
+            spawn(move || tcp_server::start(listener, tcp_logic_sender, tcp_config));
+        }
+        let scheduler_logic_sender = logic_sender.clone();
+        let scheduler_clock = clock.clone();
+        let tick_rate = config.tick_rate;
+        spawn(move || scheduler::start(scheduler_logic_sender, tick_rate, scheduler_clock));
+        #[cfg(any(feature = "websocket", feature = "quic"))]
+        let sessions = (routes.clone(), filter, clock.clone(), config.clone());
+        let worker_logic_sender = logic_sender.clone();
+        let logic = spawn(move || server_logic::start(worker_logic_sender, logic_receiver, udp_sender, routes, clock, config));
+        Ok(Server {
+            logic_sender,
+            blocklist,
+            logic,
+            #[cfg(any(feature = "websocket", feature = "quic"))]
+            sessions,
+        })
+    }
+
+    // browser players, matched against the native ones on the same boards
+    #[cfg(feature = "websocket")]
+    pub fn serve_websocket(&self, listener: impl StreamListener) {
+        let (routes, filter, clock, config) = self.sessions.clone();
+        let logic_sender = self.logic_sender.clone();
+        spawn(move || ws_server::start(listener, logic_sender, routes, filter, clock, config));
+    }
+
+    // QUIC clients on their own UDP socket, fails if the TLS certificate cannot be loaded or created
+    #[cfg(feature = "quic")]
+    pub fn serve_quic(&self, socket: std::net::UdpSocket) -> std::io::Result<()> {
+        let (routes, filter, clock, config) = self.sessions.clone();
+        let quic_config = quic_server::server_config(&config)?;
+        let logic_sender = self.logic_sender.clone();
+        spawn(move || quic_server::start(socket, quic_config, logic_sender, routes, filter, clock, config));
+        Ok(())
+    }
+
+    // the network threads run until the process ends, the game logic decides when the server is done
+    pub fn join(self) {
+        if self.logic.join().is_err() {
+            log::error!("Game logic thread panicked");
+        }
+    }
+}
@@ -1,23 +1,83 @@
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{Receiver, SendError, Sender, TrySendError};
 use std::time::{Duration, Instant};
-use log::{debug, error};
+use log::error;
 use rand::Rng;
 use tokio::sync::mpsc::UnboundedSender;
-use crate::GameState;
+use tokio::sync::oneshot;
+use crate::{BoardState, GameState};
+use crate::config::ServerConfig;
+use crate::admin::{AdminQuery, AdminReply, PlayerInfo};
+use crate::board_worker::{WorkerMessage, WorkerPool};
+use crate::clock_sync::{micros, ClockSync};
+use crate::metrics::METRICS;
+use crate::migration;
+use crate::scheduler::Clock;
+use crate::session::SessionRoutes;
+use crate::shutdown::{load_boards, save_boards, SavedBoard};
 use crate::tcp_server::TcpMessage;
-use crate::udp_server::Key::Jump;
-use crate::udp_server::{Key, MsgIn, SenderMsg};
+use crate::udp_server::{MsgIn, SenderMsg};
 
 pub enum LogicMessage {
     CalculateBoard,
     PlayerMsg(SocketAddr, MsgIn),
     SetChannel(u64, UnboundedSender<TcpMessage>),
     Disconnect(u64, Option<u64>),
+    // a ping over the player's own connection (TCP or a session), unlike a UDP ping it needs no address check
+    Alive(u64),
+    Admin(AdminQuery, oneshot::Sender<AdminReply>),
+    // sent once by the board worker when a game on that board ends
+    GameOver(u64),
+    Shutdown,
 }
 
-#[derive(Copy, Clone)]
+// counts queued messages so the logic thread backlog can be exported as a metric
+#[derive(Clone)]
+pub struct LogicSender {
+    sender: Sender<LogicMessage>,
+    // backlog above which packets from the network are dropped, messages of the server itself are always queued
+    capacity: usize,
+}
+
+impl LogicSender {
+    pub fn send(&self, msg: LogicMessage) -> Result<(), SendError<LogicMessage>> {
+        METRICS.logic_backlog.fetch_add(1, Ordering::Relaxed);
+        self.sender.send(msg).inspect_err(|_| {
+            METRICS.logic_backlog.fetch_sub(1, Ordering::Relaxed);
+        })
+    }
+
+    // for player messages, a flood must not pile up in front of the game logic
+    pub fn try_send(&self, msg: LogicMessage) -> Result<(), TrySendError<LogicMessage>> {
+        if METRICS.logic_backlog.load(Ordering::Relaxed) >= self.capacity as i64 {
+            return Err(TrySendError::Full(msg));
+        }
+        self.send(msg).map_err(|SendError(msg)| TrySendError::Disconnected(msg))
+    }
+
+    // a player packet, dropped and counted if the game logic is too far behind
+    pub fn send_packet(&self, addr: SocketAddr, msg: MsgIn) {
+        self.send_player(LogicMessage::PlayerMsg(addr, msg));
+    }
+
+    pub fn send_player(&self, msg: LogicMessage) {
+        match self.try_send(msg) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => METRICS.dropped("logic_queue_full"),
+            Err(TrySendError::Disconnected(_)) => log::error!("Cannot send player message, game logic stopped"),
+        }
+    }
+}
+
+pub fn channel(capacity: usize) -> (LogicSender, Receiver<LogicMessage>) {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    (LogicSender { sender, capacity }, receiver)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct GameStateSerialized {
     pub ball_pos: (f32, f32),
     pub ball_radius: f32,
@@ -28,101 +88,483 @@ pub struct GameStateSerialized {
     pub score1: u32,
     pub score2: u32,
     pub game_over: bool,
+    pub ball_velocity: (f32, f32),
+    pub ball_gravity: bool,
+    // differs per recipient, the rest is the same for both players of a board
+    pub own: OwnPlayer,
+    // the board frame the snapshot was taken at, clients stamp their inputs with an estimate of it
+    pub frame: u64,
+    // GameState::snapshot_checksum for the receiving player at that frame, only in some of the snapshots
+    pub checksum: Option<u32>,
 }
 
-pub fn start(logic_sender: Sender<LogicMessage>, logic_receiver: Receiver<LogicMessage>, udp_sender: Sender<SenderMsg>) {
+impl GameStateSerialized {
+    pub fn new(state: &GameState) -> GameStateSerialized {
+        let (bx, by, br) = state.ball();
+        let (p1x, p1y, p1r, p2x, p2y, _p2r) = state.players();
+        let (score1, score2, game_over) = state.points();
+        let (ball_velocity, ball_gravity) = state.ball_motion();
+        GameStateSerialized {
+            ball_pos: (bx, by),
+            ball_radius: br,
+            player_radius: p1r,
+            player1_pos: (p1x, p1y),
+            player2_pos: (p2x, p2y),
+            score1,
+            score2,
+            game_over,
+            ball_velocity,
+            ball_gravity,
+            own: OwnPlayer::default(),
+            frame: state.frame(),
+            checksum: None,
+        }
+    }
+}
+
+// what a predicting client needs about its own player to replay its newer inputs on top of the snapshot
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct OwnPlayer {
+    pub is_player1: bool,
+    pub velocity: (f32, f32),
+    // [left, right] as the server sees them, it releases both after every point
+    pub held: [bool; 2],
+    // the newest input the server applied, 0 before the first one
+    pub ack_seq: u32,
+    // frames simulated since then
+    pub ack_age: u16,
+}
+
+impl OwnPlayer {
+    pub fn new(state: &GameState, is_player1: bool, ack_seq: u32, ack_age: u16) -> OwnPlayer {
+        let (velocity, held) = state.player_motion(is_player1);
+        OwnPlayer { is_player1, velocity, held, ack_seq, ack_age }
+    }
+}
+
+// the answer to a resync request, the acknowledgement places it on the client's timeline like a snapshot
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ResyncState {
+    pub board: BoardState,
+    pub frame: u64,
+    pub ack_seq: u32,
+    pub ack_age: u16,
+}
+
+// how often idle players are looked for, the idle timeout itself comes from the config
+const LIVENESS_CHECK: Duration = Duration::from_secs(1);
+
+// the routes tell the host behind a WebSocket or QUIC session address
+pub fn start(logic_sender: LogicSender, logic_receiver: Receiver<LogicMessage>, udp_sender: Sender<SenderMsg>, routes: SessionRoutes, clock: Arc<dyn Clock>, config: ServerConfig) {
+    let idle_timeout = config.idle_timeout();
     let mut player_in_lobby: Option<(u64, u64)> = None;
-    let mut boards: HashMap<u64, (u64, u64, GameState)> = HashMap::new();
+    // the boards themselves live in the worker threads, only their players are known here
+    let pool = WorkerPool::start(config.workers, logic_sender, udp_sender.clone(), clock.clone(), config.send_rate, config.max_rewind());
+    let mut boards: HashMap<u64, (u64, u64)> = HashMap::new();
+    let mut finished: HashSet<u64> = HashSet::new();
+    // matched players waiting for a free board under the board limit: board id, player1, player2
+    let mut pending: VecDeque<(u64, u64, u64)> = VecDeque::new();
     let mut player_channels: HashMap<u64, UnboundedSender<TcpMessage>> = HashMap::new();
+    // last time anything was heard from a player, over TCP or UDP, on the server clock
+    let mut last_seen: HashMap<u64, Duration> = HashMap::new();
+    // round trip and clock offset per player, from the pongs to the server pings
+    let mut clock_syncs: HashMap<u64, ClockSync> = HashMap::new();
+    // where the first game request came from, game packets are only taken from there; a host may only hold
+    // a few boards
+    let mut player_addrs: HashMap<u64, SocketAddr> = HashMap::new();
+    // counter of the last migration per player, a migration message cannot be played again
+    let mut migrations: HashMap<u64, u64> = HashMap::new();
+    let mut last_liveness_check = clock.now();
     let mut rng = rand::rng();
+    let mut shutdown_deadline: Option<Instant> = None;
+    // handed to the players of running boards with the shutdown notice, a saved board goes back to whoever shows them
+    let mut resume_tokens: HashMap<u64, u64> = HashMap::new();
+
+    // boards saved by the previous shutdown, they run again once both players came back with their resume tokens
+    let mut suspended: HashMap<u64, SavedBoard> = match load_boards(&config.snapshot_path) {
+        Ok(saved) => saved.into_iter().map(|b| (b.board_id, b)).collect(),
+        Err(e) => {
+            log::error!("Cannot load saved boards from {:?}, {e}", config.snapshot_path);
+            HashMap::new()
+        }
+    };
+    if !suspended.is_empty() {
+        log::info!("{} saved boards waiting for their players", suspended.len());
+    }
+    let resume_deadline = Instant::now() + config.resume_timeout();
 
     loop {
-        match logic_receiver.recv() {
+        let message = logic_receiver.recv();
+        if message.is_ok() {
+            METRICS.logic_backlog.fetch_sub(1, Ordering::Relaxed);
+        }
+        match message {
             Ok(m) => match m {
                 LogicMessage::CalculateBoard => {
-                    boards.retain(|_board_id, (player1, player2, board)| {
-                        if !player_channels.contains_key(player1) && !player_channels.contains_key(player2) {
-                            false
-                        }
-                        else {
-                            if board.step() {
-                                let (bx, by, br) = board.ball();
-                                let (p1x, p1y, p1r, p2x, p2y, _p2r) = board.players();
-                                let (score1, score2, game_over) = board.points();
-                                let serialized = GameStateSerialized {
-                                    ball_pos: (bx, by),
-                                    ball_radius: br,
-                                    player_radius: p1r,
-                                    player1_pos: (p1x, p1y),
-                                    player2_pos: (p2x, p2y),
-                                    score1,
-                                    score2,
-                                    game_over
-                                };
-                                notify(&udp_sender, SenderMsg::GameLogicState(*player1, serialized));
-                                notify(&udp_sender, SenderMsg::GameLogicState(*player2, serialized));
+                    let now = clock.now();
+                    if now.saturating_sub(last_liveness_check) >= LIVENESS_CHECK {
+                        last_liveness_check = now;
+                        let idle: Vec<u64> = last_seen.iter()
+                            .filter(|&(_, &seen)| now.saturating_sub(seen) > idle_timeout)
+                            .map(|(&player_id, _)| player_id)
+                            .collect();
+                        for player_id in idle {
+                            let opponent = find_opponent(&boards, player_id);
+                            log::info!("Player {player_id} idle for more than {idle_timeout:?}, evicting");
+                            disconnect(&udp_sender, &mut player_channels, &mut last_seen, &mut player_in_lobby, player_id, opponent);
+                        }
+                        clock_syncs.retain(|player_id, _| last_seen.contains_key(player_id));
+                        player_addrs.retain(|player_id, _| last_seen.contains_key(player_id));
+                        migrations.retain(|player_id, _| last_seen.contains_key(player_id));
+                        let _ = udp_sender.send(SenderMsg::Ping(micros(clock.now())));
+                        if !suspended.is_empty() && Instant::now() >= resume_deadline {
+                            log::info!("{} saved boards were not resumed in time, dropping them", suspended.len());
+                            // the players that came back wait for nothing now
+                            for saved in suspended.drain().map(|(_, b)| b) {
+                                for (player_id, token) in [(saved.player1, saved.token1), (saved.player2, saved.token2)] {
+                                    if token == 0 {
+                                        disconnect(&udp_sender, &mut player_channels, &mut last_seen, &mut player_in_lobby, player_id, None);
+                                    }
+                                }
                             }
-                            true
                         }
+                    }
+                    boards.retain(|&board_id, (player1, player2)| {
+                        let live = last_seen.contains_key(player1) || last_seen.contains_key(player2);
+                        if !live {
+                            pool.send(board_id, WorkerMessage::RemoveBoard(board_id));
+                            finished.remove(&board_id);
+                        }
+                        live
                     });
+                    while shutdown_deadline.is_none() && boards.len() < config.max_boards && let Some((board_id, player1, player2)) = pending.pop_front() {
+                        // the other one is let go as well, as it would be by an opponent leaving a running board
+                        if !last_seen.contains_key(&player1) || !last_seen.contains_key(&player2) {
+                            disconnect(&udp_sender, &mut player_channels, &mut last_seen, &mut player_in_lobby, player1, Some(player2));
+                            continue;
+                        }
+                        log::info!("Board {board_id} is free for players {player1} and {player2}");
+                        start_board(&pool, &mut boards, &player_channels, board_id, player1, player2, GameState::with_config(config.game));
+                    }
+                    pool.tick();
+
+                    if let Some(deadline) = shutdown_deadline {
+                        // finished games are not waited for, their players are let go right away
+                        for board_id in finished.drain() {
+                            if let Some((player1, player2)) = boards.remove(&board_id) {
+                                pool.send(board_id, WorkerMessage::RemoveBoard(board_id));
+                                disconnect(&udp_sender, &mut player_channels, &mut last_seen, &mut player_in_lobby, player1, Some(player2));
+                            }
+                        }
+                        if boards.is_empty() || Instant::now() >= deadline {
+                            boards.clear();
+                            let saved: Vec<SavedBoard> = pool.drain().into_iter()
+                                .map(|b| SavedBoard {
+                                    token1: resume_tokens.get(&b.player1).copied().unwrap_or(0),
+                                    token2: resume_tokens.get(&b.player2).copied().unwrap_or(0),
+                                    ..b
+                                })
+                                .chain(suspended.drain().map(|(_, b)| b))
+                                .collect();
+                            if !saved.is_empty() {
+                                match save_boards(&config.snapshot_path, &saved) {
+                                    Ok(()) => log::info!("{} boards saved to {:?}", saved.len(), config.snapshot_path),
+                                    Err(e) => log::error!("Cannot save boards to {:?}, {e}", config.snapshot_path),
+                                }
+                            }
+                            let players: Vec<u64> = last_seen.keys().chain(player_channels.keys()).copied().collect();
+                            for player_id in players {
+                                disconnect(&udp_sender, &mut player_channels, &mut last_seen, &mut player_in_lobby, player_id, None);
+                            }
+                            log::info!("Game logic stopped, UDP packets received: {}, sent: {}",
+                                METRICS.udp_packets_in.load(Ordering::Relaxed), METRICS.udp_packets_out.load(Ordering::Relaxed));
+                            return;
+                        }
+                    }
+                }
+                LogicMessage::GameOver(board_id) => {
+                    if boards.contains_key(&board_id) {
+                        finished.insert(board_id);
+                    }
+                }
+                LogicMessage::Shutdown => {
+                    if shutdown_deadline.is_none() {
+                        log::warn!("Shutting down, {} boards running, waiting up to {:?} for them to finish", boards.len(), config.shutdown_deadline());
+                        shutdown_deadline = Some(Instant::now() + config.shutdown_deadline());
+                        if let Some((player_id, _)) = player_in_lobby {
+                            disconnect(&udp_sender, &mut player_channels, &mut last_seen, &mut player_in_lobby, player_id, None);
+                        }
+                        for (_, player1, player2) in pending.drain(..) {
+                            disconnect(&udp_sender, &mut player_channels, &mut last_seen, &mut player_in_lobby, player1, Some(player2));
+                        }
+                        for (board_id, &(player1, player2)) in &boards {
+                            if !finished.contains(board_id) {
+                                resume_tokens.insert(player1, rng.random_range(1..=u64::MAX));
+                                resume_tokens.insert(player2, rng.random_range(1..=u64::MAX));
+                            }
+                        }
+                        for (player_id, channel) in &player_channels {
+                            let _ = channel.send(TcpMessage::ServerShutdown(resume_tokens.get(player_id).copied().unwrap_or(0)));
+                        }
+                    }
                 }
                 LogicMessage::SetChannel(player_id, channel) => {
                     player_channels.insert(player_id, channel);
+                    last_seen.insert(player_id, clock.now());
+                }
+                LogicMessage::Alive(player_id) => {
+                    if player_channels.contains_key(&player_id) {
+                        last_seen.insert(player_id, clock.now());
+                    }
                 }
                 LogicMessage::PlayerMsg(addr, msg) => match msg {
-                    MsgIn::GameRequest(player_id) => {
+                    // anyone who saw a player's id could keep it alive, skew its clock sync or aim pongs at others otherwise
+                    MsgIn::Ping(player_id, ..) | MsgIn::Pong(player_id, ..) if player_addrs.get(&player_id) != Some(&addr) => METRICS.dropped("wrong_address"),
+                    MsgIn::Ping(player_id, client_time, server_time) => {
+                        last_seen.insert(player_id, clock.now());
+                        if client_time != 0 {
+                            notify(&udp_sender, SenderMsg::Pong(addr, client_time, server_time));
+                        }
+                        // UDP pings keep the TCP session alive too, clients are free to ping over either transport
+                        if let Some(channel) = player_channels.get(&player_id) {
+                            let _ = channel.send(TcpMessage::KeepAlive);
+                        }
+                    }
+                    MsgIn::Pong(player_id, server_time, client_time) => {
+                        last_seen.insert(player_id, clock.now());
+                        if let Some(rtt) = clock_syncs.entry(player_id).or_default().observe(server_time, client_time, micros(clock.now())) {
+                            METRICS.observe_rtt(rtt);
+                        }
+                    }
+                    MsgIn::GameRequest(..) if shutdown_deadline.is_some() => {
+                        log::debug!("Server is shutting down, no new games");
+                    }
+                    MsgIn::GameRequest(player_id, resume_token) => {
+                        // moving to another address needs a migration message
+                        if player_addrs.get(&player_id).is_some_and(|registered| *registered != addr) {
+                            METRICS.dropped("wrong_address");
+                            log::debug!("Game request of {player_id} from {addr}, the player is registered at another address");
+                            continue;
+                        }
+                        player_addrs.insert(player_id, addr);
+                        last_seen.insert(player_id, clock.now());
+                        // the player has a new id after the restart, the token tells which saved board and which side it is
+                        let resumed = suspended.values_mut().find(|b| resume_token != 0 && (b.token1 == resume_token || b.token2 == resume_token));
+                        if let Some(saved) = resumed {
+                            if saved.token1 == resume_token {
+                                (saved.player1, saved.token1) = (player_id, 0);
+                            } else {
+                                (saved.player2, saved.token2) = (player_id, 0);
+                            }
+                            log::info!("Player {player_id} is back for board {}", saved.board_id);
+                            notify(&udp_sender, SenderMsg::SetAddress(player_id, saved.board_id, addr));
+                            // the first one back waits for the other, as in the lobby
+                            if saved.token1 == 0 && saved.token2 == 0 {
+                                let board_id = saved.board_id;
+                                let saved = suspended.remove(&board_id).unwrap();
+                                log::info!("Board {board_id} resumed");
+                                let state = GameState::from_snapshot(&saved.snapshot);
+                                start_board(&pool, &mut boards, &player_channels, board_id, saved.player1, saved.player2, state);
+                            }
+                            continue;
+                        }
+                        let running = boards.iter()
+                            .find(|(board_id, (player1, player2))| (*player1 == player_id || *player2 == player_id) && !finished.contains(board_id))
+                            .map(|(&id, _)| id);
+                        let waiting = pending.iter()
+                            .find(|(_, player1, player2)| *player1 == player_id || *player2 == player_id)
+                            .map(|&(board_id, _, _)| board_id)
+                            .or(suspended.values().find(|b| (b.player1 == player_id && b.token1 == 0) || (b.player2 == player_id && b.token2 == 0)).map(|b| b.board_id));
+                        if let Some(board_id) = running.or(waiting) {
+                            // a repeated request during a game or the wait for one
+                            notify(&udp_sender, SenderMsg::SetAddress(player_id, board_id, addr));
+                            continue;
+                        }
+                        // a flood of game requests under made-up player ids cannot fill the server with boards, from any number
+                        // of source ports
+                        let host = routes.host(addr);
+                        let same_host = |p: &u64| player_addrs.get(p).is_some_and(|a| routes.host(*a) == host);
+                        let held = boards.iter()
+                            .filter(|(board_id, (player1, player2))| !finished.contains(board_id) && (same_host(player1) || same_host(player2)))
+                            .count()
+                            + pending.iter().filter(|(_, player1, player2)| same_host(player1) || same_host(player2)).count()
+                            + player_in_lobby.iter().filter(|(p, _)| *p != player_id && same_host(p)).count();
+                        if held >= config.max_boards_per_address {
+                            METRICS.dropped("boards_per_address");
+                            log::debug!("{host} holds {held} boards and lobby places already, game request of {player_id} ignored");
+                            continue;
+                        }
                         let (new_player_id, board_id) = match player_in_lobby {
                             None => {
                                 let game_id: u64 = rng.random();
                                 player_in_lobby = Some((player_id, game_id));
                                 (player_id, game_id)
                             }
+                            // the ids packet got lost and the waiting player asks again
+                            Some((waiting_player_id, board_id)) if waiting_player_id == player_id => (player_id, board_id),
+                            // both get the board id and wait for the first state, as a player in the lobby does
+                            Some((waiting_player_id, board_id)) if boards.len() >= config.max_boards => {
+                                log::warn!("Board limit {} reached, players {waiting_player_id} and {player_id} wait for a board", config.max_boards);
+                                pending.push_back((board_id, waiting_player_id, player_id));
+                                player_in_lobby = None;
+                                (player_id, board_id)
+                            }
                             Some((waiting_player_id, board_id)) => {
-                                let game = GameState::new();
-                                boards.insert(board_id, (waiting_player_id, player_id, game));
+                                start_board(&pool, &mut boards, &player_channels, board_id, waiting_player_id, player_id, GameState::with_config(config.game));
                                 player_in_lobby = None;
-                                send_tcp_message(&player_channels, player_id, TcpMessage::SetOpponent(waiting_player_id));
-                                send_tcp_message(&player_channels, waiting_player_id, TcpMessage::SetOpponent(player_id));
                                 (player_id, board_id)
                             }
                         };
                         notify(&udp_sender, SenderMsg::SetAddress(new_player_id, board_id, addr));
                     }
-                    MsgIn::Input(player_id, board_id, key) => match boards.get_mut(&board_id) {
+                    // not logged, anyone who saw the ids of a player could fill the log with these
+                    MsgIn::Input(player_id, ..) | MsgIn::Resync(player_id, _) if player_addrs.get(&player_id) != Some(&addr) => METRICS.dropped("wrong_address"),
+                    MsgIn::Resync(player_id, board_id) => match boards.get(&board_id) {
+                        Some(&(player1, player2)) if player_id == player1 || player_id == player2 => {
+                            log::debug!("Player {player_id} asks for a resync of board {board_id}");
+                            pool.send(board_id, WorkerMessage::Resync(board_id, player_id));
+                        }
+                        _ => log::debug!("Resync of player {player_id} for board {board_id}, not on it"),
+                    },
+                    MsgIn::Input(player_id, board_id, key, seq, frame) => match boards.get(&board_id) {
+                        // keys pressed while waiting for a free board or the opponent of a saved one
+                        None if pending.iter().any(|(b_id, _, _)| *b_id == board_id) || suspended.contains_key(&board_id) => {}
                         None => log::error!("Board id {board_id} not found"),
-                        Some((player1, player2, board)) => {
-                            if player_id != *player1 && player_id != *player2 {
-                                log::error!("Player id {player_id} not found, {} {}", *player1, *player2);
+                        Some(&(player1, player2)) => {
+                            if player_id != player1 && player_id != player2 {
+                                log::error!("Player id {player_id} not found, {player1} {player2}");
                             } else {
-                                let player = player_id == *player1;
-                                match key {
-                                    Key::Left(true) => board.add_force(false, player),
-                                    Key::Left(false) => board.reset_force(false, player),
-                                    Key::Right(true) => board.add_force(true, player),
-                                    Key::Right(false) => board.reset_force(true, player),
-                                    Jump => board.apply_impulse(false, player)
-                                }
+                                last_seen.insert(player_id, clock.now());
+                                pool.send(board_id, WorkerMessage::Input(board_id, player_id, key, seq, frame));
                             }
                         }
                     },
+                    MsgIn::Migrate(player_id, counter, tag) => {
+                        let replayed = migrations.get(&player_id).is_some_and(|&last| counter <= last);
+                        if !last_seen.contains_key(&player_id) || replayed || !migration::verify(player_id, counter, tag) {
+                            METRICS.dropped("bad_migration");
+                            log::debug!("Migration of {player_id} to {addr} rejected, replayed: {replayed}");
+                            continue;
+                        }
+                        migrations.insert(player_id, counter);
+                        last_seen.insert(player_id, clock.now());
+                        if player_addrs.insert(player_id, addr) != Some(addr) {
+                            log::info!("Player {player_id} moved to {addr}");
+                            if let Some(channel) = player_channels.get(&player_id) {
+                                let _ = channel.send(TcpMessage::Moved(addr));
+                            }
+                        }
+                        // the snapshots follow the player, a running board before a finished one
+                        let board_id = boards.iter()
+                            .filter(|(_, (player1, player2))| *player1 == player_id || *player2 == player_id)
+                            .min_by_key(|(board_id, _)| finished.contains(board_id))
+                            .map(|(&board_id, _)| board_id)
+                            .or(player_in_lobby.filter(|(p_id, _)| *p_id == player_id).map(|(_, board_id)| board_id))
+                            .or(pending.iter().find(|(_, p1, p2)| *p1 == player_id || *p2 == player_id).map(|&(board_id, _, _)| board_id));
+                        if let Some(board_id) = board_id {
+                            notify(&udp_sender, SenderMsg::SetAddress(player_id, board_id, addr));
+                        }
+                    }
                 }
                 LogicMessage::Disconnect(player, opponent) => {
-                    log::debug!("Player {player}, opponent {opponent:?} disconnects");
-                    [Some(player), opponent].iter().flatten().for_each(|&player_id| {
-                        send_tcp_message(&player_channels, player_id, TcpMessage::DisconnectPlayer);
-                        player_channels.remove(&player_id);
-                        notify(&udp_sender, SenderMsg::ForgetAddress(player_id));
-                        if let Some((p_id, _b_id)) = player_in_lobby && player_id == p_id {
-                            player_in_lobby = None;
+                    disconnect(&udp_sender, &mut player_channels, &mut last_seen, &mut player_in_lobby, player, opponent);
+                }
+                LogicMessage::Admin(query, reply) => {
+                    let response = match query {
+                        AdminQuery::Players => AdminReply::Players(last_seen.iter().map(|(&player_id, seen)| PlayerInfo {
+                            player_id,
+                            idle: clock.now().saturating_sub(*seen),
+                            tcp: player_channels.contains_key(&player_id),
+                            in_lobby: player_in_lobby.is_some_and(|(p_id, _)| p_id == player_id),
+                            board_id: boards.iter()
+                                .find(|(_, (player1, player2))| *player1 == player_id || *player2 == player_id)
+                                .map(|(&board_id, _)| board_id),
+                            rtt: clock_syncs.get(&player_id).and_then(ClockSync::rtt),
+                            clock_offset: clock_syncs.get(&player_id).and_then(ClockSync::offset),
+                        }).collect()),
+                        AdminQuery::Boards => AdminReply::Boards(pool.boards()),
+                        AdminQuery::Lobby => AdminReply::Lobby(player_in_lobby),
+                        AdminQuery::Kick(player_id) => {
+                            let found = last_seen.contains_key(&player_id) || player_channels.contains_key(&player_id);
+                            if found {
+                                log::warn!("Player {player_id} kicked from admin console");
+                                let opponent = find_opponent(&boards, player_id);
+                                disconnect(&udp_sender, &mut player_channels, &mut last_seen, &mut player_in_lobby, player_id, opponent);
+                            }
+                            AdminReply::Done(found)
                         }
-                    });
+                        AdminQuery::EndBoard(board_id) => match boards.remove(&board_id) {
+                            None => AdminReply::Done(false),
+                            Some((player1, player2)) => {
+                                log::warn!("Board {board_id} ended from admin console");
+                                pool.send(board_id, WorkerMessage::RemoveBoard(board_id));
+                                finished.remove(&board_id);
+                                disconnect(&udp_sender, &mut player_channels, &mut last_seen, &mut player_in_lobby, player1, Some(player2));
+                                AdminReply::Done(true)
+                            }
+                        }
+                    };
+                    if reply.send(response).is_err() {
+                        log::warn!("Admin console went away before the reply");
+                    }
                 }
             }
             Err(e) => error!("Game logic receive error, {e}")
         }
+        METRICS.boards.store(boards.len() as i64, Ordering::Relaxed);
+        METRICS.lobby_players.store((player_in_lobby.iter().count() + 2 * pending.len()) as i64, Ordering::Relaxed);
     }
 }
 
+fn disconnect(
+    udp_sender: &Sender<SenderMsg>,
+    player_channels: &mut HashMap<u64, UnboundedSender<TcpMessage>>,
+    last_seen: &mut HashMap<u64, Duration>,
+    player_in_lobby: &mut Option<(u64, u64)>,
+    player: u64,
+    opponent: Option<u64>,
+) {
+    log::debug!("Player {player}, opponent {opponent:?} disconnects");
+    [Some(player), opponent].iter().flatten().for_each(|&player_id| {
+        // UDP-only players have no TCP channel, nothing to close for them
+        if let Some(channel) = player_channels.remove(&player_id) && let Err(e) = channel.send(TcpMessage::DisconnectPlayer) {
+            log::warn!("Cannot send message {:?}, {e}", TcpMessage::DisconnectPlayer);
+        }
+        last_seen.remove(&player_id);
+        notify(udp_sender, SenderMsg::ForgetAddress(player_id));
+        if let Some((p_id, _b_id)) = *player_in_lobby && player_id == p_id {
+            *player_in_lobby = None;
+        }
+    });
+}
+
+fn start_board(
+    pool: &WorkerPool,
+    boards: &mut HashMap<u64, (u64, u64)>,
+    player_channels: &HashMap<u64, UnboundedSender<TcpMessage>>,
+    board_id: u64,
+    player1: u64,
+    player2: u64,
+    game: GameState,
+) {
+    boards.insert(board_id, (player1, player2));
+    pool.send(board_id, WorkerMessage::NewBoard(board_id, player1, player2, Box::new(game)));
+    send_tcp_message(player_channels, player2, TcpMessage::SetOpponent(player1));
+    send_tcp_message(player_channels, player1, TcpMessage::SetOpponent(player2));
+}
+
+fn find_opponent(boards: &HashMap<u64, (u64, u64)>, player_id: u64) -> Option<u64> {
+    boards.values().find_map(|&(player1, player2)| {
+        if player1 == player_id {
+            Some(player2)
+        } else if player2 == player_id {
+            Some(player1)
+        } else {
+            None
+        }
+    })
+}
+
 fn notify(sender: &Sender<SenderMsg>, msg: SenderMsg) {
     match sender.send(msg) {
         Ok(_) => {}
@@ -138,3 +580,212 @@ fn send_tcp_message(channels: &HashMap<u64, UnboundedSender<TcpMessage>>, player
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::sync::mpsc::channel;
+    use std::thread::spawn;
+    use std::time::Duration;
+    use tokio::sync::oneshot;
+    use crate::admin::{AdminQuery, AdminReply};
+    use crate::config::ServerConfig;
+    use crate::scheduler::{SystemClock, VirtualClock};
+    use crate::{migration, server_logic};
+    use crate::server_logic::{start, LogicMessage};
+    use crate::session::SessionRoutes;
+    use crate::tcp_server::TcpMessage;
+    use crate::udp_server::{MsgIn, SenderMsg};
+
+    #[test]
+    fn test_idle_lobby_player_evicted() {
+        let (logic_sender, logic_receiver) = server_logic::channel(100);
+        let (udp_sender, udp_receiver) = channel();
+        // idle timeout is whole seconds in the config
+        let config = ServerConfig { idle_timeout_secs: 2, ..Default::default() };
+        let clock = Arc::new(VirtualClock::default());
+        let worker_sender = logic_sender.clone();
+        let logic_clock = clock.clone();
+        spawn(move || start(worker_sender, logic_receiver, udp_sender, SessionRoutes::default(), logic_clock, config));
+        let (addr, elsewhere): (SocketAddr, SocketAddr) = ("127.0.0.1:5000".parse().unwrap(), "127.0.0.1:6000".parse().unwrap());
+        // the clock must not move before the game logic got to the message
+        let send = |addr, msg| {
+            logic_sender.send(LogicMessage::PlayerMsg(addr, msg)).unwrap();
+            let (reply, handled) = oneshot::channel();
+            logic_sender.send(LogicMessage::Admin(AdminQuery::Lobby, reply)).unwrap();
+            handled.blocking_recv().unwrap();
+        };
+
+        send(addr, MsgIn::GameRequest(7, 0));
+        assert!(matches!(udp_receiver.recv().unwrap(), SenderMsg::SetAddress(7, _, a) if a == addr));
+
+        // a ping from the player's address keeps it and is answered, the server ping goes out after the eviction would have
+        clock.advance(Duration::from_millis(1500));
+        send(addr, MsgIn::Ping(7, 100, 200));
+        assert!(matches!(udp_receiver.recv().unwrap(), SenderMsg::Pong(a, 100, 200) if a == addr));
+        clock.advance(Duration::from_millis(1500));
+        logic_sender.send(LogicMessage::CalculateBoard).unwrap();
+        assert!(matches!(udp_receiver.recv_timeout(Duration::from_secs(1)).unwrap(), SenderMsg::Ping(_)));
+
+        // the same ping or a pong from anywhere else does neither
+        send(elsewhere, MsgIn::Ping(7, 100, 200));
+        send(elsewhere, MsgIn::Pong(7, 1, 2));
+        clock.advance(Duration::from_millis(1500));
+        logic_sender.send(LogicMessage::CalculateBoard).unwrap();
+        assert!(matches!(udp_receiver.recv_timeout(Duration::from_secs(1)).unwrap(), SenderMsg::ForgetAddress(7)));
+    }
+
+    #[test]
+    fn test_shutdown_saves_and_resumes_boards() {
+        let snapshot_path = std::env::temp_dir().join(format!("volleyball_shutdown_{}.bin", std::process::id()));
+        let config = ServerConfig { shutdown_deadline_secs: 0, snapshot_path: snapshot_path.clone(), ..Default::default() };
+        let addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+
+        let (logic_sender, logic_receiver) = server_logic::channel(100);
+        let (udp_sender, udp_receiver) = channel();
+        let logic_config = config.clone();
+        let worker_sender = logic_sender.clone();
+        let logic = spawn(move || start(worker_sender, logic_receiver, udp_sender, SessionRoutes::default(), Arc::new(SystemClock::new()), logic_config));
+        let mut channels = [1, 2].map(|player_id| {
+            let (channel, receiver) = tokio::sync::mpsc::unbounded_channel();
+            logic_sender.send(LogicMessage::SetChannel(player_id, channel)).unwrap();
+            receiver
+        });
+        logic_sender.send(LogicMessage::PlayerMsg(addr, MsgIn::GameRequest(1, 0))).unwrap();
+        logic_sender.send(LogicMessage::PlayerMsg(addr, MsgIn::GameRequest(2, 0))).unwrap();
+        let Ok(SenderMsg::SetAddress(1, board_id, _)) = udp_receiver.recv() else { panic!("no lobby") };
+        logic_sender.send(LogicMessage::Shutdown).unwrap();
+        logic_sender.send(LogicMessage::PlayerMsg(addr, MsgIn::GameRequest(3, 0))).unwrap();
+        logic_sender.send(LogicMessage::CalculateBoard).unwrap();
+        logic.join().unwrap();
+        assert!(snapshot_path.exists());
+        // no lobby was opened for player 3 during the shutdown
+        assert!(!udp_receiver.try_iter().any(|m| matches!(m, SenderMsg::SetAddress(3, _, _))));
+        let [token1, token2] = channels.each_mut().map(|channel| std::iter::from_fn(|| channel.try_recv().ok())
+            .find_map(|m| match m {
+                TcpMessage::ServerShutdown(token) => Some(token),
+                _ => None,
+            })
+            .unwrap());
+        assert!(token1 != 0 && token2 != 0 && token1 != token2);
+
+        let (logic_sender, logic_receiver) = server_logic::channel(100);
+        let (udp_sender, udp_receiver) = channel();
+        let worker_sender = logic_sender.clone();
+        spawn(move || start(worker_sender, logic_receiver, udp_sender, SessionRoutes::default(), Arc::new(SystemClock::new()), config));
+        let request = |player_id, token| {
+            logic_sender.send(LogicMessage::PlayerMsg(addr, MsgIn::GameRequest(player_id, token))).unwrap();
+            let Ok(SenderMsg::SetAddress(p_id, board_id, _)) = udp_receiver.recv() else { panic!("no answer") };
+            assert_eq!(p_id, player_id);
+            board_id
+        };
+        // the old id is not enough, the players come back under new ones with their tokens
+        assert_ne!(request(2, 0), board_id);
+        assert_eq!(request(12, token2), board_id);
+        assert_eq!(request(11, token1), board_id);
+        let (reply, boards) = oneshot::channel();
+        logic_sender.send(LogicMessage::Admin(AdminQuery::Boards, reply)).unwrap();
+        let Ok(AdminReply::Boards(boards)) = boards.blocking_recv() else { panic!("no boards") };
+        assert_eq!(boards.iter().map(|b| (b.board_id, b.player1, b.player2)).collect::<Vec<_>>(), vec![(board_id, 11, 12)]);
+        assert!(!snapshot_path.exists());
+    }
+
+    #[test]
+    fn test_boards_per_address() {
+        let (logic_sender, logic_receiver) = server_logic::channel(100);
+        let (udp_sender, udp_receiver) = channel();
+        let config = ServerConfig { max_boards_per_address: 1, ..Default::default() };
+        let worker_sender = logic_sender.clone();
+        spawn(move || start(worker_sender, logic_receiver, udp_sender, SessionRoutes::default(), Arc::new(SystemClock::new()), config));
+        let (flooder, player): (SocketAddr, SocketAddr) = ("10.0.0.1:5000".parse().unwrap(), "10.0.0.2:5000".parse().unwrap());
+        let request = |addr, player_id| logic_sender.send(LogicMessage::PlayerMsg(addr, MsgIn::GameRequest(player_id, 0))).unwrap();
+
+        // the lobby place is all the first host gets, also from another port, its repeated request is still answered
+        request(flooder, 1);
+        request(flooder, 2);
+        request("10.0.0.1:5001".parse().unwrap(), 5);
+        request(flooder, 1);
+        request(player, 3);
+        request(flooder, 4);
+        let joined: Vec<u64> = std::iter::from_fn(|| udp_receiver.recv_timeout(Duration::from_millis(300)).ok())
+            .filter_map(|m| match m {
+                SenderMsg::SetAddress(player_id, _, _) => Some(player_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(joined, vec![1, 1, 3]);
+    }
+
+    #[test]
+    fn test_board_limit() {
+        let (logic_sender, logic_receiver) = server_logic::channel(100);
+        let (udp_sender, udp_receiver) = channel();
+        let config = ServerConfig { max_boards: 1, ..Default::default() };
+        let worker_sender = logic_sender.clone();
+        spawn(move || start(worker_sender, logic_receiver, udp_sender, SessionRoutes::default(), Arc::new(VirtualClock::default()), config));
+        let request = |player_id: u64| {
+            let addr = SocketAddr::from(([10, 0, 0, player_id as u8], 5000));
+            logic_sender.send(LogicMessage::PlayerMsg(addr, MsgIn::GameRequest(player_id, 0))).unwrap();
+            loop {
+                if let SenderMsg::SetAddress(p_id, board_id, _) = udp_receiver.recv_timeout(Duration::from_secs(1)).unwrap() && p_id == player_id {
+                    return board_id;
+                }
+            }
+        };
+        let admin = |query| {
+            let (reply, answer) = oneshot::channel();
+            logic_sender.send(LogicMessage::Admin(query, reply)).unwrap();
+            answer.blocking_recv().unwrap()
+        };
+        let boards = || match admin(AdminQuery::Boards) {
+            AdminReply::Boards(boards) => boards.iter().map(|b| (b.player1, b.player2)).collect::<Vec<_>>(),
+            other => panic!("unexpected reply {other:?}"),
+        };
+
+        let first = request(1);
+        assert_eq!(request(2), first);
+        // the second pair is told its board like a lobby player is, and waits for it
+        let second = request(3);
+        assert_eq!(request(4), second);
+        logic_sender.send(LogicMessage::CalculateBoard).unwrap();
+        assert_eq!(boards(), vec![(1, 2)]);
+
+        assert!(matches!(admin(AdminQuery::EndBoard(first)), AdminReply::Done(true)));
+        logic_sender.send(LogicMessage::CalculateBoard).unwrap();
+        assert_eq!(boards(), vec![(3, 4)]);
+    }
+
+    #[test]
+    fn test_migration() {
+        let (logic_sender, logic_receiver) = server_logic::channel(100);
+        let (udp_sender, udp_receiver) = channel();
+        let worker_sender = logic_sender.clone();
+        spawn(move || start(worker_sender, logic_receiver, udp_sender, SessionRoutes::default(), Arc::new(SystemClock::new()), ServerConfig::default()));
+        let (home, elsewhere): (SocketAddr, SocketAddr) = ("10.0.0.1:5000".parse().unwrap(), "10.0.0.2:6000".parse().unwrap());
+        let send = |addr, msg| logic_sender.send(LogicMessage::PlayerMsg(addr, msg)).unwrap();
+        let moved_to = || std::iter::from_fn(|| udp_receiver.recv_timeout(Duration::from_millis(300)).ok())
+            .filter_map(|m| match m {
+                SenderMsg::SetAddress(7, _, addr) => Some(addr),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        send(home, MsgIn::GameRequest(7, 0));
+        assert_eq!(moved_to(), vec![home]);
+        // the player's ids seen elsewhere are not enough to take the snapshots away
+        send(elsewhere, MsgIn::GameRequest(7, 0));
+        send(elsewhere, MsgIn::Migrate(7, 1, 0));
+        assert_eq!(moved_to(), vec![]);
+
+        let tag = migration::tag(migration::key(7), 7, 1);
+        send(elsewhere, MsgIn::Migrate(7, 1, tag));
+        assert_eq!(moved_to(), vec![elsewhere]);
+        // played again from the old address
+        send(home, MsgIn::Migrate(7, 1, tag));
+        send(home, MsgIn::GameRequest(7, 0));
+        assert_eq!(moved_to(), vec![]);
+        send(elsewhere, MsgIn::GameRequest(7, 0));
+        assert_eq!(moved_to(), vec![elsewhere]);
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use rand::Rng;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedReceiver};
+use crate::clock_sync::micros;
+use crate::flood::{self, PacketFilter};
+use crate::metrics::METRICS;
+use crate::packet_dump::DUMPS;
+use crate::scheduler::Clock;
+use crate::server_logic::{LogicMessage, LogicSender};
+use crate::tcp_server::TcpMessage;
+use crate::transport::DatagramSocket;
+use crate::udp_server;
+use crate::udp_server::{MsgIn, PacketMsg};
+
+// players on a connection based transport (WebSocket, QUIC) send the TCP control messages and the UDP game packets
+// over that one connection; the game logic knows them under a made-up address, like any UDP player, the packet filter
+// and the limits per host go by the address the connection comes from
+
+// packets waiting for a slow connection, more are dropped as a lost datagram would be
+const SESSION_QUEUE: usize = 64;
+
+// session connections by their address, the UDP sender hands packets for them over here
+#[derive(Clone, Default)]
+pub struct SessionRoutes {
+    inner: Arc<Mutex<Routes>>,
+}
+
+#[derive(Default)]
+struct Routes {
+    next: u64,
+    outgoing: HashMap<SocketAddr, Sender<Vec<u8>>>,
+    // the host of the peer behind each session address
+    hosts: HashMap<SocketAddr, IpAddr>,
+}
+
+impl SessionRoutes {
+    // addresses from the discard-only prefix 100::/64, no UDP datagram can come from one of them
+    fn register(&self, outgoing: Sender<Vec<u8>>, peer: SocketAddr) -> SocketAddr {
+        let mut routes = self.inner.lock().unwrap();
+        routes.next += 1;
+        let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::from(0x0100u128 << 112 | routes.next as u128)), 0);
+        routes.outgoing.insert(addr, outgoing);
+        routes.hosts.insert(addr, flood::host(peer));
+        addr
+    }
+
+    fn remove(&self, addr: SocketAddr) {
+        let mut routes = self.inner.lock().unwrap();
+        routes.outgoing.remove(&addr);
+        routes.hosts.remove(&addr);
+    }
+
+    // flood::host, for a session the host it is connected from
+    pub fn host(&self, addr: SocketAddr) -> IpAddr {
+        self.inner.lock().unwrap().hosts.get(&addr).copied().unwrap_or_else(|| flood::host(addr))
+    }
+
+    // None when the address is not a session
+    fn send(&self, addr: SocketAddr, data: &[u8]) -> Option<std::io::Result<usize>> {
+        let routes = self.inner.lock().unwrap();
+        let outgoing = routes.outgoing.get(&addr)?;
+        match outgoing.try_send(data.to_vec()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => METRICS.dropped("session_queue_full"),
+            // a session that is closing drops its last packets, as a lost datagram would be
+            Err(TrySendError::Closed(_)) => {}
+        }
+        Some(Ok(data.len()))
+    }
+}
+
+// the UDP sender's socket, packets for a session go to its connection instead
+pub struct RoutedSocket<S> {
+    socket: S,
+    routes: SessionRoutes,
+}
+
+impl<S: DatagramSocket> RoutedSocket<S> {
+    pub fn new(socket: S, routes: SessionRoutes) -> RoutedSocket<S> {
+        RoutedSocket { socket, routes }
+    }
+}
+
+impl<S: DatagramSocket> DatagramSocket for RoutedSocket<S> {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+        match self.routes.send(addr, buf) {
+            Some(sent) => sent,
+            None => self.socket.send_to(buf, addr),
+        }
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        self.socket.recv_from(buf)
+    }
+
+    fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(RoutedSocket { socket: self.socket.try_clone()?, routes: self.routes.clone() })
+    }
+}
+
+// what a session does with a message from the game logic
+pub enum Control {
+    Continue,
+    Send(Vec<u8>),
+    Close,
+}
+
+// one player on one connection, what tcp_server::handle_connection keeps in local variables
+pub struct PlayerSession {
+    pub player_id: u64,
+    pub addr: SocketAddr,
+    // where the connection comes from
+    peer: SocketAddr,
+    opponent_id: Option<u64>,
+    last_ping: Instant,
+    // label of the parse error counters
+    transport: &'static str,
+    logic_sender: LogicSender,
+    routes: SessionRoutes,
+    filter: PacketFilter,
+    clock: Arc<dyn Clock>,
+}
+
+impl PlayerSession {
+    // the receivers get the game logic's messages and the packets routed to the session, None when the game logic stopped
+    pub fn open(transport: &'static str, peer: SocketAddr, routes: &SessionRoutes, filter: &PacketFilter, logic_sender: LogicSender, clock: Arc<dyn Clock>)
+        -> Option<(PlayerSession, UnboundedReceiver<TcpMessage>, Receiver<Vec<u8>>)> {
+        let player_id: u64 = rand::rng().random();
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        if let Err(e) = logic_sender.send(LogicMessage::SetChannel(player_id, sender)) {
+            log::error!("Cannot open a {transport} session, {e}");
+            return None;
+        }
+        let (packet_sender, packets) = tokio::sync::mpsc::channel(SESSION_QUEUE);
+        let addr = routes.register(packet_sender, peer);
+        let session = PlayerSession {
+            player_id,
+            addr,
+            peer,
+            opponent_id: None,
+            last_ping: Instant::now(),
+            transport,
+            logic_sender,
+            routes: routes.clone(),
+            filter: filter.clone(),
+            clock,
+        };
+        Some((session, receiver, packets))
+    }
+
+    // a client packet, returns the answer to send back the same way it came
+    pub fn receive(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        let parsed = udp_server::parse_packet(data);
+        if let Ok(msg) = &parsed && let Err(reason) = self.filter.admit(self.peer, msg, Instant::now()) {
+            METRICS.dropped(reason);
+            return None;
+        }
+        let msg = match parsed {
+            Ok(PacketMsg::PlayerIdRequest) => return Some(self.player_id.to_le_bytes().to_vec()),
+            // the connection is the session already
+            Ok(m @ (PacketMsg::Session(..) | PacketMsg::MigrationKeyRequest | PacketMsg::Migrate(..))) => {
+                METRICS.parse_error(self.transport, "unexpected");
+                log::debug!("Unexpected {} message: {m:?}", self.transport);
+                return None;
+            }
+            // the session decides who the player is, the ids in the packets only have to agree
+            Ok(m) if packet_player(&m) != self.player_id => {
+                METRICS.parse_error(self.transport, "wrong_player");
+                log::debug!("{} message for another player, {}: {m:?}", self.transport, self.player_id);
+                return None;
+            }
+            Ok(PacketMsg::Ping(_, _, sent)) => {
+                self.last_ping = Instant::now();
+                self.logic_sender.send_player(LogicMessage::Alive(self.player_id));
+                return (sent != 0).then(|| udp_server::pong_packet(sent, micros(self.clock.now())).to_vec());
+            }
+            Ok(PacketMsg::GameRequest(p_id)) => MsgIn::GameRequest(p_id, 0),
+            Ok(PacketMsg::Resume(p_id, token)) => MsgIn::GameRequest(p_id, token),
+            Ok(PacketMsg::Input(p_id, b_id, key, seq, frame)) => MsgIn::Input(p_id, b_id, key, seq, frame),
+            Ok(PacketMsg::Pong(p_id, server_time, client_time)) => MsgIn::Pong(p_id, server_time, client_time),
+            Ok(PacketMsg::Resync(p_id, b_id)) => MsgIn::Resync(p_id, b_id),
+            Err(e) => {
+                METRICS.parse_error(self.transport, e.kind());
+                log::warn!("Invalid {} packet from {}, {e}", self.transport, self.player_id);
+                DUMPS.dump(self.transport, format_args!("player {}", self.player_id), &e, data);
+                return None;
+            }
+        };
+        self.logic_sender.send_packet(self.addr, msg);
+        None
+    }
+
+    pub fn control(&mut self, msg: TcpMessage) -> Control {
+        match msg {
+            TcpMessage::DisconnectPlayer => {
+                log::debug!("Disconnecting player {} after Server message", self.player_id);
+                Control::Close
+            }
+            TcpMessage::SetOpponent(opponent) => {
+                self.opponent_id = Some(opponent);
+                Control::Continue
+            }
+            TcpMessage::KeepAlive => {
+                self.last_ping = Instant::now();
+                Control::Continue
+            }
+            // the connection is the address
+            TcpMessage::Moved(_) => Control::Continue,
+            // the connection stays open, running games may still finish
+            TcpMessage::ServerShutdown(resume_token) => Control::Send(udp_server::shutdown_notice_packet(resume_token).to_vec()),
+        }
+    }
+
+    pub fn idle(&self, timeout: Duration) -> bool {
+        self.last_ping.elapsed() > timeout
+    }
+
+    // the connection is gone, the game logic drops the player and the opponent
+    pub fn disconnect(&self) {
+        self.send_logic(LogicMessage::Disconnect(self.player_id, self.opponent_id));
+    }
+
+    fn send_logic(&self, msg: LogicMessage) {
+        if let Err(e) = self.logic_sender.send(msg) {
+            log::error!("Cannot send LogicMessage, {e}");
+        }
+    }
+}
+
+impl Drop for PlayerSession {
+    fn drop(&mut self) {
+        self.routes.remove(self.addr);
+    }
+}
+
+fn packet_player(msg: &PacketMsg) -> u64 {
+    match *msg {
+        PacketMsg::PlayerIdRequest | PacketMsg::Session(..) | PacketMsg::MigrationKeyRequest => 0,
+        PacketMsg::Migrate(player_id, ..) => player_id,
+        PacketMsg::GameRequest(player_id) | PacketMsg::Resume(player_id, _) | PacketMsg::Resync(player_id, _) => player_id,
+        PacketMsg::Input(player_id, ..) | PacketMsg::Ping(player_id, ..) | PacketMsg::Pong(player_id, ..) => player_id,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::Arc;
+    use crate::config::ServerConfig;
+    use crate::flood::{BlockList, PacketFilter};
+    use crate::scheduler::SystemClock;
+    use crate::server_logic::{self, LogicMessage};
+    use crate::session::{PlayerSession, SessionRoutes, SESSION_QUEUE};
+    use crate::udp_server::{encode_packet, Key, MsgIn, PacketMsg};
+
+    #[test]
+    fn test_slow_session_drops_packets() {
+        let routes = SessionRoutes::default();
+        let (sender, mut packets) = tokio::sync::mpsc::channel(SESSION_QUEUE);
+        let addr = routes.register(sender, "10.0.0.1:5000".parse().unwrap());
+        for i in 0..SESSION_QUEUE + 10 {
+            assert_eq!(routes.send(addr, &[i as u8]).unwrap().unwrap(), 1);
+        }
+        let mut received = 0;
+        while let Ok(packet) = packets.try_recv() {
+            assert_eq!(packet, [received as u8]);
+            received += 1;
+        }
+        assert_eq!(received, SESSION_QUEUE);
+
+        routes.remove(addr);
+        assert!(routes.send(addr, &[0]).is_none());
+    }
+
+    #[test]
+    fn test_sessions_count_for_their_peer() {
+        let config = ServerConfig { request_rate: 2, ..Default::default() };
+        let blocklist = BlockList::default();
+        let filter = PacketFilter::new(&config, blocklist.clone());
+        let routes = SessionRoutes::default();
+        let (logic_sender, logic_receiver) = server_logic::channel(100);
+        let clock = Arc::new(SystemClock::new());
+        let open = |port| {
+            let peer = SocketAddr::from(([10, 0, 0, 1], port));
+            PlayerSession::open("ws", peer, &routes, &filter, logic_sender.clone(), clock.clone()).unwrap().0
+        };
+        let mut sessions = [open(5000), open(5001), open(5002)];
+        assert!(sessions.iter().all(|s| routes.host(s.addr) == "10.0.0.1".parse::<IpAddr>().unwrap()));
+
+        // more connections from the same host do not give more game requests
+        for session in &mut sessions {
+            session.receive(&encode_packet(&PacketMsg::GameRequest(session.player_id)));
+        }
+        let requests = logic_receiver.try_iter().filter(|m| matches!(m, LogicMessage::PlayerMsg(_, MsgIn::GameRequest(..)))).count();
+        assert_eq!(requests, 2);
+
+        // a blocked host gets nothing through on any of its connections
+        blocklist.block("10.0.0.1".parse().unwrap());
+        for session in &mut sessions {
+            session.receive(&encode_packet(&PacketMsg::Input(session.player_id, 1, Key::Jump, 1, 0)));
+        }
+        assert!(!logic_receiver.try_iter().any(|m| matches!(m, LogicMessage::PlayerMsg(..))));
+
+        let addr = sessions[0].addr;
+        drop(sessions);
+        assert_eq!(routes.host(addr), "100::".parse::<IpAddr>().unwrap());
+    }
+}
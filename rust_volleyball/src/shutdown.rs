@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use tokio::signal::unix::{signal, SignalKind};
+use crate::GameSnapshot;
+use crate::server_logic::{LogicMessage, LogicSender};
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedBoard {
+    pub board_id: u64,
+    pub player1: u64,
+    pub player2: u64,
+    // what the players got with the shutdown notice, 0 once the player is back under its new id
+    pub token1: u64,
+    pub token2: u64,
+    pub snapshot: GameSnapshot,
+}
+
+// first SIGINT or SIGTERM starts a graceful shutdown, the second one exits immediately
+pub fn watch_signals(logic_sender: LogicSender) {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .unwrap()
+        .block_on(async {
+            let mut interrupt = signal(SignalKind::interrupt()).expect("Cannot listen for SIGINT");
+            let mut terminate = signal(SignalKind::terminate()).expect("Cannot listen for SIGTERM");
+            for attempt in 0.. {
+                tokio::select! {
+                    _ = interrupt.recv() => log::warn!("SIGINT received"),
+                    _ = terminate.recv() => log::warn!("SIGTERM received"),
+                }
+                if attempt > 0 {
+                    log::error!("Second signal, exiting without saving boards");
+                    std::process::exit(1);
+                }
+                if let Err(e) = logic_sender.send(LogicMessage::Shutdown) {
+                    log::error!("Cannot start graceful shutdown, {e}");
+                    std::process::exit(1);
+                }
+            }
+        });
+}
+
+pub fn save_boards(path: &Path, boards: &[SavedBoard]) -> std::io::Result<()> {
+    // write next to the target and rename, a crash while saving must not leave half a file behind
+    let tmp_path = path.with_extension("tmp");
+    let writer = BufWriter::new(File::create(&tmp_path)?);
+    bincode::serialize_into(writer, boards).map_err(std::io::Error::other)?;
+    std::fs::rename(tmp_path, path)
+}
+
+// boards are loaded once, the file is removed so a later restart does not resume them again
+pub fn load_boards(path: &Path) -> std::io::Result<Vec<SavedBoard>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let boards = bincode::deserialize_from(BufReader::new(file)).map_err(std::io::Error::other)?;
+    std::fs::remove_file(path)?;
+    Ok(boards)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::GameState;
+    use crate::shutdown::{load_boards, save_boards, SavedBoard};
+
+    #[test]
+    fn test_save_load_boards() {
+        let path = std::env::temp_dir().join(format!("volleyball_boards_{}.bin", std::process::id()));
+        assert!(load_boards(&path).unwrap().is_empty());
+
+        let mut game = GameState::new();
+        for _ in 0..100 {
+            game.step_frame();
+        }
+        save_boards(&path, &[SavedBoard { board_id: 3, player1: 1, player2: 2, token1: 4, token2: 5, snapshot: game.snapshot() }]).unwrap();
+
+        let boards = load_boards(&path).unwrap();
+        assert_eq!(boards.len(), 1);
+        assert_eq!((boards[0].board_id, boards[0].player1, boards[0].player2, boards[0].token1, boards[0].token2), (3, 1, 2, 4, 5));
+        assert_eq!(GameState::from_snapshot(&boards[0].snapshot).ball(), game.ball());
+        assert!(!path.exists());
+    }
+}
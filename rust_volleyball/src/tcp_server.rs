@@ -1,76 +1,61 @@
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::AtomicU64;
-use std::sync::mpsc::Sender;
+use std::sync::atomic::Ordering;
 use std::time::{Duration, Instant};
 use rand::Rng;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use crate::server_logic::LogicMessage;
-use crate::server_logic::LogicMessage::PlayerMsg;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use crate::config::ServerConfig;
+use crate::metrics::METRICS;
+use crate::migration;
+use crate::packet_dump::DUMPS;
+use crate::server_logic::{LogicMessage, LogicSender};
+use crate::transport::StreamListener;
 use crate::udp_server;
-use crate::udp_server::{MsgIn, PacketMsg};
+use crate::udp_server::PacketMsg;
 
 #[derive(Copy, Clone, Debug)]
 pub enum TcpMessage {
     DisconnectPlayer,
     SetOpponent(u64),
+    KeepAlive,
+    // the player's resume token, see udp_server::shutdown_notice_packet
+    ServerShutdown(u64),
+    // the player proved with a migration that it is at that address now, a UDP-only session sends its replies there
+    Moved(SocketAddr),
 }
 
-pub fn start(sender: Sender<LogicMessage>) {
+pub fn start(listener: impl StreamListener, sender: LogicSender, config: ServerConfig) {
     tokio::runtime::Builder::new_current_thread()
         .enable_io()
         .enable_time()
         .build()
         .unwrap()
-        .block_on(async { run(sender).await });
+        .block_on(async { run(listener, sender, config).await });
     log::error!("TCP server stopped");
 }
 
-async fn run(sender: Sender<LogicMessage>) {
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:12541").await.expect("Cannot bind");
-    // todo is unwrap safe on Arc<Mutex<u64>> in this case?
-    let counter: Arc<Mutex<u64>> = Arc::new(Mutex::new(0));
-
-    // let mut game_logic_timer = tokio::time::interval(Duration::from_secs_f32(1.0 / 60.0));
-    // let mut game_logic_timer = tokio::time::interval(Duration::from_secs_f32(1.0 / 70.0));
-    // let mut game_logic_timer = tokio::time::interval(Duration::from_secs_f32(1.0 / 30.0));
-    let mut game_logic_timer = tokio::time::interval(Duration::from_secs_f32(1.0 / 100.0));
-    let sender_clone = sender.clone();
+async fn run(mut listener: impl StreamListener, sender: LogicSender, config: ServerConfig) {
+    let idle_timeout = config.idle_timeout();
 
     loop {
-        tokio::select! {
-            _ = game_logic_timer.tick() => {
-                if let Err(e) = sender_clone.send(LogicMessage::CalculateBoard) {
-                    log::error!("Cannot send GameLogic tick");
-                }
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let logic_sender = sender.clone();
+                tokio::spawn(async move {
+                    let c = METRICS.tcp_sessions.fetch_add(1, Ordering::Relaxed) + 1;
+                    log::debug!("TCP connection, counter: {c}");
+                    handle_connection(stream, addr, logic_sender, idle_timeout).await;
+                    let c = METRICS.tcp_sessions.fetch_sub(1, Ordering::Relaxed) - 1;
+                    log::debug!("TCP disconnection, counter: {c}");
+                });
             }
-            incoming = listener.accept() => match incoming {
-                Ok((stream, addr)) => {
-                    let logic_sender = sender.clone();
-                    let counter_clone = Arc::clone(&counter);
-                    tokio::spawn(async move {
-                        {
-                            let mut c = counter_clone.lock().unwrap();
-                            *c += 1;
-                            log::debug!("TCP connection, counter: {c}");
-                        }
-                        handle_connection(stream, addr, logic_sender).await;
-                        {
-                            let mut c = counter_clone.lock().unwrap();
-                            *c -= 1;
-                            log::debug!("TCP disconnection, counter: {c:?}");
-                        }
-                    });
-                }
-                Err(e) => {
-                    log::error!("Could not accept connection: {}", e);
-                }
+            Err(e) => {
+                log::error!("Could not accept connection: {}", e);
             }
         }
     }
 }
 
-async fn handle_connection(mut stream: tokio::net::TcpStream, addr: SocketAddr, logic_sender: Sender<LogicMessage>) {
+async fn handle_connection(mut stream: impl AsyncRead + AsyncWrite + Unpin, addr: SocketAddr, logic_sender: LogicSender, idle_timeout: Duration) {
     let mut ping_timer = tokio::time::interval(Duration::from_secs(10));
     // let mut timer2 = tokio::time::interval(Duration::from_secs(5));
     // loop {
@@ -94,11 +79,12 @@ async fn handle_connection(mut stream: tokio::net::TcpStream, addr: SocketAddr,
     loop {
         tokio::select! {
             _ = ping_timer.tick() => {
-                if last_ping.elapsed() > Duration::from_secs(30) {
+                if last_ping.elapsed() > idle_timeout {
                     log::debug!("No ping, disconnect, {player_id}");
                     if let Err(e) = logic_sender.send(LogicMessage::Disconnect(player_id, opponent_id)) {
-                        log::error!("Cannot send LogicMessage");
+                        log::error!("Cannot send LogicMessage, {e}");
                     }
+                    break;
                 }
             }
             Some(ch_recv) = receiver.recv() => {
@@ -108,6 +94,12 @@ async fn handle_connection(mut stream: tokio::net::TcpStream, addr: SocketAddr,
                         break;
                     }
                     TcpMessage::SetOpponent(opponent) => opponent_id = Some(opponent),
+                    TcpMessage::KeepAlive => last_ping = Instant::now(),
+                    TcpMessage::Moved(_) => {}
+                    // the connection stays open, running games may still finish
+                    TcpMessage::ServerShutdown(resume_token) => if let Err(e) = stream.write_all(&udp_server::shutdown_notice_packet(resume_token)).await {
+                        log::warn!("Cannot send shutdown notice, {player_id}, error: {e}");
+                    }
                 }
             }
             res = stream.read(&mut buffer) => {
@@ -115,7 +107,7 @@ async fn handle_connection(mut stream: tokio::net::TcpStream, addr: SocketAddr,
                     Ok(0) => {
                         log::debug!("Connection closed, {player_id}");
                         if let Err(e) = logic_sender.send(LogicMessage::Disconnect(player_id, opponent_id)) {
-                            log::error!("Cannot send LogicMessage");
+                            log::error!("Cannot send LogicMessage, {e}");
                         }
                         break;
                     }
@@ -127,24 +119,40 @@ async fn handle_connection(mut stream: tokio::net::TcpStream, addr: SocketAddr,
                                     if let Err(e) = stream.write_all(&player_id.to_le_bytes()).await {
                                         log::error!("Cannot send TCP, {player_id}, error: {e}");
                                         if let Err(e) = logic_sender.send(LogicMessage::Disconnect(player_id, opponent_id)) {
-                                            log::error!("Cannot send LogicMessage");
+                                            log::error!("Cannot send LogicMessage, {e}");
                                         }
                                         break;
                                     }
                                 }
+                                // signs the UDP packet that moves the player to a new address
+                                PacketMsg::MigrationKeyRequest => {
+                                    if let Err(e) = stream.write_all(&migration::key(player_id).to_le_bytes()).await {
+                                        log::warn!("Cannot send migration key, {player_id}, error: {e}");
+                                    }
+                                }
                                 // todo remove player_id and board_id from ping, it is recognize by the connection itself
-                                PacketMsg::Ping(_player_id, _board_id) => _ = last_ping = Instant::now(),
-                                m => log::debug!("Unexpected TCP message: {m:?}")
+                                PacketMsg::Ping(..) => {
+                                    last_ping = Instant::now();
+                                    if let Err(e) = logic_sender.send(LogicMessage::Alive(player_id)) {
+                                        log::error!("Cannot send LogicMessage, {e}");
+                                    }
+                                }
+                                m => {
+                                    METRICS.parse_error("tcp", "unexpected");
+                                    log::debug!("Unexpected TCP message: {m:?}");
+                                }
                             }
                             Err(e) => {
-                                log::error!("Error parsing packet: {e:?}");
+                                METRICS.parse_error("tcp", e.kind());
+                                log::warn!("Invalid TCP packet from {player_id}, {e}");
+                                DUMPS.dump("tcp", addr, &e, &buffer[..len]);
                             }
                         };
                     }
                     Err(e) => {
                         log::warn!("Error reading from stream, {player_id}, error: {}", e);
                         if let Err(e) = logic_sender.send(LogicMessage::Disconnect(player_id, opponent_id)) {
-                            log::error!("Cannot send LogicMessage");
+                            log::error!("Cannot send LogicMessage, {e}");
                         }
                         break;
                     }
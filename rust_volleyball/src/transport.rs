@@ -0,0 +1,322 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+
+// datagram side of the protocol, each server thread gets its own handle of the same socket
+pub trait DatagramSocket: Send + 'static {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize>;
+    fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)>;
+    fn try_clone(&self) -> std::io::Result<Self> where Self: Sized;
+}
+
+impl DatagramSocket for UdpSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+        UdpSocket::send_to(self, buf, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn try_clone(&self) -> std::io::Result<Self> {
+        UdpSocket::try_clone(self)
+    }
+}
+
+// stream side of the protocol, accepted inside the TCP server runtime
+pub trait StreamListener: Send + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    fn accept(&mut self) -> impl Future<Output = std::io::Result<(Self::Stream, SocketAddr)>>;
+}
+
+// bound outside of any runtime so a taken port fails at startup, handed to tokio on the first accept
+pub struct TcpStreamListener {
+    pending: Option<std::net::TcpListener>,
+    listener: Option<tokio::net::TcpListener>,
+}
+
+impl TcpStreamListener {
+    pub fn bind(addr: SocketAddr) -> std::io::Result<TcpStreamListener> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(TcpStreamListener { pending: Some(listener), listener: None })
+    }
+
+    // the real port when bound to port 0
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        match (&self.pending, &self.listener) {
+            (Some(pending), _) => pending.local_addr(),
+            (None, Some(listener)) => listener.local_addr(),
+            (None, None) => Err(Error::new(ErrorKind::NotConnected, "listener was not bound")),
+        }
+    }
+}
+
+impl StreamListener for TcpStreamListener {
+    type Stream = tokio::net::TcpStream;
+
+    async fn accept(&mut self) -> std::io::Result<(Self::Stream, SocketAddr)> {
+        if let Some(pending) = self.pending.take() {
+            self.listener = Some(tokio::net::TcpListener::from_std(pending)?);
+        }
+        match &self.listener {
+            Some(listener) => listener.accept().await,
+            None => Err(Error::new(ErrorKind::NotConnected, "listener was not bound")),
+        }
+    }
+}
+
+// bytes buffered per direction of an in-memory stream
+const STREAM_BUFFER: usize = 4096;
+
+// datagrams between in-memory sockets, with optional loss and latency, no ports and no threads of its own
+#[derive(Clone)]
+pub struct MemoryNetwork {
+    inner: Arc<Mutex<Network>>,
+}
+
+struct Network {
+    rng: StdRng,
+    loss: f64,
+    latency: Duration,
+    jitter: Duration,
+    next_port: u16,
+    sockets: HashMap<SocketAddr, Sender<Datagram>>,
+    listeners: HashMap<SocketAddr, tokio::sync::mpsc::UnboundedSender<(DuplexStream, SocketAddr)>>,
+}
+
+struct Datagram {
+    deliver_at: Instant,
+    from: SocketAddr,
+    data: Vec<u8>,
+}
+
+impl MemoryNetwork {
+    // the seed makes the loss and jitter pattern repeatable
+    pub fn new(seed: u64) -> MemoryNetwork {
+        MemoryNetwork {
+            inner: Arc::new(Mutex::new(Network {
+                rng: StdRng::seed_from_u64(seed),
+                loss: 0.0,
+                latency: Duration::ZERO,
+                jitter: Duration::ZERO,
+                next_port: 1,
+                sockets: HashMap::new(),
+                listeners: HashMap::new(),
+            })),
+        }
+    }
+
+    // applies to datagrams sent from now on, in both directions
+    pub fn set_conditions(&self, loss: f64, latency: Duration, jitter: Duration) {
+        let mut network = self.inner.lock().unwrap();
+        network.loss = loss;
+        network.latency = latency;
+        network.jitter = jitter;
+    }
+
+    // a fresh address for clients that do not care about theirs
+    pub fn next_addr(&self) -> SocketAddr {
+        let mut network = self.inner.lock().unwrap();
+        network.next_port += 1;
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), network.next_port)
+    }
+
+    pub fn bind(&self, addr: SocketAddr) -> std::io::Result<MemorySocket> {
+        let mut network = self.inner.lock().unwrap();
+        if network.sockets.contains_key(&addr) {
+            return Err(Error::new(ErrorKind::AddrInUse, format!("{addr} already bound")));
+        }
+        let (sender, receiver) = channel();
+        network.sockets.insert(addr, sender);
+        Ok(MemorySocket {
+            addr,
+            network: self.clone(),
+            queue: Arc::new(Mutex::new(Queue { receiver, pending: DelayQueue::default() })),
+        })
+    }
+
+    pub fn listen(&self, addr: SocketAddr) -> std::io::Result<MemoryListener> {
+        let mut network = self.inner.lock().unwrap();
+        if network.listeners.contains_key(&addr) {
+            return Err(Error::new(ErrorKind::AddrInUse, format!("{addr} already listening")));
+        }
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        network.listeners.insert(addr, sender);
+        Ok(MemoryListener { receiver })
+    }
+
+    // the returned address is what the server sees as the peer
+    pub fn connect(&self, addr: SocketAddr) -> std::io::Result<(DuplexStream, SocketAddr)> {
+        let local = self.next_addr();
+        let network = self.inner.lock().unwrap();
+        let listener = network.listeners.get(&addr).ok_or(Error::new(ErrorKind::ConnectionRefused, format!("nothing listens on {addr}")))?;
+        let (client, server) = tokio::io::duplex(STREAM_BUFFER);
+        listener.send((server, local)).map_err(|_| Error::new(ErrorKind::ConnectionRefused, format!("{addr} stopped listening")))?;
+        Ok((client, local))
+    }
+
+    fn deliver(&self, from: SocketAddr, to: SocketAddr, data: &[u8]) -> std::io::Result<usize> {
+        let mut guard = self.inner.lock().unwrap();
+        let network = &mut *guard;
+        // like UDP, datagrams to nowhere and lost datagrams both look sent
+        if network.loss > 0.0 && network.rng.random_bool(network.loss.min(1.0)) {
+            return Ok(data.len());
+        }
+        let jitter = if network.jitter.is_zero() { Duration::ZERO } else { network.rng.random_range(Duration::ZERO..=network.jitter) };
+        let deliver_at = Instant::now() + network.latency + jitter;
+        if let Some(socket) = network.sockets.get(&to) {
+            let _ = socket.send(Datagram { deliver_at, from, data: data.to_vec() });
+        }
+        Ok(data.len())
+    }
+}
+
+#[derive(Clone)]
+pub struct MemorySocket {
+    addr: SocketAddr,
+    network: MemoryNetwork,
+    queue: Arc<Mutex<Queue>>,
+}
+
+struct Queue {
+    receiver: Receiver<Datagram>,
+    pending: DelayQueue,
+}
+
+// ordered by delivery time, then by arrival so datagrams with the same delay keep their order
+type Pending = Reverse<(Instant, u64, SocketAddr, Vec<u8>)>;
+
+// datagrams held back until their delivery time, for the simulated networks
+#[derive(Default)]
+pub(crate) struct DelayQueue {
+    pending: BinaryHeap<Pending>,
+    arrivals: u64,
+}
+
+impl DelayQueue {
+    pub(crate) fn push(&mut self, deliver_at: Instant, addr: SocketAddr, data: Vec<u8>) {
+        self.arrivals += 1;
+        self.pending.push(Reverse((deliver_at, self.arrivals, addr, data)));
+    }
+
+    // the next datagram whose time has come
+    pub(crate) fn pop_due(&mut self, now: Instant) -> Option<(SocketAddr, Vec<u8>)> {
+        if self.next_due()? > now {
+            return None;
+        }
+        self.pending.pop().map(|Reverse((_, _, addr, data))| (addr, data))
+    }
+
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.pending.peek().map(|Reverse((deliver_at, ..))| *deliver_at)
+    }
+}
+
+impl MemorySocket {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // like recv_from, None once the timeout passes without a datagram
+    pub fn recv_timeout(&self, buf: &mut [u8], timeout: Duration) -> std::io::Result<Option<(usize, SocketAddr)>> {
+        let give_up = Instant::now() + timeout;
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            if let Some((from, data)) = queue.pending.pop_due(now) {
+                // longer datagrams are truncated, as with a real socket
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                return Ok(Some((len, from)));
+            }
+            let wake_at = queue.pending.next_due().unwrap_or(give_up).min(give_up);
+            if now >= give_up {
+                return Ok(None);
+            }
+            match queue.receiver.recv_timeout(wake_at - now) {
+                Ok(datagram) => queue.pending.push(datagram.deliver_at, datagram.from, datagram.data),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::new(ErrorKind::NotConnected, "network is gone")),
+            }
+        }
+    }
+}
+
+impl DatagramSocket for MemorySocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+        self.network.deliver(self.addr, addr, buf)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        loop {
+            if let Some(received) = self.recv_timeout(buf, Duration::from_secs(3600))? {
+                return Ok(received);
+            }
+        }
+    }
+
+    fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(self.clone())
+    }
+}
+
+pub struct MemoryListener {
+    receiver: tokio::sync::mpsc::UnboundedReceiver<(DuplexStream, SocketAddr)>,
+}
+
+impl StreamListener for MemoryListener {
+    type Stream = DuplexStream;
+
+    async fn accept(&mut self) -> std::io::Result<(Self::Stream, SocketAddr)> {
+        self.receiver.recv().await.ok_or(Error::new(ErrorKind::NotConnected, "network is gone"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use crate::transport::{DatagramSocket, MemoryNetwork};
+
+    #[test]
+    fn test_memory_sockets() {
+        let network = MemoryNetwork::new(7);
+        let server = network.bind(network.next_addr()).unwrap();
+        let client = network.bind(network.next_addr()).unwrap();
+        assert!(network.bind(server.local_addr()).is_err());
+        let mut buf = [0; 4];
+
+        client.send_to(&[1, 2, 3, 4, 5], server.local_addr()).unwrap();
+        assert_eq!(server.recv_from(&mut buf).unwrap(), (4, client.local_addr()));
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        network.set_conditions(0.0, Duration::from_millis(30), Duration::ZERO);
+        let sent = Instant::now();
+        for i in 0..3 {
+            client.send_to(&[i], server.local_addr()).unwrap();
+        }
+        for i in 0..3 {
+            assert_eq!(server.recv_from(&mut buf).unwrap().0, 1);
+            assert_eq!(buf[0], i);
+        }
+        assert!(sent.elapsed() >= Duration::from_millis(30));
+
+        network.set_conditions(0.5, Duration::ZERO, Duration::ZERO);
+        for i in 0..100 {
+            client.send_to(&[i], server.local_addr()).unwrap();
+        }
+        let mut received = 0;
+        while server.recv_timeout(&mut buf, Duration::from_millis(10)).unwrap().is_some() {
+            received += 1;
+        }
+        assert!((25..75).contains(&received), "{received} of 100 datagrams received");
+    }
+}
@@ -1,27 +1,60 @@
 use std::collections::HashMap;
-use std::net::{SocketAddr, UdpSocket};
-use std::sync::mpsc::{Receiver, Sender};
-use crate::server_logic::{GameStateSerialized, LogicMessage};
-use crate::server_logic::LogicMessage::PlayerMsg;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
+use tokio::sync::mpsc::error::TrySendError;
+use crate::clock_sync::micros;
+use crate::flood::PacketFilter;
+use crate::metrics::METRICS;
+use crate::packet_dump::DUMPS;
+use crate::protocol::{self, CLIENT_PACKET_LEN, MAGIC, OPCODE_AT, PROTOCOL_VERSION, SERVER_MAGIC};
+use crate::scheduler::Clock;
+use crate::{BodyState, BoardState};
+use crate::server_logic::{GameStateSerialized, LogicSender, OwnPlayer, ResyncState};
+use crate::transport::DatagramSocket;
+use crate::udp_session::SessionSender;
 
-pub fn start(socket: UdpSocket, logic_sender: Sender<LogicMessage>) {
-    let mut buf = [0; 32];
+pub fn start(socket: impl DatagramSocket, logic_sender: LogicSender, sessions: SessionSender, filter: PacketFilter, clock: Arc<dyn Clock>) {
+    // an Ethernet MTU, whatever is longer than a packet is cut to a length that still fails the length check
+    let mut buf = [0; 1500];
     loop {
-        log::debug!("Waiting for data...");
         match socket.recv_from(&mut buf) {
             Ok((len, sender_addr)) => {
-                log::debug!("{} bytes received from {}, received: {:?}", len, sender_addr, &buf[..len]);
-                match parse_packet(&buf[..len]) {
-                    Ok(PacketMsg::Input(p_id, b_id, key)) => if let Err(e) = logic_sender.send(PlayerMsg(sender_addr, MsgIn::Input(p_id, b_id, key))) {
-                        log::error!("Cannot send player message, {e}");
-                    },
+                METRICS.udp_packets_in.fetch_add(1, Ordering::Relaxed);
+                // not the payload, under a flood even this is too much for anything but tracing
+                log::trace!("{len} bytes received from {sender_addr}");
+                let msg = parse_packet(&buf[..len]);
+                if let Ok(msg) = &msg && let Err(reason) = filter.admit(sender_addr, msg, Instant::now()) {
+                    METRICS.dropped(reason);
+                    continue;
+                }
+                match msg {
+                    Ok(PacketMsg::Input(p_id, b_id, key, seq, frame)) => logic_sender.send_packet(sender_addr, MsgIn::Input(p_id, b_id, key, seq, frame)),
                     // todo make sure that client sends GameRequest multiple times, so this UDP packet is successfully delivered
-                    Ok(PacketMsg::GameRequest(p_id)) => match logic_sender.send(PlayerMsg(sender_addr, MsgIn::GameRequest(p_id))) {
+                    Ok(PacketMsg::GameRequest(p_id)) => logic_sender.send_packet(sender_addr, MsgIn::GameRequest(p_id, 0)),
+                    Ok(PacketMsg::Resume(p_id, token)) => logic_sender.send_packet(sender_addr, MsgIn::GameRequest(p_id, token)),
+                    // stamped on arrival, the detour through the game logic to check the address does not count as server time
+                    Ok(PacketMsg::Ping(p_id, _b_id, sent)) => logic_sender.send_packet(sender_addr, MsgIn::Ping(p_id, sent, micros(clock.now()))),
+                    Ok(PacketMsg::Pong(p_id, server_time, client_time)) => logic_sender.send_packet(sender_addr, MsgIn::Pong(p_id, server_time, client_time)),
+                    Ok(PacketMsg::Migrate(p_id, counter, tag)) => logic_sender.send_packet(sender_addr, MsgIn::Migrate(p_id, counter, tag)),
+                    Ok(PacketMsg::Resync(p_id, b_id)) => logic_sender.send_packet(sender_addr, MsgIn::Resync(p_id, b_id)),
+                    Ok(PacketMsg::Session(token, seq, ack, request)) => match sessions.try_send((sender_addr, token, seq, ack, request)) {
                         Ok(()) => {}
-                        Err(e) => log::error!("Cannot send player message, {e}")
+                        Err(TrySendError::Full(_)) => METRICS.dropped("session_queue_full"),
+                        Err(TrySendError::Closed(_)) => log::error!("Cannot send session packet, UDP sessions stopped"),
                     },
-                    Ok(m) => log::error!("Unexpected UDP message: {m:?}"),
-                    Err(e) => log::warn!("parse error: {e:?}"),
+                    Ok(m) => {
+                        METRICS.parse_error("udp", "unexpected");
+                        log::error!("Unexpected UDP message: {m:?}");
+                    }
+                    Err(e) => {
+                        METRICS.parse_error("udp", e.kind());
+                        log::debug!("Invalid packet from {sender_addr}, {e}");
+                        DUMPS.dump("udp", sender_addr, &e, &buf[..len]);
+                    }
                 };
             }
             Err(e) => log::error!("Error receiving data, kind: {}, error: {e}", {e.kind()})
@@ -33,9 +66,15 @@ pub enum SenderMsg {
     SetAddress(u64, u64, SocketAddr),
     GameLogicState(u64, GameStateSerialized),
     ForgetAddress(u64),
+    // a timestamped ping to every known player, on the server clock
+    Ping(u64),
+    // the answer to a player's ping: address, client time echoed, server time the ping arrived at
+    Pong(SocketAddr, u64, u64),
+    // the whole board for a player that asked for a resync
+    Board(u64, ResyncState),
 }
 
-pub fn start_sender(socket: UdpSocket, receiver: Receiver<SenderMsg>) {
+pub fn start_sender(socket: impl DatagramSocket, receiver: Receiver<SenderMsg>) {
     // todo maybe it is better to keep all the state in a single place, in server_logic thread?
     let mut addresses: HashMap<u64, SocketAddr> = HashMap::new();
     loop {
@@ -44,27 +83,54 @@ pub fn start_sender(socket: UdpSocket, receiver: Receiver<SenderMsg>) {
                 SenderMsg::SetAddress(player_id, board_id, addr) => {
                     addresses.insert(player_id, addr);
                     match socket.send_to(&parse_ids_to_packet(player_id, board_id), addr) {
-                        Ok(_) => log::debug!("Set address packet sent"),
+                        Ok(_) => {
+                            METRICS.udp_packets_out.fetch_add(1, Ordering::Relaxed);
+                            log::debug!("Set address packet sent");
+                        }
                         Err(e) => log::error!("Cannot send UDP set address, {e}")
                     }
                 },
                 SenderMsg::GameLogicState(id, state) => match addresses.get(&id) {
                     None => log::error!("Socket address not found for id {id}"),
-                    Some(addr) => match socket.send_to(&parse_to_packet(&state), addr) {
-                        Ok(_len) => {},
+                    Some(&addr) => match socket.send_to(&parse_to_packet(&state), addr) {
+                        Ok(_len) => _ = METRICS.udp_packets_out.fetch_add(1, Ordering::Relaxed),
                         Err(e) => log::error!("Cannot send bytes, {e}")
                     }
                 }
                 SenderMsg::ForgetAddress(player_id) => {
                     addresses.remove(&player_id);
                 }
+                SenderMsg::Board(id, resync) => match addresses.get(&id) {
+                    None => log::debug!("Socket address not found for id {id}"),
+                    Some(&addr) => match socket.send_to(&board_packet(&resync), addr) {
+                        Ok(_) => _ = METRICS.udp_packets_out.fetch_add(1, Ordering::Relaxed),
+                        Err(e) => log::warn!("Cannot send board, {e}"),
+                    }
+                }
+                SenderMsg::Pong(addr, client_time, server_time) => match socket.send_to(&pong_packet(client_time, server_time), addr) {
+                    Ok(_) => _ = METRICS.udp_packets_out.fetch_add(1, Ordering::Relaxed),
+                    Err(e) => log::warn!("Cannot send pong, {e}"),
+                },
+                SenderMsg::Ping(server_time) => {
+                    let packet = server_ping_packet(server_time);
+                    for addr in addresses.values() {
+                        match socket.send_to(&packet, *addr) {
+                            Ok(_) => _ = METRICS.udp_packets_out.fetch_add(1, Ordering::Relaxed),
+                            Err(e) => log::warn!("Cannot send ping, {e}"),
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                // only happens once the game logic is gone
+                log::info!("UDP sender stopped, {e}");
+                break;
             }
-            Err(e) => log::error!("Cannot receive udp message, {e}")
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Key {
     Left(bool),
     Right(bool),
@@ -73,86 +139,478 @@ pub enum Key {
 
 #[derive(Debug, PartialEq)]
 pub enum MsgIn {
-    GameRequest(u64),
-    Input(u64, u64, Key),
+    // player, resume token from the last shutdown, 0 for a new game
+    GameRequest(u64, u64),
+    // player, board, key, sequence number, intended frame (0 for none)
+    Input(u64, u64, Key, u32, u32),
+    // player, client time to echo (0 for none), server time it arrived at
+    Ping(u64, u64, u64),
+    // player, server time echoed, client time
+    Pong(u64, u64, u64),
+    // player, counter, tag, see PacketMsg::Migrate
+    Migrate(u64, u64, u64),
+    // player, board
+    Resync(u64, u64),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum PacketMsg {
     PlayerIdRequest,
     GameRequest(u64),
-    Input(u64, u64, Key),
-    Ping(u64, u64),
+    // player, board, key, input sequence number (0 when the client does not count its inputs)
+    // player, board, key, sequence number, the server frame the client saw when pressing (0 for now)
+    Input(u64, u64, Key, u32, u32),
+    // player, board, client time to be echoed in a pong (0 when only a keepalive)
+    Ping(u64, u64, u64),
+    // answer to a server ping: player, server time echoed, client time
+    Pong(u64, u64, u64),
+    // UDP-only session in place of the TCP connection: token chosen by the client, seq (0 for an acknowledgement only),
+    // ack, the control message
+    Session(u64, u32, u32, Option<SessionRequest>),
+    // over TCP, the key for signing migrations is sent back in 8 bytes like the player id
+    MigrationKeyRequest,
+    // the player is at the sender's address now: player, a counter growing with every migration, migration::tag of both
+    Migrate(u64, u64, u64),
+    // a game request back to the board saved by the last shutdown: player, the resume token of the shutdown notice
+    Resume(u64, u64),
+    // the client's board differs from the server's: player, board
+    Resync(u64, u64),
 }
 
+// control messages of a UDP-only session, what the client says over TCP otherwise
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SessionRequest {
+    // asks for a player id, with the cookie of the server's Retry (0 before it)
+    Connect(u32),
+    Ping,
+    Disconnect,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SessionReply {
+    // player id, migration key
+    Welcome(u64, u64),
+    // resume token
+    ShutdownNotice(u64),
+    // the server closed the session
+    Disconnect,
+    // the connect has to come again with that cookie, the client proves it gets what is sent to its address
+    Retry(u32),
+}
+
+// what the server sends to clients, ids and the shutdown notice are 32 bytes, the state is 68, the board 152
 #[derive(Debug, PartialEq)]
-pub struct ParseError;
+pub enum ServerPacket {
+    Ids(u64, u64),
+    State(GameStateSerialized),
+    // resume token, 0 when the player is on no board
+    ShutdownNotice(u64),
+    // client time echoed, server time
+    Pong(u64, u64),
+    // server time, to be echoed in a pong
+    Ping(u64),
+    // seq, ack, the control message, see PacketMsg::Session
+    Session(u32, u32, Option<SessionReply>),
+    // the answer to a resync request
+    Board(ResyncState),
+}
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParseError {
+    // the length that came, client packets have 32 bytes, server packets 32 or 68
+    WrongLength(usize),
+    BadMagic,
+    UnknownOpcode([u8; 2]),
+    // the magic of another protocol version
+    UnsupportedVersion(u8),
+    // a player id or session token of 0, the server never hands those out
+    InvalidId,
+    // a field out of its range, named
+    InvalidField(&'static str),
+}
+
+impl ParseError {
+    // label of the parse error counters
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ParseError::WrongLength(_) => "wrong_length",
+            ParseError::BadMagic => "bad_magic",
+            ParseError::UnknownOpcode(_) => "unknown_opcode",
+            ParseError::UnsupportedVersion(_) => "unsupported_version",
+            ParseError::InvalidId => "invalid_id",
+            ParseError::InvalidField(_) => "invalid_field",
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::WrongLength(len) => write!(f, "wrong length {len}"),
+            ParseError::BadMagic => write!(f, "bad magic"),
+            ParseError::UnknownOpcode(opcode) => write!(f, "unknown opcode {opcode:?}"),
+            ParseError::UnsupportedVersion(version) => write!(f, "unsupported protocol version {version}, expected {PROTOCOL_VERSION}"),
+            ParseError::InvalidId => write!(f, "invalid id 0"),
+            ParseError::InvalidField(field) => write!(f, "invalid {field}"),
+        }
+    }
+}
+
+// the byte layout comes from protocol.toml, what the fields mean is decided here
 pub fn parse_packet(data: &[u8]) -> Result<PacketMsg, ParseError> {
     // byte protocol invented by me based on random opcodes
-    if data.len() != 32 || data[..6] != [58, 41, 58, 80, 58, 68] {
-        Err(ParseError)
+    if data.len() != CLIENT_PACKET_LEN {
+        Err(ParseError::WrongLength(data.len()))
+    }
+    else if data[..MAGIC.len() - 1] == MAGIC[..MAGIC.len() - 1] && data[MAGIC.len() - 1] != PROTOCOL_VERSION {
+        Err(ParseError::UnsupportedVersion(data[MAGIC.len() - 1]))
+    }
+    else if data[..MAGIC.len()] != MAGIC {
+        Err(ParseError::BadMagic)
     }
     else {
         // todo player doesn't need to send boardId, the mapping is in the sever already
-        let player_id = u64::from_le_bytes(data[8..16].try_into().unwrap());
-        let board_id = u64::from_le_bytes(data[16..24].try_into().unwrap());
-        match data[6..8] {
-            [11, 13] => Ok(PacketMsg::GameRequest(player_id)),
-            [13, 22] => Ok(PacketMsg::PlayerIdRequest),
-            [17, 23] => Ok(PacketMsg::Input(player_id, board_id, Key::Left(true))),
-            [25, 99] => Ok(PacketMsg::Input(player_id, board_id, Key::Left(false))),
-            [37, 31] => Ok(PacketMsg::Input(player_id, board_id, Key::Right(true))),
-            [67, 58] => Ok(PacketMsg::Input(player_id, board_id, Key::Right(false))),
-            [97, 33] => Ok(PacketMsg::Input(player_id, board_id, Key::Jump)),
-            [96, 22] => Ok(PacketMsg::Ping(player_id, board_id)),
-            _ => Err(ParseError)
+        let input = |key| {
+            let input = protocol::Input::read(data);
+            PacketMsg::Input(input.player_id, input.board_id, key, input.seq, input.frame)
+        };
+        let msg = match [data[OPCODE_AT], data[OPCODE_AT + 1]] {
+            protocol::GAME_REQUEST => PacketMsg::GameRequest(protocol::Player::read(data).player_id),
+            protocol::RESUME_REQUEST => {
+                let resume = protocol::Resume::read(data);
+                PacketMsg::Resume(resume.player_id, resume.resume_token)
+            }
+            protocol::PLAYER_ID_REQUEST => PacketMsg::PlayerIdRequest,
+            protocol::MIGRATION_KEY_REQUEST => PacketMsg::MigrationKeyRequest,
+            protocol::MIGRATE => {
+                let migrate = protocol::Migrate::read(data);
+                PacketMsg::Migrate(migrate.player_id, migrate.counter, migrate.tag)
+            }
+            protocol::LEFT_PRESSED => input(Key::Left(true)),
+            protocol::LEFT_RELEASED => input(Key::Left(false)),
+            protocol::RIGHT_PRESSED => input(Key::Right(true)),
+            protocol::RIGHT_RELEASED => input(Key::Right(false)),
+            protocol::JUMP => input(Key::Jump),
+            protocol::PING => {
+                let ping = protocol::Ping::read(data);
+                PacketMsg::Ping(ping.player_id, ping.board_id, ping.client_time)
+            }
+            protocol::PONG => {
+                let pong = protocol::Pong::read(data);
+                PacketMsg::Pong(pong.player_id, pong.server_time, pong.client_time)
+            }
+            protocol::RESYNC_REQUEST => {
+                let request = protocol::PlayerBoard::read(data);
+                PacketMsg::Resync(request.player_id, request.board_id)
+            }
+            protocol::SESSION => {
+                let session = protocol::Session::read(data);
+                PacketMsg::Session(session.token, session.seq, session.ack, match session.request {
+                    0 => None,
+                    1 => Some(SessionRequest::Connect(session.cookie)),
+                    2 => Some(SessionRequest::Ping),
+                    3 => Some(SessionRequest::Disconnect),
+                    _ => return Err(ParseError::InvalidField("session request")),
+                })
+            }
+            opcode => return Err(ParseError::UnknownOpcode(opcode)),
+        };
+        // every message but the TCP requests names a player or a session, the TCP keepalive of the Godot client does not
+        // either, the connection tells whose it is
+        match msg {
+            PacketMsg::PlayerIdRequest | PacketMsg::MigrationKeyRequest | PacketMsg::Ping(..) => Ok(msg),
+            PacketMsg::GameRequest(0) | PacketMsg::Resume(0, _) | PacketMsg::Input(0, ..) | PacketMsg::Pong(0, ..) | PacketMsg::Session(0, ..) | PacketMsg::Migrate(0, ..) | PacketMsg::Resync(0, _) => Err(ParseError::InvalidId),
+            _ => Ok(msg),
         }
     }
 }
 
-fn parse_ids_to_packet(client_id: u64, board_id: u64) -> [u8; 32]{
-    let mut result = [0; 32];
-    result[..4].copy_from_slice(&[12, 64, 13, 56]);
-    result[4..12].copy_from_slice(&client_id.to_le_bytes());
-    result[12..20].copy_from_slice(&board_id.to_le_bytes());
-    result
+// the client side of parse_packet
+pub fn encode_packet(msg: &PacketMsg) -> [u8; CLIENT_PACKET_LEN] {
+    match *msg {
+        PacketMsg::PlayerIdRequest => protocol::encode_player_id_request(),
+        PacketMsg::MigrationKeyRequest => protocol::encode_migration_key_request(),
+        PacketMsg::Migrate(player_id, counter, tag) => protocol::encode_migrate(&protocol::Migrate { player_id, counter, tag }),
+        PacketMsg::GameRequest(player_id) => protocol::encode_game_request(&protocol::Player { player_id }),
+        PacketMsg::Resume(player_id, resume_token) => protocol::encode_resume_request(&protocol::Resume { player_id, resume_token }),
+        PacketMsg::Input(player_id, board_id, key, seq, frame) => {
+            let input = protocol::Input { player_id, board_id, seq, frame };
+            match key {
+                Key::Left(true) => protocol::encode_left_pressed(&input),
+                Key::Left(false) => protocol::encode_left_released(&input),
+                Key::Right(true) => protocol::encode_right_pressed(&input),
+                Key::Right(false) => protocol::encode_right_released(&input),
+                Key::Jump => protocol::encode_jump(&input),
+            }
+        }
+        PacketMsg::Ping(player_id, board_id, client_time) => protocol::encode_ping(&protocol::Ping { player_id, board_id, client_time }),
+        PacketMsg::Resync(player_id, board_id) => protocol::encode_resync_request(&protocol::PlayerBoard { player_id, board_id }),
+        PacketMsg::Pong(player_id, server_time, client_time) => protocol::encode_pong(&protocol::Pong { player_id, server_time, client_time }),
+        PacketMsg::Session(token, seq, ack, request) => {
+            let (request, cookie) = match request {
+                None => (0, 0),
+                Some(SessionRequest::Connect(cookie)) => (1, cookie),
+                Some(SessionRequest::Ping) => (2, 0),
+                Some(SessionRequest::Disconnect) => (3, 0),
+            };
+            protocol::encode_session(&protocol::Session { token, seq, ack, request, cookie })
+        }
+    }
+}
+
+pub fn parse_server_packet(data: &[u8]) -> Result<ServerPacket, ParseError> {
+    let header = |header: &[u8], len| data.len() == len && data.starts_with(header);
+    if header(&protocol::IDS_HEADER, protocol::IDS_LEN) {
+        let ids = protocol::Ids::read(data);
+        Ok(ServerPacket::Ids(ids.player_id, ids.board_id))
+    }
+    else if header(&protocol::SHUTDOWN_NOTICE_HEADER, protocol::SHUTDOWN_NOTICE_LEN) {
+        Ok(ServerPacket::ShutdownNotice(protocol::ShutdownNotice::read(data).resume_token))
+    }
+    else if header(&protocol::SERVER_PONG_HEADER, protocol::SERVER_PONG_LEN) {
+        let pong = protocol::ServerPong::read(data);
+        Ok(ServerPacket::Pong(pong.client_time, pong.server_time))
+    }
+    else if header(&protocol::SERVER_PING_HEADER, protocol::SERVER_PING_LEN) {
+        Ok(ServerPacket::Ping(protocol::ServerPing::read(data).server_time))
+    }
+    else if header(&protocol::SESSION_REPLY_HEADER, protocol::SESSION_REPLY_LEN) {
+        let session = protocol::SessionReply::read(data);
+        Ok(ServerPacket::Session(session.seq, session.ack, match session.reply {
+            0 => None,
+            1 => Some(SessionReply::Welcome(session.player_id, session.key)),
+            2 => Some(SessionReply::ShutdownNotice(session.key)),
+            3 => Some(SessionReply::Disconnect),
+            4 => Some(SessionReply::Retry(session.key as u32)),
+            _ => return Err(ParseError::InvalidField("session reply")),
+        }))
+    }
+    else if data.len() == protocol::STATE_LEN {
+        let state = protocol::State::read(data);
+        Ok(ServerPacket::State(GameStateSerialized {
+            ball_pos: (state.ball_x, state.ball_y),
+            ball_radius: state.ball_radius,
+            player_radius: state.player_radius,
+            player1_pos: (state.player1_x, state.player1_y),
+            player2_pos: (state.player2_x, state.player2_y),
+            score1: state.score1,
+            score2: state.score2,
+            game_over: state.game_over == 1,
+            ball_velocity: (state.ball_vx, state.ball_vy),
+            ball_gravity: state.ball_gravity == 1,
+            own: OwnPlayer {
+                is_player1: state.own_player == 1,
+                velocity: (state.own_vx, state.own_vy),
+                held: [state.held & 1 != 0, state.held & 2 != 0],
+                ack_seq: state.ack_seq,
+                ack_age: state.ack_age,
+            },
+            frame: state.frame as u64,
+            checksum: (state.has_checksum == 1).then_some(state.checksum),
+        }))
+    }
+    else if header(&protocol::BOARD_HEADER, protocol::BOARD_LEN) {
+        let b = protocol::Board::read(data);
+        let bit = |byte: u8, i: usize| byte & 1 << i != 0;
+        let body = |position, rotation, velocity, spin, force, gravity| BodyState { position, rotation, velocity, spin, force, gravity };
+        Ok(ServerPacket::Board(ResyncState {
+            board: BoardState {
+                bodies: [
+                    body((b.player1_x, b.player1_y), (b.player1_cos, b.player1_sin), (b.player1_vx, b.player1_vy), b.player1_spin, (b.player1_fx, b.player1_fy), b.player1_gravity),
+                    body((b.player2_x, b.player2_y), (b.player2_cos, b.player2_sin), (b.player2_vx, b.player2_vy), b.player2_spin, (b.player2_fx, b.player2_fy), b.player2_gravity),
+                    body((b.ball_x, b.ball_y), (b.ball_cos, b.ball_sin), (b.ball_vx, b.ball_vy), b.ball_spin, (b.ball_fx, b.ball_fy), b.ball_gravity),
+                ],
+                points: (b.score1, b.score2),
+                flags: [0, 1, 2, 3].map(|i| bit(b.flags, i)),
+                input: [[bit(b.held, 0), bit(b.held, 1)], [bit(b.held, 2), bit(b.held, 3)]],
+                reset_in: b.reset_in,
+                gravity_in: b.gravity_in,
+            },
+            frame: b.frame as u64,
+            ack_seq: b.ack_seq,
+            ack_age: b.ack_age,
+        }))
+    }
+    else if data.len() == CLIENT_PACKET_LEN && data.starts_with(&SERVER_MAGIC) {
+        Err(ParseError::UnknownOpcode([data[2], data[3]]))
+    }
+    else if data.len() == CLIENT_PACKET_LEN {
+        Err(ParseError::BadMagic)
+    }
+    else {
+        Err(ParseError::WrongLength(data.len()))
+    }
 }
 
-fn parse_to_packet(state: &GameStateSerialized) -> [u8; 64] {
-    let mut packet = [0; 64];
-    packet[..4].copy_from_slice(&state.ball_radius.to_le_bytes());
-    packet[4..8].copy_from_slice(&state.ball_pos.0.to_le_bytes());
-    packet[8..12].copy_from_slice(&state.ball_pos.1.to_le_bytes());
-    packet[12..16].copy_from_slice(&state.player_radius.to_le_bytes());
-    packet[16..20].copy_from_slice(&state.player1_pos.0.to_le_bytes());
-    packet[20..24].copy_from_slice(&state.player1_pos.1.to_le_bytes());
-    packet[24..28].copy_from_slice(&state.player2_pos.0.to_le_bytes());
-    packet[28..32].copy_from_slice(&state.player2_pos.1.to_le_bytes());
-    packet[32..36].copy_from_slice(&state.score1.to_le_bytes());
-    packet[36..40].copy_from_slice(&state.score2.to_le_bytes());
-    packet[40] = if state.game_over { 1 } else { 0 };
-    packet
+pub fn parse_ids_to_packet(client_id: u64, board_id: u64) -> [u8; 32]{
+    protocol::encode_ids(&protocol::Ids { player_id: client_id, board_id })
 }
 
+pub fn pong_packet(client_time: u64, server_time: u64) -> [u8; 32] {
+    protocol::encode_server_pong(&protocol::ServerPong { client_time, server_time })
+}
+
+pub fn server_ping_packet(server_time: u64) -> [u8; 32] {
+    protocol::encode_server_ping(&protocol::ServerPing { server_time })
+}
+
+pub fn session_packet(seq: u32, ack: u32, reply: Option<SessionReply>) -> [u8; 32] {
+    let (reply, player_id, key) = match reply {
+        None => (0, 0, 0),
+        Some(SessionReply::Welcome(player_id, key)) => (1, player_id, key),
+        Some(SessionReply::ShutdownNotice(resume_token)) => (2, 0, resume_token),
+        Some(SessionReply::Disconnect) => (3, 0, 0),
+        Some(SessionReply::Retry(cookie)) => (4, 0, cookie as u64),
+    };
+    protocol::encode_session_reply(&protocol::SessionReply { seq, ack, reply, player_id, key })
+}
+
+// sent over TCP when the server stops, running games may continue until the connection closes; a board that does not
+// finish is saved, the token takes the player back to it after the restart
+pub fn shutdown_notice_packet(resume_token: u64) -> [u8; 32] {
+    protocol::encode_shutdown_notice(&protocol::ShutdownNotice { resume_token })
+}
+
+pub fn parse_to_packet(state: &GameStateSerialized) -> [u8; 68] {
+    protocol::encode_state(&protocol::State {
+        ball_radius: state.ball_radius,
+        ball_x: state.ball_pos.0,
+        ball_y: state.ball_pos.1,
+        player_radius: state.player_radius,
+        player1_x: state.player1_pos.0,
+        player1_y: state.player1_pos.1,
+        player2_x: state.player2_pos.0,
+        player2_y: state.player2_pos.1,
+        score1: state.score1,
+        score2: state.score2,
+        game_over: state.game_over as u8,
+        own_player: if state.own.is_player1 { 1 } else { 2 },
+        held: state.own.held[0] as u8 | (state.own.held[1] as u8) << 1,
+        ball_gravity: state.ball_gravity as u8,
+        ack_seq: state.own.ack_seq,
+        ack_age: state.own.ack_age,
+        ball_vx: state.ball_velocity.0,
+        ball_vy: state.ball_velocity.1,
+        own_vx: state.own.velocity.0,
+        own_vy: state.own.velocity.1,
+        // wraps after two years at 60 frames per second
+        frame: state.frame as u32,
+        has_checksum: state.checksum.is_some() as u8,
+        checksum: state.checksum.unwrap_or(0),
+    })
+}
+
+pub fn board_packet(resync: &ResyncState) -> [u8; protocol::BOARD_LEN] {
+    let [player1, player2, ball] = resync.board.bodies;
+    let bits = |bits: &[bool]| bits.iter().enumerate().fold(0, |byte, (i, bit)| byte | (*bit as u8) << i);
+    protocol::encode_board(&protocol::Board {
+        frame: resync.frame as u32,
+        ack_seq: resync.ack_seq,
+        ack_age: resync.ack_age,
+        flags: bits(&resync.board.flags),
+        held: bits(resync.board.input.as_flattened()),
+        score1: resync.board.points.0,
+        score2: resync.board.points.1,
+        player1_x: player1.position.0,
+        player1_y: player1.position.1,
+        player1_cos: player1.rotation.0,
+        player1_sin: player1.rotation.1,
+        player1_vx: player1.velocity.0,
+        player1_vy: player1.velocity.1,
+        player1_spin: player1.spin,
+        player1_fx: player1.force.0,
+        player1_fy: player1.force.1,
+        player2_x: player2.position.0,
+        player2_y: player2.position.1,
+        player2_cos: player2.rotation.0,
+        player2_sin: player2.rotation.1,
+        player2_vx: player2.velocity.0,
+        player2_vy: player2.velocity.1,
+        player2_spin: player2.spin,
+        player2_fx: player2.force.0,
+        player2_fy: player2.force.1,
+        ball_x: ball.position.0,
+        ball_y: ball.position.1,
+        ball_cos: ball.rotation.0,
+        ball_sin: ball.rotation.1,
+        ball_vx: ball.velocity.0,
+        ball_vy: ball.velocity.1,
+        ball_spin: ball.spin,
+        ball_fx: ball.force.0,
+        ball_fy: ball.force.1,
+        player1_gravity: player1.gravity,
+        player2_gravity: player2.gravity,
+        ball_gravity: ball.gravity,
+        reset_in: resync.board.reset_in,
+        gravity_in: resync.board.gravity_in,
+    })
+}
+
+#[cfg(test)]
 mod test {
+    use crate::server_logic::{GameStateSerialized, OwnPlayer};
     use crate::udp_server::Key::{Jump, Left, Right};
-    use crate::udp_server::{parse_packet, PacketMsg, ParseError};
+    use crate::udp_server::{encode_packet, parse_ids_to_packet, parse_packet, parse_server_packet, parse_to_packet, pong_packet, server_ping_packet, session_packet, shutdown_notice_packet, PacketMsg, ParseError, ServerPacket, SessionReply, SessionRequest};
 
     #[test]
     fn test_parse_packet() {
         let one = 1u64.to_le_bytes();
-        assert_eq!(parse_packet(&[13, 14]), Err(ParseError));
-        assert_eq!(parse_packet(&[13, 14, 31, 43, 53]), Err(ParseError));
-        assert_eq!(parse_packet(&[]), Err(ParseError));
-        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[11, 13]].concat()), Err(ParseError));
-        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[11, 13], &[0; 8], &[0; 16]].concat()), Ok(PacketMsg::GameRequest(0)));
+        assert_eq!(parse_packet(&[13, 14]), Err(ParseError::WrongLength(2)));
+        assert_eq!(parse_packet(&[13, 14, 31, 43, 53]), Err(ParseError::WrongLength(5)));
+        assert_eq!(parse_packet(&[]), Err(ParseError::WrongLength(0)));
+        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[11, 13]].concat()), Err(ParseError::WrongLength(8)));
+        assert_eq!(parse_packet(&[0; 32]), Err(ParseError::BadMagic));
+        assert_eq!(parse_packet(&[b":):P:E".as_slice(), &[11, 13], &one, &[0; 16]].concat()), Err(ParseError::UnsupportedVersion(b'E')));
+        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[11, 15], &one, &[0; 16]].concat()), Err(ParseError::UnknownOpcode([11, 15])));
+        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[11, 13], &[0; 8], &[0; 16]].concat()), Err(ParseError::InvalidId));
+        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[41, 7], &one, &[0; 8], &[4], &[0; 7]].concat()), Err(ParseError::InvalidField("session request")));
+        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[11, 13], &one, &[0; 16]].concat()), Ok(PacketMsg::GameRequest(1)));
         assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[13, 22], &[0; 8], &[0; 16]].concat()), Ok(PacketMsg::PlayerIdRequest));
-        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[17, 23], &one, &one, &[0; 8]].concat()), Ok(PacketMsg::Input(1, 1, Left(true))));
-        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[25, 99], &99u64.to_le_bytes(), &one, &[0; 8]].concat()), Ok(PacketMsg::Input(99, 1, Left(false))));
-        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[37, 31], &99u64.to_le_bytes(), &one, &[0; 8]].concat()), Ok(PacketMsg::Input(99, 1, Right(true))));
-        assert_eq!(parse_packet(&[58, 41, 58, 80, 58, 68, 67, 58, 2, 1, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Ok(PacketMsg::Input(258, 1, Right(false))));
-        assert_eq!(parse_packet(&[58, 41, 58, 80, 58, 68, 97, 33, 7, 0, 1, 0, 0, 0, 0, 0, 163, 49, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Ok(PacketMsg::Input(65543, 78243, Jump)));
-        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[96, 22], &[197], &[0; 7], &one, &[0; 8]].concat()), Ok(PacketMsg::Ping(197, 1)));
+        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[17, 23], &one, &one, &[0; 8]].concat()), Ok(PacketMsg::Input(1, 1, Left(true), 0, 0)));
+        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[25, 99], &99u64.to_le_bytes(), &one, &[0; 8]].concat()), Ok(PacketMsg::Input(99, 1, Left(false), 0, 0)));
+        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[37, 31], &99u64.to_le_bytes(), &one, &[0; 8]].concat()), Ok(PacketMsg::Input(99, 1, Right(true), 0, 0)));
+        assert_eq!(parse_packet(&[58, 41, 58, 80, 58, 68, 67, 58, 2, 1, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 5, 1, 0, 0, 0, 0, 0, 0]), Ok(PacketMsg::Input(258, 1, Right(false), 261, 0)));
+        assert_eq!(parse_packet(&[58, 41, 58, 80, 58, 68, 97, 33, 7, 0, 1, 0, 0, 0, 0, 0, 163, 49, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Ok(PacketMsg::Input(65543, 78243, Jump, 0, 0)));
+        assert_eq!(parse_packet(&[b":):P:D".as_slice(), &[96, 22], &[197], &[0; 7], &one, &[0; 8]].concat()), Ok(PacketMsg::Ping(197, 1, 0)));
+    }
+
+    #[test]
+    fn test_client_codec() {
+        for msg in [PacketMsg::PlayerIdRequest, PacketMsg::GameRequest(7), PacketMsg::Input(1, u64::MAX, Left(true), 0, 0), PacketMsg::Input(3, 4, Right(false), u32::MAX, 9),
+                    PacketMsg::Input(5, 6, Jump, 17, u32::MAX), PacketMsg::Ping(8, 9, 0), PacketMsg::Ping(8, 9, 123_456_789), PacketMsg::Pong(8, 1_000_000, u64::MAX),
+                    PacketMsg::MigrationKeyRequest, PacketMsg::Migrate(9, u64::MAX, 12_345), PacketMsg::Resume(10, u64::MAX),
+                    PacketMsg::Session(u64::MAX, 0, 3, None), PacketMsg::Session(1, u32::MAX, 0, Some(SessionRequest::Connect(u32::MAX))),
+                    PacketMsg::Session(2, 5, u32::MAX, Some(SessionRequest::Ping)), PacketMsg::Session(3, 6, 7, Some(SessionRequest::Disconnect))] {
+            assert_eq!(parse_packet(&encode_packet(&msg)), Ok(msg));
+        }
+        assert_eq!(encode_packet(&PacketMsg::Input(258, 1, Right(false), 0, 0))[..10], [58, 41, 58, 80, 58, 68, 67, 58, 2, 1]);
+
+        let state = GameStateSerialized {
+            ball_pos: (4.0, 2.5),
+            ball_radius: 0.3,
+            player_radius: 0.5,
+            player1_pos: (6.0, 0.6),
+            player2_pos: (2.0, -0.25),
+            score1: 3,
+            score2: 10,
+            game_over: true,
+            ball_velocity: (-1.25, 0.5),
+            ball_gravity: true,
+            own: OwnPlayer { is_player1: true, velocity: (3.0, -0.125), held: [false, true], ack_seq: 70000, ack_age: 3 },
+            frame: 123_456,
+            checksum: Some(0xdeadbeef),
+        };
+        assert_eq!(parse_server_packet(&parse_to_packet(&state)), Ok(ServerPacket::State(state)));
+        assert_eq!(parse_server_packet(&parse_ids_to_packet(11, 12)), Ok(ServerPacket::Ids(11, 12)));
+        assert_eq!(parse_server_packet(&shutdown_notice_packet(0)), Ok(ServerPacket::ShutdownNotice(0)));
+        assert_eq!(parse_server_packet(&shutdown_notice_packet(u64::MAX)), Ok(ServerPacket::ShutdownNotice(u64::MAX)));
+        assert_eq!(parse_server_packet(&pong_packet(5, 6)), Ok(ServerPacket::Pong(5, 6)));
+        assert_eq!(parse_server_packet(&server_ping_packet(7)), Ok(ServerPacket::Ping(7)));
+        for reply in [None, Some(SessionReply::Welcome(u64::MAX, 7)), Some(SessionReply::ShutdownNotice(u64::MAX)), Some(SessionReply::Disconnect), Some(SessionReply::Retry(u32::MAX))] {
+            assert_eq!(parse_server_packet(&session_packet(4, u32::MAX, reply)), Ok(ServerPacket::Session(4, u32::MAX, reply)));
+        }
+        assert_eq!(parse_server_packet(&[0; 32]), Err(ParseError::BadMagic));
+        assert_eq!(parse_server_packet(&[[12, 64, 13, 0].as_slice(), &[0; 28]].concat()), Err(ParseError::UnknownOpcode([13, 0])));
+        assert_eq!(parse_server_packet(&[0; 40]), Err(ParseError::WrongLength(40)));
     }
 }
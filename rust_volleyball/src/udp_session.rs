@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{Receiver, Sender};
+use crate::config::ServerConfig;
+use crate::metrics::METRICS;
+use crate::migration;
+use crate::reliable::ReliableChannel;
+use crate::server_logic::{LogicMessage, LogicSender};
+use crate::tcp_server::TcpMessage;
+use crate::transport::DatagramSocket;
+use crate::udp_server::{session_packet, SessionReply, SessionRequest};
+
+// the TCP session on the UDP socket, for hosts where only the UDP port can be opened: what tcp_server::handle_connection
+// does with a connection is done here with a session the client opens with a token of its own choice; the first
+// connect only gets a Retry with a cookie, nothing is kept or resent for an address until the connect comes back with it
+
+// what the UDP receiver hands over: source address, token, seq, ack, the control message
+pub type SessionPacket = (SocketAddr, u64, u32, u32, Option<SessionRequest>);
+pub type SessionSender = Sender<SessionPacket>;
+
+// how often resends and timeouts are looked at
+const SESSION_TICK: Duration = Duration::from_millis(50);
+// session packets waiting for the session thread, the UDP receiver drops the rest
+const SESSION_QUEUE: usize = 1024;
+// packets waiting for one session task, and the replies of all sessions waiting for the socket
+const SESSION_PACKETS: usize = 16;
+const OUT_QUEUE: usize = 1024;
+// a cookie is taken during its period and the next one
+const COOKIE_PERIOD_SECS: u64 = 10;
+
+// cookies are derived from it, a restarted server sends a new Retry
+static COOKIE_SECRET: OnceLock<[u8; 32]> = OnceLock::new();
+
+pub fn channel() -> (SessionSender, Receiver<SessionPacket>) {
+    tokio::sync::mpsc::channel(SESSION_QUEUE)
+}
+
+pub fn start(socket: impl DatagramSocket, receiver: Receiver<SessionPacket>, sender: LogicSender, config: ServerConfig) {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(async { run(socket, receiver, sender, config).await });
+    log::info!("UDP sessions stopped");
+}
+
+async fn run(socket: impl DatagramSocket, mut receiver: Receiver<SessionPacket>, sender: LogicSender, config: ServerConfig) {
+    let timeout = config.session_timeout();
+    let started = Instant::now();
+    let mut sessions: HashMap<u64, Sender<SessionPacket>> = HashMap::new();
+    // the session tasks do not send themselves, their packets are sent here
+    let (out_sender, mut out) = tokio::sync::mpsc::channel::<(SocketAddr, [u8; 32])>(OUT_QUEUE);
+
+    loop {
+        tokio::select! {
+            Some((addr, packet)) = out.recv() => match socket.send_to(&packet, addr) {
+                Ok(_) => _ = METRICS.udp_packets_out.fetch_add(1, Ordering::Relaxed),
+                Err(e) => log::warn!("Cannot send session packet, {e}"),
+            },
+            packet = receiver.recv() => {
+                let Some(packet) = packet else { break };
+                let (addr, token, seq, _, request) = packet;
+                if let Some(session) = sessions.get(&token) {
+                    match session.try_send(packet) {
+                        Ok(()) => continue,
+                        Err(TrySendError::Full(_)) => {
+                            METRICS.dropped("session_queue_full");
+                            continue;
+                        }
+                        // a session that ended is found by its closed channel
+                        Err(TrySendError::Closed(_)) => _ = sessions.remove(&token),
+                    }
+                }
+                // unknown, e.g. after a server restart: no answer to an address nobody verified, the client
+                // notices that its requests are not acknowledged and connects again
+                let (1, Some(SessionRequest::Connect(cookie))) = (seq, request) else {
+                    log::debug!("Packet of unknown UDP session {token} from {addr}");
+                    continue;
+                };
+                let period = started.elapsed().as_secs() / COOKIE_PERIOD_SECS;
+                if cookie == 0 || (cookie != session_cookie(addr, token, period) && cookie != session_cookie(addr, token, period.wrapping_sub(1))) {
+                    // as long as the connect, and nothing is kept for it
+                    send(&out_sender, addr, session_packet(0, 0, Some(SessionReply::Retry(session_cookie(addr, token, period)))));
+                    continue;
+                }
+                sessions.retain(|_, session| !session.is_closed());
+                if sessions.len() >= config.max_sessions {
+                    METRICS.dropped("sessions_full");
+                    log::warn!("UDP session {token} from {addr} refused, {} sessions open", sessions.len());
+                    continue;
+                }
+                let (session_sender, packets) = tokio::sync::mpsc::channel(SESSION_PACKETS);
+                let _ = session_sender.try_send(packet);
+                sessions.insert(token, session_sender);
+                tokio::spawn(handle_session(addr, token, packets, out_sender.clone(), sender.clone(), timeout));
+            }
+        }
+    }
+}
+
+// what only a client that gets the packets sent to its address can repeat, for the token it chose
+fn session_cookie(addr: SocketAddr, token: u64, period: u64) -> u32 {
+    let secret = COOKIE_SECRET.get_or_init(|| rand::rng().random());
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+    match addr.ip() {
+        IpAddr::V4(ip) => mac.update(&ip.octets()),
+        IpAddr::V6(ip) => mac.update(&ip.octets()),
+    }
+    mac.update(&addr.port().to_le_bytes());
+    mac.update(&token.to_le_bytes());
+    mac.update(&period.to_le_bytes());
+    // 0 stands for no cookie
+    u32::from_le_bytes(mac.finalize().into_bytes()[..4].try_into().unwrap()).max(1)
+}
+
+// the replies are resent, one that does not fit is lost like a datagram
+fn send(out: &Sender<(SocketAddr, [u8; 32])>, addr: SocketAddr, packet: [u8; 32]) {
+    if out.try_send((addr, packet)).is_err() {
+        METRICS.dropped("session_out_full");
+    }
+}
+
+async fn handle_session(mut addr: SocketAddr, token: u64, mut packets: Receiver<SessionPacket>, out: Sender<(SocketAddr, [u8; 32])>,
+                        logic_sender: LogicSender, timeout: Duration) {
+    let mut timer = tokio::time::interval(SESSION_TICK);
+    let player_id: u64 = rand::rng().random();
+    let mut opponent_id: Option<u64> = None;
+    let mut last_heard = Instant::now();
+    let mut channel = ReliableChannel::default();
+    // the session ends once the disconnect is acknowledged, or times out
+    let mut closing = false;
+    let mut client_left = false;
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    if let Err(e) = logic_sender.send(LogicMessage::SetChannel(player_id, sender)) {
+        log::error!("Cannot open UDP session {token}, {e}");
+        return;
+    }
+    log::info!("UDP session {token} opened: {addr:?}, player_id: {player_id}");
+    let disconnect = |opponent_id| if let Err(e) = logic_sender.send(LogicMessage::Disconnect(player_id, opponent_id)) {
+        log::error!("Cannot send LogicMessage, {e}");
+    };
+    loop {
+        tokio::select! {
+            _ = timer.tick() => {
+                if last_heard.elapsed() > timeout {
+                    log::debug!("UDP session {token} timed out, {player_id}");
+                    if !closing {
+                        disconnect(opponent_id);
+                    }
+                    break;
+                }
+                if closing && channel.is_idle() {
+                    break;
+                }
+            }
+            Some(ch_recv) = receiver.recv() => match ch_recv {
+                TcpMessage::DisconnectPlayer => {
+                    log::debug!("Disconnecting player {player_id} after Server message");
+                    channel.send(SessionReply::Disconnect);
+                    closing = true;
+                }
+                TcpMessage::SetOpponent(opponent) => opponent_id = Some(opponent),
+                // a UDP ping of the player, the session is alive as well
+                TcpMessage::KeepAlive => last_heard = Instant::now(),
+                TcpMessage::ServerShutdown(resume_token) => channel.send(SessionReply::ShutdownNotice(resume_token)),
+                // only a verified migration moves the session, the token alone does not
+                TcpMessage::Moved(to) => {
+                    log::debug!("UDP session {token} moved from {addr} to {to}");
+                    addr = to;
+                }
+            },
+            Some((from, _, seq, ack, request)) = packets.recv() => {
+                // from a new address of the client before its migration arrived, or from someone who knows the token
+                if from != addr {
+                    METRICS.dropped("session_wrong_address");
+                    log::debug!("UDP session {token} packet from {from}, the session is at {addr}");
+                    continue;
+                }
+                last_heard = Instant::now();
+                match channel.receive(seq, ack, request) {
+                    Some(SessionRequest::Connect(_)) => channel.send(SessionReply::Welcome(player_id, migration::key(player_id))),
+                    Some(SessionRequest::Ping) => logic_sender.send_player(LogicMessage::Alive(player_id)),
+                    Some(SessionRequest::Disconnect) => {
+                        log::debug!("UDP session {token} closed by the client, {player_id}");
+                        if !closing {
+                            disconnect(opponent_id);
+                        }
+                        client_left = true;
+                    }
+                    None => {}
+                }
+            }
+        }
+        for (seq, reply) in channel.outgoing(Instant::now()) {
+            send(&out, addr, session_packet(seq, channel.ack(), reply));
+        }
+        // the acknowledgement of the disconnect went out above, nothing else will be heard
+        if client_left {
+            break;
+        }
+    }
+    log::info!("UDP session {token} finished");
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use crate::config::ServerConfig;
+    use crate::server_logic;
+    use crate::transport::{MemoryNetwork, MemorySocket};
+    use crate::udp_server::{parse_server_packet, ServerPacket, SessionReply, SessionRequest};
+    use crate::udp_session;
+
+    // what arrives within 300 ms
+    fn replies(socket: &MemorySocket) -> Vec<SessionReply> {
+        let give_up = Instant::now() + Duration::from_millis(300);
+        let mut buf = [0; 64];
+        let mut replies = vec![];
+        while let Some((len, _)) = socket.recv_timeout(&mut buf, give_up.saturating_duration_since(Instant::now())).unwrap() {
+            if let Ok(ServerPacket::Session(_, _, Some(reply))) = parse_server_packet(&buf[..len]) {
+                replies.push(reply);
+            }
+        }
+        replies
+    }
+
+    #[test]
+    fn test_connect_needs_cookie() {
+        let network = MemoryNetwork::new(1);
+        let server = network.bind(network.next_addr()).unwrap();
+        let (client, other) = (network.bind(network.next_addr()).unwrap(), network.bind(network.next_addr()).unwrap());
+        let (logic_sender, _logic_receiver) = server_logic::channel(100);
+        let (sender, receiver) = udp_session::channel();
+        let config = ServerConfig { max_sessions: 1, ..Default::default() };
+        let thread = std::thread::spawn(move || udp_session::start(server, receiver, logic_sender, config));
+
+        // a connect from an address nobody verified gets one retry, nothing is resent to it
+        sender.try_send((client.local_addr(), 1, 1, 0, Some(SessionRequest::Connect(0)))).unwrap();
+        let [SessionReply::Retry(cookie)] = replies(&client)[..] else { panic!("no single retry") };
+        // the cookie is for that address and token only
+        sender.try_send((other.local_addr(), 1, 1, 0, Some(SessionRequest::Connect(cookie)))).unwrap();
+        assert!(matches!(replies(&other)[..], [SessionReply::Retry(_)]));
+        sender.try_send((client.local_addr(), 2, 1, 0, Some(SessionRequest::Connect(cookie)))).unwrap();
+        assert!(matches!(replies(&client)[..], [SessionReply::Retry(_)]));
+
+        sender.try_send((client.local_addr(), 1, 1, 0, Some(SessionRequest::Connect(cookie)))).unwrap();
+        assert!(matches!(replies(&client).first(), Some(SessionReply::Welcome(..))));
+        // the token alone does not move the session, the welcome is not resent elsewhere
+        sender.try_send((other.local_addr(), 1, 1, 0, Some(SessionRequest::Connect(cookie)))).unwrap();
+        assert!(replies(&other).is_empty());
+        assert!(matches!(replies(&client).first(), Some(SessionReply::Welcome(..))));
+        // no answer to an unknown session, no second session past the limit
+        sender.try_send((other.local_addr(), 3, 2, 0, Some(SessionRequest::Ping))).unwrap();
+        assert!(replies(&other).is_empty());
+        sender.try_send((other.local_addr(), 4, 1, 0, Some(SessionRequest::Connect(0)))).unwrap();
+        let [SessionReply::Retry(cookie)] = replies(&other)[..] else { panic!("no single retry") };
+        sender.try_send((other.local_addr(), 4, 1, 0, Some(SessionRequest::Connect(cookie)))).unwrap();
+        assert!(replies(&other).is_empty());
+
+        drop(sender);
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message;
+use crate::config::ServerConfig;
+use crate::flood::PacketFilter;
+use crate::metrics::METRICS;
+use crate::scheduler::Clock;
+use crate::server_logic::LogicSender;
+use crate::session::{Control, PlayerSession, SessionRoutes};
+use crate::transport::StreamListener;
+
+// browsers cannot send datagrams, a WebSocket session carries both the TCP control messages and the UDP game packets,
+// each packet in its own binary frame and byte for byte as on the native transports
+
+pub fn start(listener: impl StreamListener, sender: LogicSender, routes: SessionRoutes, filter: PacketFilter, clock: Arc<dyn Clock>, config: ServerConfig) {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(async { run(listener, sender, routes, filter, clock, config).await });
+    log::error!("WebSocket server stopped");
+}
+
+async fn run(mut listener: impl StreamListener, sender: LogicSender, routes: SessionRoutes, filter: PacketFilter, clock: Arc<dyn Clock>, config: ServerConfig) {
+    let idle_timeout = config.idle_timeout();
+    let handshake_timeout = config.handshake_timeout();
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let logic_sender = sender.clone();
+                let routes = routes.clone();
+                let filter = filter.clone();
+                let clock = clock.clone();
+                tokio::spawn(async move {
+                    // a client that never finishes the upgrade does not keep the task and the socket
+                    let ws = match tokio::time::timeout(handshake_timeout, tokio_tungstenite::accept_async(stream)).await {
+                        Ok(Ok(ws)) => ws,
+                        Ok(Err(e)) => {
+                            log::warn!("WebSocket handshake with {addr} failed, {e}");
+                            return;
+                        }
+                        Err(_) => {
+                            log::debug!("No WebSocket handshake from {addr} within {handshake_timeout:?}, closing");
+                            return;
+                        }
+                    };
+                    let c = METRICS.ws_sessions.fetch_add(1, Ordering::Relaxed) + 1;
+                    log::debug!("WebSocket connection, counter: {c}");
+                    handle_session(ws, addr, logic_sender, routes, filter, clock, idle_timeout).await;
+                    let c = METRICS.ws_sessions.fetch_sub(1, Ordering::Relaxed) - 1;
+                    log::debug!("WebSocket disconnection, counter: {c}");
+                });
+            }
+            Err(e) => {
+                log::error!("Could not accept WebSocket connection: {}", e);
+            }
+        }
+    }
+}
+
+async fn handle_session<S>(mut ws: tokio_tungstenite::WebSocketStream<S>, peer: SocketAddr, logic_sender: LogicSender, routes: SessionRoutes, filter: PacketFilter, clock: Arc<dyn Clock>, idle_timeout: Duration)
+where S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin {
+    let mut ping_timer = tokio::time::interval(Duration::from_secs(10));
+    let Some((mut session, mut receiver, mut packets)) = PlayerSession::open("ws", peer, &routes, &filter, logic_sender, clock) else {
+        return;
+    };
+    let player_id = session.player_id;
+    log::info!("WebSocket connection accepted: {peer:?}, player_id: {player_id}, session address: {}", session.addr);
+    loop {
+        // packets to send are collected first, the stream is borrowed by the select
+        let outgoing = tokio::select! {
+            _ = ping_timer.tick() => {
+                if session.idle(idle_timeout) {
+                    log::debug!("No ping, disconnect, {player_id}");
+                    session.disconnect();
+                    break;
+                }
+                None
+            }
+            Some(ch_recv) = receiver.recv() => match session.control(ch_recv) {
+                Control::Continue => None,
+                Control::Send(packet) => Some(packet),
+                Control::Close => break,
+            },
+            Some(packet) = packets.recv() => Some(packet),
+            frame = ws.next() => match frame {
+                Some(Ok(Message::Binary(data))) => session.receive(&data),
+                // pings and pongs of the WebSocket protocol itself are answered by the library
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => None,
+                Some(Ok(Message::Text(_))) => {
+                    METRICS.parse_error("ws", "text");
+                    log::debug!("Text frame from {player_id}, only binary frames are expected");
+                    None
+                }
+                Some(Ok(Message::Close(_))) | None => {
+                    log::debug!("Connection closed, {player_id}");
+                    session.disconnect();
+                    break;
+                }
+                Some(Err(e)) => {
+                    log::warn!("Error reading from WebSocket, {player_id}, error: {e}");
+                    session.disconnect();
+                    break;
+                }
+            },
+        };
+        if let Some(packet) = outgoing && let Err(e) = ws.send(Message::binary(packet)).await {
+            log::warn!("Cannot send WebSocket message, {player_id}, error: {e}");
+            session.disconnect();
+            break;
+        }
+    }
+    drop(session);
+    let _ = ws.close(None).await;
+    log::info!("WebSocket task finished");
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio_tungstenite::WebSocketStream;
+    use tokio_tungstenite::tungstenite::Message;
+    use crate::config::ServerConfig;
+    use crate::scheduler::SystemClock;
+    use crate::server::Server;
+    use crate::transport::{DatagramSocket, MemoryNetwork};
+    use crate::udp_server::{encode_packet, parse_server_packet, PacketMsg, ServerPacket};
+
+    async fn next_binary(ws: &mut WebSocketStream<DuplexStream>) -> Vec<u8> {
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap() {
+                Some(Ok(Message::Binary(data))) => return data.to_vec(),
+                Some(Ok(_)) => {}
+                other => panic!("WebSocket closed: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_websocket_player_meets_udp_player() {
+        let network = MemoryNetwork::new(3);
+        let (tcp_addr, udp_addr, ws_addr) = (network.next_addr(), network.next_addr(), network.next_addr());
+        let config = ServerConfig {
+            workers: 1,
+            snapshot_path: std::env::temp_dir().join(format!("volleyball_ws_{}.bin", std::process::id())),
+            ..Default::default()
+        };
+        let server = Server::start(config, network.bind(udp_addr).unwrap(), network.listen(tcp_addr).unwrap(), Arc::new(SystemClock::new())).unwrap();
+        server.serve_websocket(network.listen(ws_addr).unwrap());
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+
+        runtime.block_on(async {
+            let (stream, _) = network.connect(ws_addr).unwrap();
+            let (mut ws, _) = tokio_tungstenite::client_async("ws://volleyball/", stream).await.unwrap();
+            // the first player is in the browser
+            ws.send(Message::binary(encode_packet(&PacketMsg::PlayerIdRequest).to_vec())).await.unwrap();
+            let ws_player = u64::from_le_bytes(next_binary(&mut ws).await.try_into().unwrap());
+            ws.send(Message::binary(encode_packet(&PacketMsg::GameRequest(ws_player)).to_vec())).await.unwrap();
+            let Ok(ServerPacket::Ids(id, board_id)) = parse_server_packet(&next_binary(&mut ws).await) else { panic!("no ids") };
+            assert_eq!(id, ws_player);
+
+            // the second one is native, TCP session and UDP socket
+            let (mut stream, _) = network.connect(tcp_addr).unwrap();
+            stream.write_all(&encode_packet(&PacketMsg::PlayerIdRequest)).await.unwrap();
+            let udp_player = stream.read_u64_le().await.unwrap();
+            let socket = network.bind(network.next_addr()).unwrap();
+            socket.send_to(&encode_packet(&PacketMsg::GameRequest(udp_player)), udp_addr).unwrap();
+            let mut buf = [0; 128];
+            let (len, _) = socket.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap().unwrap();
+            assert_eq!(parse_server_packet(&buf[..len]), Ok(ServerPacket::Ids(udp_player, board_id)));
+
+            // both get the board, the browser over its session
+            let ws_state = loop {
+                if let Ok(ServerPacket::State(state)) = parse_server_packet(&next_binary(&mut ws).await) {
+                    break state;
+                }
+            };
+            assert!(ws_state.own.is_player1);
+            let (len, _) = socket.recv_timeout(&mut buf, Duration::from_secs(5)).unwrap().unwrap();
+            assert!(matches!(parse_server_packet(&buf[..len]), Ok(ServerPacket::State(s)) if !s.own.is_player1));
+
+            // timestamped pings are answered by the session, packets under another player's id are dropped
+            ws.send(Message::binary(encode_packet(&PacketMsg::GameRequest(udp_player)).to_vec())).await.unwrap();
+            ws.send(Message::binary(encode_packet(&PacketMsg::Ping(ws_player, board_id, 1234)).to_vec())).await.unwrap();
+            loop {
+                match parse_server_packet(&next_binary(&mut ws).await) {
+                    Ok(ServerPacket::Pong(1234, _)) => break,
+                    Ok(ServerPacket::State(_) | ServerPacket::Ping(_)) => {}
+                    other => panic!("unexpected packet {other:?}"),
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_unfinished_handshake_is_closed() {
+        let network = MemoryNetwork::new(4);
+        let (tcp_addr, udp_addr, ws_addr) = (network.next_addr(), network.next_addr(), network.next_addr());
+        let config = ServerConfig {
+            workers: 1,
+            handshake_timeout_secs: 1,
+            snapshot_path: std::env::temp_dir().join(format!("volleyball_ws_handshake_{}.bin", std::process::id())),
+            ..Default::default()
+        };
+        let server = Server::start(config, network.bind(udp_addr).unwrap(), network.listen(tcp_addr).unwrap(), Arc::new(SystemClock::new())).unwrap();
+        server.serve_websocket(network.listen(ws_addr).unwrap());
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+
+        runtime.block_on(async {
+            // connected, but the upgrade request never comes
+            let (mut stream, _) = network.connect(ws_addr).unwrap();
+            let mut buf = [0; 16];
+            let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf)).await;
+            assert!(matches!(read, Ok(Ok(0))), "the connection is still open: {read:?}");
+        });
+    }
+}
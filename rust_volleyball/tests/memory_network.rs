@@ -0,0 +1,163 @@
+// whole matches over the in-memory network: TCP session, lobby, play until game over, disconnect
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rust_volleyball::GameConfig;
+use rust_volleyball::admin::{AdminQuery, AdminReply};
+use rust_volleyball::config::ServerConfig;
+use rust_volleyball::scheduler::SystemClock;
+use rust_volleyball::server::Server;
+use rust_volleyball::server_logic::{LogicMessage, LogicSender};
+use rust_volleyball::transport::{DatagramSocket, MemoryNetwork};
+use rust_volleyball::udp_server::{encode_packet, parse_server_packet, Key, PacketMsg, ServerPacket};
+
+const INPUTS: [Key; 5] = [Key::Left(true), Key::Left(false), Key::Right(true), Key::Right(false), Key::Jump];
+
+struct MatchResult {
+    player_id: u64,
+    board_id: u64,
+    score: (u32, u32),
+}
+
+fn start_server(network: &MemoryNetwork, name: &str) -> (Server, SocketAddr, SocketAddr) {
+    let tcp_addr = network.next_addr();
+    let udp_addr = network.next_addr();
+    let config = ServerConfig {
+        workers: 2,
+        snapshot_path: std::env::temp_dir().join(format!("volleyball_{name}_{}.bin", std::process::id())),
+        // short points, the ball drops soon after the serve and one point ends the game
+        game: GameConfig { point_limit: 1, gravity_after_frames: 10, point_reset_frames: 10, ..Default::default() },
+        ..Default::default()
+    };
+    let server = Server::start(config, network.bind(udp_addr).unwrap(), network.listen(tcp_addr).unwrap(), Arc::new(SystemClock::new())).unwrap();
+    (server, tcp_addr, udp_addr)
+}
+
+// a headless client, it presses random keys now and then and plays until the server reports game over
+fn play(network: MemoryNetwork, tcp_addr: SocketAddr, udp_addr: SocketAddr, seed: u64) -> MatchResult {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let (mut stream, _) = network.connect(tcp_addr).unwrap();
+    let player_id = runtime.block_on(async {
+        stream.write_all(&encode_packet(&PacketMsg::PlayerIdRequest)).await.unwrap();
+        stream.read_u64_le().await.unwrap()
+    });
+
+    let socket = network.bind(network.next_addr()).unwrap();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut buf = [0; 128];
+    let give_up = Instant::now() + Duration::from_secs(30);
+    // the request or the answer may get lost, ask until the ids arrive
+    let board_id = loop {
+        assert!(Instant::now() < give_up, "player {player_id} got no board");
+        socket.send_to(&encode_packet(&PacketMsg::GameRequest(player_id)), udp_addr).unwrap();
+        if let Some((len, _)) = socket.recv_timeout(&mut buf, Duration::from_millis(100)).unwrap()
+            && let Ok(ServerPacket::Ids(id, board_id)) = parse_server_packet(&buf[..len]) {
+            assert_eq!(id, player_id);
+            break board_id;
+        }
+    };
+
+    loop {
+        assert!(Instant::now() < give_up, "player {player_id} never saw the game end");
+        if rng.random_bool(0.05) {
+            socket.send_to(&encode_packet(&PacketMsg::Input(player_id, board_id, INPUTS[rng.random_range(0..INPUTS.len())], 0, 0)), udp_addr).unwrap();
+        }
+        if rng.random_bool(0.02) {
+            socket.send_to(&encode_packet(&PacketMsg::Ping(player_id, board_id, 0)), udp_addr).unwrap();
+        }
+        if let Some((len, _)) = socket.recv_timeout(&mut buf, Duration::from_millis(20)).unwrap()
+            && let Ok(ServerPacket::State(state)) = parse_server_packet(&buf[..len]) && state.game_over {
+            let score = (state.score1, state.score2);
+            // the opponent is disconnected together with this player, give their copy of the final state time to arrive
+            std::thread::sleep(Duration::from_millis(500));
+            // closing the TCP session tells the server the player is gone
+            drop(stream);
+            return MatchResult { player_id, board_id, score };
+        }
+    }
+}
+
+fn query(logic_sender: &LogicSender, admin_query: AdminQuery) -> AdminReply {
+    let (reply_sender, reply_receiver) = tokio::sync::oneshot::channel();
+    logic_sender.send(LogicMessage::Admin(admin_query, reply_sender)).unwrap();
+    reply_receiver.blocking_recv().unwrap()
+}
+
+fn run_matches(network: MemoryNetwork, name: &str, players: u64) {
+    let (server, tcp_addr, udp_addr) = start_server(&network, name);
+    let clients: Vec<_> = (0..players).map(|seed| {
+        let network = network.clone();
+        spawn(move || play(network, tcp_addr, udp_addr, seed))
+    }).collect();
+    let results: Vec<MatchResult> = clients.into_iter().map(|c| c.join().unwrap()).collect();
+
+    for result in &results {
+        assert!(matches!(result.score, (1, 0) | (0, 1)), "player {} ended with {:?}", result.player_id, result.score);
+        // exactly one opponent on the same board, who saw the same final score
+        let opponents: Vec<&MatchResult> = results.iter().filter(|r| r.board_id == result.board_id && r.player_id != result.player_id).collect();
+        assert_eq!(opponents.len(), 1);
+        assert_eq!(opponents[0].score, result.score);
+    }
+
+    // boards of players that left are dropped on the next tick
+    let give_up = Instant::now() + Duration::from_secs(5);
+    loop {
+        let AdminReply::Players(connected) = query(&server.logic_sender, AdminQuery::Players) else { panic!("wrong reply") };
+        let AdminReply::Boards(boards) = query(&server.logic_sender, AdminQuery::Boards) else { panic!("wrong reply") };
+        if connected.is_empty() && boards.is_empty() {
+            break;
+        }
+        assert!(Instant::now() < give_up, "{} players and {} boards left after the games", connected.len(), boards.len());
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_matches_on_perfect_network() {
+    run_matches(MemoryNetwork::new(1), "perfect", 4);
+}
+
+#[test]
+fn test_matches_with_loss_and_latency() {
+    let network = MemoryNetwork::new(2);
+    network.set_conditions(0.2, Duration::from_millis(30), Duration::from_millis(20));
+    run_matches(network, "lossy", 6);
+}
+
+#[test]
+fn test_oversized_packets_are_dropped() {
+    let network = MemoryNetwork::new(3);
+    let (server, tcp_addr, udp_addr) = start_server(&network, "oversized");
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let [(first, _stream1), (second, _stream2)] = [(); 2].map(|_| {
+        let (mut stream, _) = network.connect(tcp_addr).unwrap();
+        let player_id = runtime.block_on(async {
+            stream.write_all(&encode_packet(&PacketMsg::PlayerIdRequest)).await.unwrap();
+            stream.read_u64_le().await.unwrap()
+        });
+        (player_id, stream)
+    });
+    let socket = network.bind(network.next_addr()).unwrap();
+
+    // a valid game request with bytes after it, then a valid one of the other player
+    let oversized = [encode_packet(&PacketMsg::GameRequest(first)).as_slice(), &[0; 8]].concat();
+    socket.send_to(&oversized, udp_addr).unwrap();
+    socket.send_to(&encode_packet(&PacketMsg::GameRequest(second)), udp_addr).unwrap();
+
+    // had the first one been taken the two would share a board now
+    let give_up = Instant::now() + Duration::from_secs(5);
+    loop {
+        if let AdminReply::Lobby(Some((waiting, _))) = query(&server.logic_sender, AdminQuery::Lobby) {
+            assert_eq!(waiting, second);
+            break;
+        }
+        assert!(Instant::now() < give_up, "the valid request never reached the lobby");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    let AdminReply::Boards(boards) = query(&server.logic_sender, AdminQuery::Boards) else { panic!("wrong reply") };
+    assert!(boards.is_empty());
+}
@@ -0,0 +1,166 @@
+// round trips of every message through its encoder and parser, and the byte vectors of the Godot client
+use proptest::prelude::*;
+use rust_volleyball::{BodyState, BoardState};
+use rust_volleyball::server_logic::{GameStateSerialized, OwnPlayer, ResyncState};
+use rust_volleyball::udp_server::{board_packet, encode_packet, parse_ids_to_packet, parse_packet, parse_server_packet, parse_to_packet, pong_packet, server_ping_packet, session_packet, shutdown_notice_packet, Key, PacketMsg, ServerPacket, SessionReply, SessionRequest};
+
+const FIXTURES: &str = include_str!("fixtures/godot_packets.txt");
+
+fn key() -> impl Strategy<Value = Key> {
+    prop_oneof![any::<bool>().prop_map(Key::Left), any::<bool>().prop_map(Key::Right), Just(Key::Jump)]
+}
+
+fn session_request() -> impl Strategy<Value = Option<SessionRequest>> {
+    prop::option::of(prop_oneof![any::<u32>().prop_map(SessionRequest::Connect), Just(SessionRequest::Ping), Just(SessionRequest::Disconnect)])
+}
+
+fn session_reply() -> impl Strategy<Value = Option<SessionReply>> {
+    prop::option::of(prop_oneof![
+        (any::<u64>(), any::<u64>()).prop_map(|(player_id, key)| SessionReply::Welcome(player_id, key)),
+        any::<u64>().prop_map(SessionReply::ShutdownNotice),
+        Just(SessionReply::Disconnect),
+        any::<u32>().prop_map(SessionReply::Retry),
+    ])
+}
+
+// player ids and session tokens are never 0, but the TCP ping may have none
+fn client_packet() -> impl Strategy<Value = PacketMsg> {
+    let id = 1..=u64::MAX;
+    prop_oneof![
+        Just(PacketMsg::PlayerIdRequest),
+        Just(PacketMsg::MigrationKeyRequest),
+        id.clone().prop_map(PacketMsg::GameRequest),
+        (id.clone(), any::<u64>()).prop_map(|(p, token)| PacketMsg::Resume(p, token)),
+        (id.clone(), any::<u64>(), key(), any::<u32>(), any::<u32>()).prop_map(|(p, b, k, seq, frame)| PacketMsg::Input(p, b, k, seq, frame)),
+        (any::<u64>(), any::<u64>(), any::<u64>()).prop_map(|(p, b, sent)| PacketMsg::Ping(p, b, sent)),
+        (id.clone(), any::<u64>(), any::<u64>()).prop_map(|(p, server, client)| PacketMsg::Pong(p, server, client)),
+        (id.clone(), any::<u32>(), any::<u32>(), session_request()).prop_map(|(token, seq, ack, r)| PacketMsg::Session(token, seq, ack, r)),
+        (id.clone(), any::<u64>(), any::<u64>()).prop_map(|(p, counter, tag)| PacketMsg::Migrate(p, counter, tag)),
+        (id, any::<u64>()).prop_map(|(p, b)| PacketMsg::Resync(p, b)),
+    ]
+}
+
+// what the encoding keeps exactly: finite floats, velocities in whole mm/s, frames of 32 bits
+fn state() -> impl Strategy<Value = GameStateSerialized> {
+    let pos = || (-100.0f32..100.0, -100.0f32..100.0);
+    let velocity = || (any::<i16>(), any::<i16>()).prop_map(|(x, y)| (x as f32 / 1000.0, y as f32 / 1000.0));
+    let own = (any::<bool>(), velocity(), any::<[bool; 2]>(), any::<u32>(), any::<u16>())
+        .prop_map(|(is_player1, velocity, held, ack_seq, ack_age)| OwnPlayer { is_player1, velocity, held, ack_seq, ack_age });
+    (
+        (pos(), 0.0f32..1.0, 0.0f32..1.0, pos(), pos()),
+        (any::<u32>(), any::<u32>(), any::<bool>(), velocity(), any::<bool>()),
+        (own, any::<u32>(), prop::option::of(any::<u32>())),
+    ).prop_map(|((ball_pos, ball_radius, player_radius, player1_pos, player2_pos), (score1, score2, game_over, ball_velocity, ball_gravity), (own, frame, checksum))| {
+        GameStateSerialized {
+            ball_pos, ball_radius, player_radius, player1_pos, player2_pos, score1, score2, game_over, ball_velocity, ball_gravity,
+            own, frame: frame as u64, checksum,
+        }
+    })
+}
+
+proptest! {
+    #[test]
+    fn client_packets_round_trip(msg in client_packet()) {
+        prop_assert_eq!(parse_packet(&encode_packet(&msg)), Ok(msg));
+    }
+
+    #[test]
+    fn server_packets_round_trip(ids in any::<(u64, u64)>(), times in any::<(u64, u64)>(), seq in any::<(u32, u32)>(), reply in session_reply()) {
+        prop_assert_eq!(parse_server_packet(&parse_ids_to_packet(ids.0, ids.1)), Ok(ServerPacket::Ids(ids.0, ids.1)));
+        prop_assert_eq!(parse_server_packet(&pong_packet(times.0, times.1)), Ok(ServerPacket::Pong(times.0, times.1)));
+        prop_assert_eq!(parse_server_packet(&server_ping_packet(times.0)), Ok(ServerPacket::Ping(times.0)));
+        prop_assert_eq!(parse_server_packet(&session_packet(seq.0, seq.1, reply)), Ok(ServerPacket::Session(seq.0, seq.1, reply)));
+        prop_assert_eq!(parse_server_packet(&shutdown_notice_packet(times.0)), Ok(ServerPacket::ShutdownNotice(times.0)));
+    }
+
+    #[test]
+    fn board_round_trip(values in prop::collection::vec(-100.0f32..100.0, 30), flags in any::<[bool; 8]>(), points in any::<(u32, u32)>(), frame in any::<u32>(), ack in any::<(u32, u16)>(), countdowns in any::<(u32, u32)>()) {
+        let body = |v: &[f32]| BodyState { position: (v[0], v[1]), rotation: (v[2], v[3]), velocity: (v[4], v[5]), spin: v[6], force: (v[7], v[8]), gravity: v[9] };
+        let board = BoardState {
+            bodies: [body(&values[..10]), body(&values[10..20]), body(&values[20..])],
+            points,
+            flags: [flags[0], flags[1], flags[2], flags[3]],
+            input: [[flags[4], flags[5]], [flags[6], flags[7]]],
+            reset_in: countdowns.0,
+            gravity_in: countdowns.1,
+        };
+        let resync = ResyncState { board, frame: frame as u64, ack_seq: ack.0, ack_age: ack.1 };
+        prop_assert_eq!(parse_server_packet(&board_packet(&resync)), Ok(ServerPacket::Board(resync)));
+    }
+
+    #[test]
+    fn state_round_trip(state in state()) {
+        let packet = parse_to_packet(&state);
+        prop_assert_eq!(parse_server_packet(&packet), Ok(ServerPacket::State(state)));
+    }
+
+    // whatever comes in, parsing does not panic and what parses encodes to the same message again
+    #[test]
+    fn parse_any_bytes(data in prop_oneof![
+        prop::collection::vec(any::<u8>(), 0..80),
+        prop::collection::vec(any::<u8>(), 26).prop_map(|rest| [b":):P:D".as_slice(), &rest].concat()),
+        prop::collection::vec(any::<u8>(), 68),
+        prop::collection::vec(any::<u8>(), 148).prop_map(|rest| [[12, 64, 13, 66].as_slice(), &rest].concat()),
+    ]) {
+        if let Ok(msg) = parse_packet(&data) {
+            prop_assert_eq!(parse_packet(&encode_packet(&msg)), Ok(msg));
+        }
+        if let Ok(ServerPacket::State(state)) = parse_server_packet(&data) {
+            // NaN is not equal to itself, the bytes have to be
+            let packet = parse_to_packet(&state);
+            let Ok(ServerPacket::State(again)) = parse_server_packet(&packet) else { panic!("state did not parse again") };
+            prop_assert_eq!(parse_to_packet(&again), packet);
+        }
+        if let Ok(ServerPacket::Board(resync)) = parse_server_packet(&data) {
+            let packet = board_packet(&resync);
+            let Ok(ServerPacket::Board(again)) = parse_server_packet(&packet) else { panic!("board did not parse again") };
+            prop_assert_eq!(board_packet(&again), packet);
+        }
+    }
+}
+
+fn fixture(name: &str) -> Vec<u8> {
+    let line = FIXTURES.lines().find(|line| line.split(' ').next() == Some(name)).unwrap_or_else(|| panic!("no fixture {name}"));
+    let hex = line.split(' ').nth(1).unwrap();
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+}
+
+#[test]
+fn godot_fixtures() {
+    let client = [
+        ("player_id_request", PacketMsg::PlayerIdRequest),
+        ("tcp_ping", PacketMsg::Ping(0, 0, 0)),
+        ("game_request", PacketMsg::GameRequest(7)),
+        ("jump", PacketMsg::Input(7, 3, Key::Jump, 0, 0)),
+        ("left_pressed", PacketMsg::Input(7, 3, Key::Left(true), 0, 0)),
+        ("left_released", PacketMsg::Input(7, 3, Key::Left(false), 0, 0)),
+        ("right_pressed", PacketMsg::Input(7, 3, Key::Right(true), 0, 0)),
+        ("right_released", PacketMsg::Input(7, 3, Key::Right(false), 0, 0)),
+        ("rtt_ping", PacketMsg::Ping(7, 3, 1234567)),
+        ("pong", PacketMsg::Pong(7, 5000000, 1234567)),
+    ];
+    for (name, msg) in client {
+        assert_eq!(encode_packet(&msg).as_slice(), fixture(name), "{name}");
+        assert_eq!(parse_packet(&fixture(name)), Ok(msg), "{name}");
+    }
+
+    assert_eq!(parse_ids_to_packet(7, 3).as_slice(), fixture("ids"));
+    assert_eq!(server_ping_packet(5000000).as_slice(), fixture("server_ping"));
+    assert_eq!(pong_packet(1234567, 5000000).as_slice(), fixture("pong_reply"));
+    let state = GameStateSerialized {
+        ball_pos: (4.0, 2.5),
+        ball_radius: 0.25,
+        player_radius: 0.5,
+        player1_pos: (6.0, 1.0),
+        player2_pos: (2.0, 1.0),
+        score1: 3,
+        score2: 5,
+        game_over: false,
+        ball_velocity: (1.25, 0.0),
+        ball_gravity: true,
+        own: OwnPlayer { is_player1: true, velocity: (0.5, -1.0), held: [true, false], ack_seq: 12, ack_age: 2 },
+        frame: 600,
+        checksum: Some(0xabcd),
+    };
+    assert_eq!(parse_to_packet(&state).as_slice(), fixture("state"));
+}